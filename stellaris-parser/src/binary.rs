@@ -0,0 +1,226 @@
+//! Binary (ironman) Clausewitz save decoding.
+//!
+//! Stellaris's "ironman" saves replace the plain-text gamestate/meta bodies
+//! with a binary token stream: control tokens for `=`, `{`, `}` interleaved
+//! with typed-value tokens (integers, floats, bools, length-prefixed quoted
+//! strings, unquoted strings) and field/enum name tokens. Unlike the text
+//! format, field names aren't spelled out in the stream — they're looked up
+//! by id in a resolver table that has to be supplied from outside (PDS
+//! doesn't ship one; tools maintain their own by diffing known saves).
+
+use crate::error::CompanionError;
+use jomini::binary::TokenResolver;
+use serde_json::Value;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+
+/// Maps binary token ids to their field/enum name, loaded from an external
+/// tokens file: one `0xID name` pair per line, blank lines and `#` comments
+/// ignored. For example:
+///
+/// ```text
+/// # stellaris token table
+/// 0x0036 name
+/// 0x0039 owner
+/// ```
+pub struct TokenLookup(HashMap<u16, String>);
+
+impl TokenLookup {
+    /// Parse a tokens file into a lookup table.
+    pub fn load(path: &str) -> Result<Self, CompanionError> {
+        let contents = fs::read_to_string(path).map_err(|_| CompanionError::FileNotFound {
+            path: path.to_string(),
+        })?;
+
+        let mut map = HashMap::new();
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut parts = line.splitn(2, char::is_whitespace);
+            let (id_str, name) = match (parts.next(), parts.next()) {
+                (Some(id), Some(name)) if !name.trim().is_empty() => (id, name.trim()),
+                _ => {
+                    return Err(CompanionError::InvalidArgument {
+                        detail: format!("Malformed token line in {}: {:?}", path, line),
+                    });
+                }
+            };
+
+            let id = u16::from_str_radix(id_str.trim_start_matches("0x"), 16).map_err(|_| {
+                CompanionError::InvalidArgument {
+                    detail: format!("Invalid token id in {}: {:?}", path, id_str),
+                }
+            })?;
+
+            map.insert(id, name.to_string());
+        }
+
+        Ok(Self(map))
+    }
+}
+
+impl TokenResolver for TokenLookup {
+    fn resolve(&self, token: u16) -> Option<&str> {
+        self.0.get(&token).map(|s| s.as_str())
+    }
+}
+
+/// Sniff whether `bytes` is a binary (ironman) gamestate/meta body rather
+/// than plain Clausewitz text. Text saves always open with an identifier,
+/// a comment, or whitespace; binary saves open directly with a token id
+/// whose leading byte essentially never lands on a printable ASCII letter.
+pub fn looks_like_binary(bytes: &[u8]) -> bool {
+    match bytes.first() {
+        Some(b) => !(b.is_ascii_alphabetic() || matches!(b, b'#' | b'\n' | b'\r' | b'\t' | b' ')),
+        None => false,
+    }
+}
+
+/// Decode a binary gamestate/meta body into the same `HashMap<String, Value>`
+/// shape the text decoder produces, so the rest of the session ops don't
+/// need to know which format a save used. Generic over the resolver so
+/// callers can plug in a stricter or more lenient lookup (see
+/// `LenientTokenLookup`) without this function caring which.
+///
+/// Numeric scalars match the text path without any extra conversion here:
+/// `jomini::binary::de::from_slice` deserializes binary floats/fixed-point
+/// into the same `serde_json::Value` numbers that `from_utf8_slice`/
+/// `from_windows1252_slice` produce for their text equivalents, since both
+/// paths target the same `Value` deserialization regardless of which wire
+/// format they read from.
+///
+/// Dates are the exception: the binary format has no distinct date token,
+/// so a date field comes back as a plain integer here, while the text path
+/// yields the literal `"Y.M.D"` string it was written as. `--typed-dates`
+/// (`promote_dates_in_value`) only recognizes that string shape, so it has
+/// no effect on dates decoded from a binary save.
+pub fn decode_binary<R: TokenResolver>(
+    bytes: &[u8],
+    resolver: &R,
+) -> Result<HashMap<String, Value>, CompanionError> {
+    jomini::binary::de::from_slice(bytes, resolver).map_err(|e| match unknown_token_id(&e) {
+        Some(token_id) => CompanionError::UnknownToken { token_id },
+        None => CompanionError::ParseError {
+            source: anyhow::Error::new(e),
+            decode_offset: None,
+        },
+    })
+}
+
+/// Wraps a `TokenLookup` and falls back to a synthesized `"0x<hex>"` key for
+/// any token id the table doesn't recognize, instead of failing the whole
+/// decode the way a bare `TokenLookup` does. Intended for one-shot CLI
+/// exports (`extract-save`/`iter-save`), where aborting an entire export
+/// over a single unrecognized field is worse than emitting a placeholder key
+/// a user can grep for; `serve` keeps the strict behavior since it's
+/// diagnosing one session's token table interactively.
+///
+/// Synthesized names are leaked (`Box::leak`) so `resolve` can hand back a
+/// plain `&str` without unsafe lifetime tricks; this is bounded by the
+/// number of distinct u16 token ids (at most 65536) and the process exits
+/// shortly after the export finishes, so it isn't worth the complexity of
+/// avoiding.
+pub struct LenientTokenLookup<'a> {
+    inner: &'a TokenLookup,
+    unresolved: RefCell<HashMap<u16, &'static str>>,
+}
+
+impl<'a> LenientTokenLookup<'a> {
+    pub fn new(inner: &'a TokenLookup) -> Self {
+        Self {
+            inner,
+            unresolved: RefCell::new(HashMap::new()),
+        }
+    }
+}
+
+impl<'a> TokenResolver for LenientTokenLookup<'a> {
+    fn resolve(&self, token: u16) -> Option<&str> {
+        if let Some(name) = self.inner.resolve(token) {
+            return Some(name);
+        }
+
+        let mut unresolved = self.unresolved.borrow_mut();
+        let name: &'static str = *unresolved
+            .entry(token)
+            .or_insert_with(|| Box::leak(format!("0x{:04x}", token).into_boxed_str()));
+        Some(name)
+    }
+}
+
+/// Pull the offending token id out of a jomini binary-deserialize error, if
+/// it's an unresolved-token error, so callers can report it by name instead
+/// of a generic parse failure.
+fn unknown_token_id(err: &jomini::Error) -> Option<u16> {
+    match err.kind() {
+        jomini::ErrorKind::UnknownToken { token_id } => Some(*token_id),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_looks_like_binary_for_text_save() {
+        assert!(!looks_like_binary(b"empire={\n    name=\"Test\"\n}"));
+        assert!(!looks_like_binary(b"# a leading comment\nfoo=bar"));
+    }
+
+    #[test]
+    fn test_looks_like_binary_for_token_stream() {
+        // A control token (e.g. 0x0003 for `{`) never starts with a printable
+        // ASCII letter in its low byte.
+        assert!(looks_like_binary(&[0x03, 0x00, 0x36, 0x00]));
+    }
+
+    #[test]
+    fn test_looks_like_binary_empty() {
+        assert!(!looks_like_binary(b""));
+    }
+
+    fn write_token_file(name: &str, contents: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("stellaris-tokens-test-{}.txt", name));
+        let mut file = std::fs::File::create(&path).expect("create temp token file");
+        file.write_all(contents.as_bytes()).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_token_lookup_parses_hex_ids() {
+        let path = write_token_file(
+            "valid",
+            "0x0036 name\n0x0039 owner\n# comment\n\n",
+        );
+        let lookup =
+            TokenLookup::load(path.to_str().unwrap()).expect("should parse token file");
+        assert_eq!(lookup.resolve(0x0036), Some("name"));
+        assert_eq!(lookup.resolve(0x0039), Some("owner"));
+        assert_eq!(lookup.resolve(0x9999), None);
+    }
+
+    #[test]
+    fn test_token_lookup_rejects_malformed_line() {
+        let path = write_token_file("malformed", "not-a-valid-line\n");
+        let result = TokenLookup::load(path.to_str().unwrap());
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_lenient_token_lookup_falls_back_to_hex_key() {
+        let path = write_token_file("lenient", "0x0036 name\n");
+        let inner = TokenLookup::load(path.to_str().unwrap()).unwrap();
+        let lenient = LenientTokenLookup::new(&inner);
+
+        assert_eq!(lenient.resolve(0x0036), Some("name"));
+        assert_eq!(lenient.resolve(0x1234), Some("0x1234"));
+        // Repeated lookups of the same unresolved id return the same key.
+        assert_eq!(lenient.resolve(0x1234), Some("0x1234"));
+    }
+}
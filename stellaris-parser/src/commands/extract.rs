@@ -1,92 +1,191 @@
-use crate::error::{exit_with_error, ErrorKind, SCHEMA_VERSION, TOOL_VERSION};
-use anyhow::{Context, Result};
-use jomini::text::de::from_windows1252_slice;
+use crate::binary::TokenLookup;
+use crate::encoding::Encoding;
+use crate::error::{CompanionError, SCHEMA_VERSION, TOOL_VERSION};
+use crate::gamestate_bytes::GamestateBytes;
+use crate::serialize::Format;
+use anyhow::Result;
 use serde_json::{json, Map, Value};
-use std::collections::HashMap;
 use std::fs::File;
-use std::io::{BufWriter, Read, Write};
+use std::io::{self, BufWriter, Write};
 use zip::ZipArchive;
 
-/// Run extraction on a .sav file (ZIP archive containing gamestate and meta)
-pub fn run_save(path: &str, sections: &str, schema_version: &str, output: &str) -> Result<()> {
+/// Run extraction on a .sav file (ZIP archive containing gamestate and meta).
+/// `tokens_path` is only consulted if the save turns out to be binary
+/// (ironman); plain-text saves ignore it entirely. `encoding` selects the
+/// input text encoding for plain-text saves (see `crate::encoding`); the
+/// `preserve_duplicates` decode path does not apply it.
+/// `use_mmap` extracts the gamestate/meta entries to a temp file and maps
+/// them instead of reading them fully into memory (see
+/// `gamestate_bytes::GamestateBytes`) — worth it for the hundreds-of-MB
+/// gamestates a late-game save can produce. `strip_color_codes` strips
+/// Stellaris's `\x15`-prefixed color markup from every string in the
+/// emitted JSON, for callers that want clean display text (see
+/// `crate::output::strip_color_codes`); the raw form is kept by default.
+/// `preserve_duplicates` decodes through jomini's `TextTape` mid-level API
+/// instead of straight into a `HashMap`, so a key that repeats within one
+/// object (species traits, fleet lists) comes out as a JSON array instead
+/// of only its last occurrence (see
+/// `crate::output::decode_gamestate_preserving_duplicates`). `typed_dates`
+/// promotes bare `Y.M.D[.H]` date strings to the tagged object
+/// `crate::output::ClausewitzDate::to_json` produces, so consumers can sort
+/// and compare in-game dates without re-parsing them. `format` selects the
+/// output serialization (see `crate::serialize`); defaults to pretty JSON.
+pub fn run_save(
+    path: &str,
+    sections: &str,
+    schema_version: &str,
+    output: &str,
+    tokens_path: Option<&str>,
+    encoding: Encoding,
+    use_mmap: bool,
+    strip_color_codes: bool,
+    preserve_duplicates: bool,
+    typed_dates: bool,
+    format: Format,
+) -> Result<()> {
     // Validate schema version
-    validate_schema_version(schema_version);
+    validate_schema_version(schema_version)?;
 
+    let tokens = tokens_path.map(TokenLookup::load).transpose()?;
     let section_list: Vec<&str> = sections.split(',').map(|s| s.trim()).collect();
 
     // Read the .sav file (ZIP archive)
-    let file = File::open(path).with_context(|| format!("Failed to open file: {}", path))?;
-    let mut archive = ZipArchive::new(file).with_context(|| "Failed to read ZIP archive")?;
+    let file = File::open(path).map_err(|_| CompanionError::FileNotFound {
+        path: path.to_string(),
+    })?;
+    let mut archive = ZipArchive::new(file).map_err(|e| CompanionError::CorruptArchive {
+        source: anyhow::Error::new(e),
+    })?;
 
     // Extract gamestate content
     let gamestate_content = {
-        let mut gamestate_file = archive
+        let gamestate_file = archive
             .by_name("gamestate")
-            .with_context(|| "No gamestate file in archive")?;
-        let mut content = Vec::new();
-        gamestate_file.read_to_end(&mut content)?;
-        content
+            .map_err(|_| CompanionError::MissingGamestate)?;
+        read_entry(gamestate_file, "gamestate", use_mmap)?
     };
 
     // Extract meta content if requested
     let meta_content = if section_list.contains(&"meta") {
-        let mut meta_file = archive
+        let meta_file = archive
             .by_name("meta")
-            .with_context(|| "No meta file in archive")?;
-        let mut content = Vec::new();
-        meta_file.read_to_end(&mut content)?;
-        Some(content)
+            .map_err(|_| CompanionError::MissingMeta)?;
+        Some(read_entry(meta_file, "meta", use_mmap)?)
     } else {
         None
     };
 
     // Parse and extract sections
-    let result = extract_sections(&gamestate_content, meta_content.as_deref(), &section_list)?;
+    let result = extract_sections(
+        &gamestate_content,
+        meta_content.as_deref(),
+        &section_list,
+        tokens.as_ref(),
+        encoding,
+        strip_color_codes,
+        preserve_duplicates,
+        typed_dates,
+    )?;
 
     // Output
-    write_output(&result, output)?;
+    write_output(&result, output, format)?;
 
     Ok(())
 }
 
 /// Run extraction on an already-extracted gamestate file (debug command)
-pub fn run_gamestate(path: &str, sections: &str, schema_version: &str, output: &str) -> Result<()> {
+pub fn run_gamestate(
+    path: &str,
+    sections: &str,
+    schema_version: &str,
+    output: &str,
+    tokens_path: Option<&str>,
+    encoding: Encoding,
+    use_mmap: bool,
+    strip_color_codes: bool,
+    preserve_duplicates: bool,
+    typed_dates: bool,
+    format: Format,
+) -> Result<()> {
     // Validate schema version
-    validate_schema_version(schema_version);
+    validate_schema_version(schema_version)?;
 
+    let tokens = tokens_path.map(TokenLookup::load).transpose()?;
     let section_list: Vec<&str> = sections.split(',').map(|s| s.trim()).collect();
 
     // Read the raw gamestate file
-    let mut file = File::open(path).with_context(|| format!("Failed to open file: {}", path))?;
-    let mut content = Vec::new();
-    file.read_to_end(&mut content)?;
+    let file = File::open(path).map_err(|_| CompanionError::FileNotFound {
+        path: path.to_string(),
+    })?;
+    let content = read_entry(file, "gamestate", use_mmap)?;
 
     // Parse and extract sections
-    let result = extract_sections(&content, None, &section_list)?;
+    let result = extract_sections(
+        &content,
+        None,
+        &section_list,
+        tokens.as_ref(),
+        encoding,
+        strip_color_codes,
+        preserve_duplicates,
+        typed_dates,
+    )?;
 
     // Output
-    write_output(&result, output)?;
+    write_output(&result, output, format)?;
 
     Ok(())
 }
 
-fn extract_sections(gamestate: &[u8], meta: Option<&[u8]>, sections: &[&str]) -> Result<Value> {
+/// Read a source fully into memory, or extract it to a temp file and map
+/// it, depending on `use_mmap`. `label` distinguishes the temp file names
+/// when both a gamestate and a meta body are mapped in the same run.
+fn read_entry<R: std::io::Read>(
+    reader: R,
+    label: &str,
+    use_mmap: bool,
+) -> Result<GamestateBytes, CompanionError> {
+    if use_mmap {
+        GamestateBytes::extract_and_map(reader, label)
+    } else {
+        GamestateBytes::read_owned(reader).map_err(|e| CompanionError::ParseError {
+            source: anyhow::Error::new(e),
+            decode_offset: None,
+        })
+    }
+}
+
+fn extract_sections(
+    gamestate: &[u8],
+    meta: Option<&[u8]>,
+    sections: &[&str],
+    tokens: Option<&TokenLookup>,
+    encoding: Encoding,
+    strip_color_codes: bool,
+    preserve_duplicates: bool,
+    typed_dates: bool,
+) -> Result<Value> {
     let mut result = Map::new();
     result.insert("schema_version".to_string(), json!(SCHEMA_VERSION));
     result.insert("tool_version".to_string(), json!(TOOL_VERSION));
     result.insert("game".to_string(), json!("stellaris"));
 
-    // Parse the full gamestate once using Windows-1252 encoding
-    // (Stellaris saves use Windows-1252, not UTF-8)
-    let parsed: HashMap<String, Value> =
-        from_windows1252_slice(gamestate).with_context(|| "Failed to parse gamestate")?;
+    // Decode and parse the gamestate. Prefers UTF-8, falling back to
+    // Windows-1252 for older/localized text saves (unless `encoding`
+    // overrides that), or to the binary (ironman) decoder if `gamestate`
+    // turns out to be a token stream (see `output::decode_auto`). With
+    // `preserve_duplicates`, repeated keys within an object come back as a
+    // JSON array instead of the last occurrence silently winning (see
+    // `output::decode_gamestate_preserving_duplicates`); that decoder
+    // doesn't handle binary saves, so `preserve_duplicates` only takes
+    // effect for plain-text gamestates.
+    let (parsed, _decode_offset) = decode(gamestate, tokens, encoding, preserve_duplicates)?;
 
     // Extract requested sections from gamestate
     for section in sections {
         if *section == "meta" {
             if let Some(meta_bytes) = meta {
-                let meta_parsed: HashMap<String, Value> = from_windows1252_slice(meta_bytes)
-                    .with_context(|| "Failed to parse meta file")?;
+                let (meta_parsed, _) = decode(meta_bytes, tokens, encoding, preserve_duplicates)?;
                 result.insert("meta".to_string(), json!(meta_parsed));
             }
         } else if let Some(value) = parsed.get(*section) {
@@ -94,67 +193,93 @@ fn extract_sections(gamestate: &[u8], meta: Option<&[u8]>, sections: &[&str]) ->
         }
     }
 
-    Ok(Value::Object(result))
+    let mut value = Value::Object(result);
+    if strip_color_codes {
+        crate::output::strip_color_codes_in_value(&mut value);
+    }
+    if typed_dates {
+        crate::output::promote_dates_in_value(&mut value);
+    }
+
+    Ok(value)
 }
 
-fn write_output(result: &Value, output: &str) -> Result<()> {
-    let json_str = serde_json::to_string_pretty(result)?;
+fn decode(
+    bytes: &[u8],
+    tokens: Option<&TokenLookup>,
+    encoding: Encoding,
+    preserve_duplicates: bool,
+) -> Result<(std::collections::HashMap<String, Value>, Option<usize>), CompanionError> {
+    if preserve_duplicates && !crate::binary::looks_like_binary(bytes) {
+        crate::output::decode_gamestate_preserving_duplicates(bytes)
+    } else {
+        crate::output::decode_auto(bytes, tokens, encoding)
+    }
+}
+
+/// Encode `result` through the `Serializer` for `format` and write it either
+/// to stdout (`output == "-"`) or to a file. Writes raw bytes rather than
+/// `println!`-ing a `String`, since the binary formats (`msgpack`, `cbor`)
+/// aren't valid UTF-8. A trailing newline is appended only after `Json` and
+/// `Toml` output written to stdout; output written to a file gets none.
+fn write_output(result: &Value, output: &str, format: Format) -> Result<()> {
+    let serializer = crate::serialize::for_format(format);
 
     if output == "-" {
-        println!("{}", json_str);
+        let mut stdout = io::stdout().lock();
+        serializer.write(result, &mut stdout)?;
+        if matches!(format, Format::Json | Format::Toml) {
+            stdout.write_all(b"\n")?;
+        }
     } else {
         let file = File::create(output)?;
         let mut writer = BufWriter::new(file);
-        writer.write_all(json_str.as_bytes())?;
+        serializer.write(result, &mut writer)?;
     }
 
     Ok(())
 }
 
-/// Validate schema version or exit with error
-fn validate_schema_version(schema_version: &str) {
+/// Validate the requested schema version, returning a typed error instead of
+/// exiting directly so this stays usable as a library function.
+fn validate_schema_version(schema_version: &str) -> Result<(), CompanionError> {
     match schema_version.parse::<u32>() {
-        Ok(v) if v == SCHEMA_VERSION => {}
-        Ok(v) => {
-            exit_with_error(
-                ErrorKind::InvalidArgument,
-                &format!(
-                    "Requested schema version {} is not supported. Supported: {}",
-                    v, SCHEMA_VERSION
-                ),
-            );
-        }
-        Err(_) => {
-            exit_with_error(
-                ErrorKind::InvalidArgument,
-                &format!("Invalid schema version: {}", schema_version),
-            );
-        }
+        Ok(v) if v == SCHEMA_VERSION => Ok(()),
+        Ok(v) => Err(CompanionError::SchemaVersionMismatch {
+            requested: v.to_string(),
+            supported: SCHEMA_VERSION,
+        }),
+        Err(_) => Err(CompanionError::InvalidArgument {
+            detail: format!("Invalid schema version: {}", schema_version),
+        }),
     }
 }
 
-/// Read gamestate and optionally meta from a .sav ZIP archive
-pub fn read_sav_file(path: &str) -> Result<(Vec<u8>, Option<Vec<u8>>)> {
-    let file = File::open(path).with_context(|| format!("Failed to open file: {}", path))?;
-    let mut archive = ZipArchive::new(file).with_context(|| "Failed to read ZIP archive")?;
+/// Read gamestate and optionally meta from a .sav ZIP archive. `use_mmap`
+/// extracts each entry to a temp file and maps it instead of reading it
+/// fully into memory (see `gamestate_bytes::GamestateBytes`).
+pub fn read_sav_file(
+    path: &str,
+    use_mmap: bool,
+) -> Result<(GamestateBytes, Option<GamestateBytes>)> {
+    let file = File::open(path).map_err(|_| CompanionError::FileNotFound {
+        path: path.to_string(),
+    })?;
+    let mut archive = ZipArchive::new(file).map_err(|e| CompanionError::CorruptArchive {
+        source: anyhow::Error::new(e),
+    })?;
 
     // Extract gamestate content
     let gamestate_content = {
-        let mut gamestate_file = archive
+        let gamestate_file = archive
             .by_name("gamestate")
-            .with_context(|| "No gamestate file in archive")?;
-        let mut content = Vec::new();
-        gamestate_file.read_to_end(&mut content)?;
-        content
+            .map_err(|_| CompanionError::MissingGamestate)?;
+        read_entry(gamestate_file, "gamestate", use_mmap)?
     };
 
     // Try to extract meta content (may not always need it)
     let meta_content = match archive.by_name("meta") {
-        Ok(mut meta_file) => {
-            let mut content = Vec::new();
-            meta_file.read_to_end(&mut content)?;
-            Some(content)
-        }
+        Ok(meta_file) => Some(read_entry(meta_file, "meta", use_mmap)?),
         Err(_) => None,
     };
 
@@ -168,8 +293,8 @@ mod tests {
 
     #[test]
     fn test_schema_version_validation() {
-        // Schema version 1 should be valid
-        assert_eq!(SCHEMA_VERSION, 1);
+        // Schema version 2 should be valid
+        assert_eq!(SCHEMA_VERSION, 2);
     }
 
     #[test]
@@ -178,7 +303,7 @@ mod tests {
         // This test uses the actual test_save.sav if available
         let test_path = "../test_save.sav";
         if Path::new(test_path).exists() {
-            let result = read_sav_file(test_path);
+            let result = read_sav_file(test_path, false);
             assert!(
                 result.is_ok(),
                 "Should be able to read test_save.sav as ZIP"
@@ -193,10 +318,144 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_sav_reading_file_structure_mmap() {
+        // Same as above, but through the mmap-backed path.
+        let test_path = "../test_save.sav";
+        if Path::new(test_path).exists() {
+            let result = read_sav_file(test_path, true);
+            assert!(result.is_ok(), "Should be able to mmap test_save.sav");
+            let (gamestate, meta) = result.unwrap();
+            assert!(!gamestate.is_empty(), "Gamestate should not be empty");
+            assert!(meta.is_some(), "Meta should be present in test save");
+        }
+    }
+
+    #[test]
+    fn test_extract_sections_strips_color_codes_when_requested() {
+        let data = b"country={text=\"\x15BColored\x15! Text\"}";
+        let result = extract_sections(
+            data,
+            None,
+            &["country"],
+            None,
+            Encoding::default(),
+            true,
+            false,
+            false,
+        )
+        .expect("should extract successfully");
+        assert_eq!(result["country"]["text"], "Colored Text");
+    }
+
+    #[test]
+    fn test_extract_sections_preserves_duplicates_when_requested() {
+        let data = b"traits={trait=a trait=b}";
+        let result = extract_sections(
+            data,
+            None,
+            &["traits"],
+            None,
+            Encoding::default(),
+            false,
+            true,
+            false,
+        )
+        .expect("should extract successfully");
+        assert_eq!(result["traits"]["trait"], serde_json::json!(["a", "b"]));
+    }
+
+    #[test]
+    fn test_extract_sections_promotes_dates_when_requested() {
+        let data = b"country={start_date=\"2200.01.01\"}";
+        let result = extract_sections(
+            data,
+            None,
+            &["country"],
+            None,
+            Encoding::default(),
+            false,
+            false,
+            true,
+        )
+        .expect("should extract successfully");
+        assert_eq!(result["country"]["start_date"]["__type"], "date");
+        assert_eq!(result["country"]["start_date"]["year"], 2200);
+    }
+
+    #[test]
+    fn test_extract_sections_binary_without_tokens_fails() {
+        // A byte string `looks_like_binary` flags as a token stream, with no
+        // token table supplied, should fail with a clear message rather
+        // than a generic parse error.
+        let binary_bytes = [0x03, 0x00, 0x36, 0x00];
+        let result = extract_sections(
+            &binary_bytes,
+            None,
+            &["owner"],
+            None,
+            Encoding::default(),
+            false,
+            false,
+            false,
+        );
+        assert!(result.is_err());
+        let err_msg = format!("{:#}", result.unwrap_err());
+        assert!(
+            err_msg.contains("--tokens"),
+            "Error should mention --tokens: {}",
+            err_msg
+        );
+    }
+
+    #[test]
+    fn test_write_output_json_round_trips_through_file() {
+        let value = serde_json::json!({"country": {"name": "Test Empire"}});
+        let path = std::env::temp_dir().join("stellaris-extract-test-json.json");
+        write_output(&value, path.to_str().unwrap(), Format::Json).expect("should write");
+        let contents = std::fs::read_to_string(&path).expect("should read back");
+        let decoded: Value = serde_json::from_str(&contents).expect("should parse");
+        assert_eq!(decoded, value);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_write_output_toml_round_trips_through_file() {
+        let value = serde_json::json!({"country": {"name": "Test Empire", "id": 42}});
+        let path = std::env::temp_dir().join("stellaris-extract-test-toml.toml");
+        write_output(&value, path.to_str().unwrap(), Format::Toml).expect("should write");
+        let contents = std::fs::read_to_string(&path).expect("should read back");
+        let decoded: toml::Table = toml::from_str(&contents).expect("should parse");
+        assert_eq!(decoded["country"]["name"].as_str(), Some("Test Empire"));
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_write_output_msgpack_round_trips_through_file() {
+        let value = serde_json::json!({"country": {"name": "Test Empire"}});
+        let path = std::env::temp_dir().join("stellaris-extract-test.msgpack");
+        write_output(&value, path.to_str().unwrap(), Format::MessagePack).expect("should write");
+        let contents = std::fs::read(&path).expect("should read back");
+        let decoded: Value = rmp_serde::from_slice(&contents).expect("should decode");
+        assert_eq!(decoded, value);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_write_output_cbor_round_trips_through_file() {
+        let value = serde_json::json!({"country": {"name": "Test Empire"}});
+        let path = std::env::temp_dir().join("stellaris-extract-test.cbor");
+        write_output(&value, path.to_str().unwrap(), Format::Cbor).expect("should write");
+        let contents = std::fs::read(&path).expect("should read back");
+        let decoded: Value = ciborium::de::from_reader(&contents[..]).expect("should decode");
+        assert_eq!(decoded, value);
+        let _ = std::fs::remove_file(&path);
+    }
+
     #[test]
     fn test_sav_reading_nonexistent_file() {
         // Test that nonexistent files return proper error
-        let result = read_sav_file("nonexistent.sav");
+        let result = read_sav_file("nonexistent.sav", false);
         assert!(result.is_err(), "Should fail for nonexistent file");
         let err_msg = format!("{:#}", result.unwrap_err());
         assert!(
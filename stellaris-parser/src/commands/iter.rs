@@ -1,96 +1,357 @@
-use crate::error::{exit_with_error, ErrorKind, SCHEMA_VERSION, TOOL_VERSION};
-use anyhow::{Context, Result};
-use jomini::text::de::from_windows1252_slice;
-use serde_json::{json, Value};
-use std::collections::HashMap;
+use crate::binary::TokenLookup;
+use crate::encoding::Encoding;
+use crate::error::{CompanionError, SCHEMA_VERSION, TOOL_VERSION};
+use crate::gamestate_bytes::GamestateBytes;
+use crate::serialize::Format;
+use anyhow::Result;
+use jomini::text::ObjectReader;
+use jomini::{Encoding as JominiEncoding, TextTape};
+use serde::Serialize;
+use serde_json::{json, Map, Value};
 use std::fs::File;
-use std::io::Read;
+use std::io::{self, Write};
+use std::time::Instant;
 use zip::ZipArchive;
 
-/// Stream entries from a large section in a .sav file as JSONL
-pub fn run_save(path: &str, section: &str, schema_version: &str, format: &str) -> Result<()> {
+/// A single line of the JSONL event stream, tagged the way ripgrep tags its
+/// own JSON output: `{"type": "...", "data": {...}}`. `begin` opens the
+/// stream, one `entry` follows per key/value pair, and a closing `summary`
+/// lets consumers detect completion without waiting on EOF.
+#[derive(Serialize)]
+#[serde(tag = "type", content = "data", rename_all = "snake_case")]
+enum JsonlEvent<'a> {
+    Begin {
+        schema_version: u32,
+        tool_version: &'static str,
+        game: &'static str,
+        section: &'a str,
+    },
+    Entry {
+        key: &'a str,
+        value: &'a Value,
+    },
+    Summary {
+        entries: usize,
+        value_bytes: usize,
+        elapsed_ms: u128,
+    },
+}
+
+/// Stream entries from a large section in a .sav file. `format` selects the
+/// output serialization (see `crate::serialize`): `Jsonl`, the default,
+/// streams one line per entry the way it always has; any other format
+/// buffers the whole section into a single document (see
+/// `buffer_entries`) and writes it once, since TOML/MessagePack/CBOR have no
+/// line-oriented equivalent of JSONL.
+/// `tokens_path` is only consulted if the save turns out to be binary
+/// (ironman); plain-text saves ignore it entirely. `encoding` selects the
+/// input text encoding for plain-text saves (see `crate::encoding`); the
+/// `preserve_duplicates` and `streaming` decode paths do not apply it.
+/// `use_mmap` extracts the gamestate entry to a temp file and maps it
+/// instead of reading it fully into memory (see
+/// `gamestate_bytes::GamestateBytes`). `preserve_duplicates` decodes
+/// through jomini's `TextTape` mid-level API instead of straight into a
+/// `HashMap`, so a key that repeats within one object comes out as a JSON
+/// array instead of only its last occurrence (see
+/// `crate::output::decode_gamestate_preserving_duplicates`); it has no
+/// effect on binary (ironman) saves. `streaming` walks the requested
+/// section through `crate::events::stream_section` instead of decoding the
+/// whole gamestate into a `HashMap<String, Value>` first (see
+/// `run_streaming`), bounding peak memory to one entry at a time; it has no
+/// effect when combined with `preserve_duplicates` or on binary (ironman)
+/// saves, both of which fall back to the existing whole-gamestate decode.
+pub fn run_save(
+    path: &str,
+    section: &str,
+    schema_version: &str,
+    format: Format,
+    tokens_path: Option<&str>,
+    encoding: Encoding,
+    use_mmap: bool,
+    preserve_duplicates: bool,
+    streaming: bool,
+) -> Result<()> {
     // Validate schema version
-    validate_schema_version(schema_version);
+    validate_schema_version(schema_version)?;
 
-    // Validate format
-    if format != "jsonl" {
-        exit_with_error(
-            ErrorKind::InvalidArgument,
-            &format!("Unsupported format: {}. Only 'jsonl' is supported.", format),
-        );
-    }
+    let tokens = tokens_path.map(TokenLookup::load).transpose()?;
 
     // Read the .sav file (ZIP archive)
-    let file = File::open(path).with_context(|| format!("Failed to open file: {}", path))?;
-    let mut archive = ZipArchive::new(file).with_context(|| "Failed to read ZIP archive")?;
+    let file = File::open(path).map_err(|_| CompanionError::FileNotFound {
+        path: path.to_string(),
+    })?;
+    let mut archive = ZipArchive::new(file).map_err(|e| CompanionError::CorruptArchive {
+        source: anyhow::Error::new(e),
+    })?;
 
     // Extract gamestate content
     let gamestate_content = {
-        let mut gamestate_file = archive
+        let gamestate_file = archive
             .by_name("gamestate")
-            .with_context(|| "No gamestate file in archive")?;
-        let mut content = Vec::new();
-        gamestate_file.read_to_end(&mut content)?;
-        content
+            .map_err(|_| CompanionError::MissingGamestate)?;
+        if use_mmap {
+            GamestateBytes::extract_and_map(gamestate_file, "gamestate")?
+        } else {
+            GamestateBytes::read_owned(gamestate_file).map_err(|e| CompanionError::ParseError {
+                source: anyhow::Error::new(e),
+                decode_offset: None,
+            })?
+        }
     };
 
-    // Parse the full gamestate using Windows-1252 encoding
-    // (Stellaris saves use Windows-1252, not UTF-8)
-    let parsed: HashMap<String, Value> =
-        from_windows1252_slice(&gamestate_content).with_context(|| "Failed to parse gamestate")?;
-
-    // Get the requested section
-    if let Some(section_value) = parsed.get(section) {
-        // First line includes full metadata
-        let mut is_first = true;
-
-        // If it's an object with key-value pairs, iterate over them
-        if let Value::Object(map) = section_value {
-            for (key, value) in map {
-                let line = if is_first {
-                    is_first = false;
-                    json!({
-                        "schema_version": SCHEMA_VERSION,
-                        "tool_version": TOOL_VERSION,
-                        "game": "stellaris",
-                        "section": section,
-                        "key": key,
-                        "value": value
-                    })
-                } else {
-                    json!({
-                        "key": key,
-                        "value": value
-                    })
-                };
-                println!("{}", serde_json::to_string(&line)?);
-            }
-        }
+    if streaming
+        && !preserve_duplicates
+        && !crate::binary::looks_like_binary(&gamestate_content)
+    {
+        return run_streaming(&gamestate_content, section, format);
+    }
+
+    // Decode and parse the gamestate, preferring UTF-8 and falling back to
+    // Windows-1252 for older/localized text saves, or to the binary
+    // (ironman) decoder if `gamestate_content` turns out to be a token
+    // stream (see `output::decode_auto`).
+    let (parsed, _decode_offset) = if preserve_duplicates
+        && !crate::binary::looks_like_binary(&gamestate_content)
+    {
+        crate::output::decode_gamestate_preserving_duplicates(&gamestate_content)?
+    } else {
+        crate::output::decode_auto(&gamestate_content, tokens.as_ref(), encoding)?
+    };
+
+    if format.is_line_oriented() {
+        stream_jsonl(&parsed, section)
+    } else {
+        let document = buffer_entries(&parsed, section)?;
+        let mut stdout = io::stdout().lock();
+        crate::serialize::for_format(format).write(&document, &mut stdout)?;
+        stdout.write_all(b"\n").map_err(anyhow::Error::new)?;
+        Ok(())
     }
+}
+
+/// Walk `section` via `crate::events::stream_section` instead of decoding
+/// the whole gamestate into a `HashMap<String, Value>` first: each entry's
+/// `Value` is built (and, for JSONL, printed) as soon as it's visited, so
+/// the rest of the gamestate — and the rest of the section, past the entry
+/// currently being processed — never has to exist as a `Value` tree at the
+/// same time. Picks UTF-8 vs. Windows-1252 the same way `output::decode_gamestate`
+/// does; unlike that path there's no binary (ironman) branch here, since the
+/// caller already excludes binary saves before calling this.
+fn run_streaming(bytes: &[u8], section: &str, format: Format) -> Result<()> {
+    let tape = TextTape::from_slice(bytes).map_err(|e| CompanionError::ParseError {
+        source: anyhow::Error::new(e),
+        decode_offset: None,
+    })?;
+
+    if std::str::from_utf8(bytes).is_ok() {
+        run_streaming_with_reader(tape.utf8_reader(), section, format)
+    } else {
+        run_streaming_with_reader(tape.windows1252_reader(), section, format)
+    }
+}
+
+fn run_streaming_with_reader<E: JominiEncoding + Clone>(
+    root: ObjectReader<'_, '_, E>,
+    section: &str,
+    format: Format,
+) -> Result<()> {
+    if format.is_line_oriented() {
+        stream_jsonl_via_events(root, section)
+    } else {
+        let document = buffer_entries_via_events(root, section)?;
+        let mut stdout = io::stdout().lock();
+        crate::serialize::for_format(format).write(&document, &mut stdout)?;
+        stdout.write_all(b"\n").map_err(anyhow::Error::new)?;
+        Ok(())
+    }
+}
+
+/// The `--streaming` counterpart of `stream_jsonl`: prints the same
+/// `Begin`/`Entry`/`Summary` lines, but each `Entry`'s `Value` is built from
+/// `crate::events::stream_section` as that one entry is visited, rather than
+/// looked up out of an already-fully-decoded `HashMap`.
+fn stream_jsonl_via_events<E: JominiEncoding + Clone>(
+    root: ObjectReader<'_, '_, E>,
+    section: &str,
+) -> Result<()> {
+    let start = Instant::now();
+    let mut entries = 0usize;
+    let mut value_bytes = 0usize;
+
+    println!(
+        "{}",
+        serde_json::to_string(&JsonlEvent::Begin {
+            schema_version: SCHEMA_VERSION,
+            tool_version: TOOL_VERSION,
+            game: "stellaris",
+            section,
+        })?
+    );
+
+    crate::events::stream_section(root, section, |key, value| {
+        let value = crate::events::entry_to_value(value)?;
+        value_bytes += serde_json::to_string(&value)
+            .map_err(|e| CompanionError::ParseError {
+                source: anyhow::Error::new(e),
+                decode_offset: None,
+            })?
+            .len();
+        entries += 1;
+        println!(
+            "{}",
+            serde_json::to_string(&JsonlEvent::Entry {
+                key: &key,
+                value: &value,
+            })
+            .map_err(|e| CompanionError::ParseError {
+                source: anyhow::Error::new(e),
+                decode_offset: None,
+            })?
+        );
+        Ok(())
+    })?;
+
+    println!(
+        "{}",
+        serde_json::to_string(&JsonlEvent::Summary {
+            entries,
+            value_bytes,
+            elapsed_ms: start.elapsed().as_millis(),
+        })?
+    );
 
     Ok(())
 }
 
-/// Validate schema version or exit with error
-fn validate_schema_version(schema_version: &str) {
-    match schema_version.parse::<u32>() {
-        Ok(v) if v == SCHEMA_VERSION => {}
-        Ok(v) => {
-            exit_with_error(
-                ErrorKind::InvalidArgument,
-                &format!(
-                    "Requested schema version {} is not supported. Supported: {}",
-                    v, SCHEMA_VERSION
-                ),
+/// The `--streaming` counterpart of `buffer_entries`, for formats with no
+/// line-oriented mode: still buffers the section's entries into one
+/// document (TOML/MessagePack/CBOR have nowhere else to put them), but each
+/// entry's `Value` comes from `crate::events::stream_section` instead of an
+/// already-fully-decoded `HashMap`.
+fn buffer_entries_via_events<E: JominiEncoding + Clone>(
+    root: ObjectReader<'_, '_, E>,
+    section: &str,
+) -> Result<Value> {
+    let start = Instant::now();
+    let mut value_bytes = 0usize;
+    let mut entries_map = Map::new();
+
+    crate::events::stream_section(root, section, |key, value| {
+        let value = crate::events::entry_to_value(value)?;
+        value_bytes += serde_json::to_string(&value)
+            .map_err(|e| CompanionError::ParseError {
+                source: anyhow::Error::new(e),
+                decode_offset: None,
+            })?
+            .len();
+        entries_map.insert(key.into_owned(), value);
+        Ok(())
+    })?;
+
+    let entries = entries_map.len();
+    Ok(json!({
+        "schema_version": SCHEMA_VERSION,
+        "tool_version": TOOL_VERSION,
+        "game": "stellaris",
+        "section": section,
+        "entries": entries_map,
+        "summary": {
+            "entries": entries,
+            "value_bytes": value_bytes,
+            "elapsed_ms": start.elapsed().as_millis() as u64,
+        },
+    }))
+}
+
+/// The original streaming path: one `JsonlEvent` line per entry, printed as
+/// they're produced instead of buffered, so a consumer piping this output
+/// sees entries as soon as they're available rather than waiting on the
+/// whole section.
+fn stream_jsonl(parsed: &std::collections::HashMap<String, Value>, section: &str) -> Result<()> {
+    let start = Instant::now();
+    let mut entries = 0usize;
+    let mut value_bytes = 0usize;
+
+    println!(
+        "{}",
+        serde_json::to_string(&JsonlEvent::Begin {
+            schema_version: SCHEMA_VERSION,
+            tool_version: TOOL_VERSION,
+            game: "stellaris",
+            section,
+        })?
+    );
+
+    // If it's an object with key-value pairs, iterate over them. A missing
+    // or non-object section still gets a `begin`/`summary` pair, just with
+    // zero entries, so the stream's completion contract always holds.
+    if let Some(Value::Object(map)) = parsed.get(section) {
+        for (key, value) in map {
+            value_bytes += serde_json::to_string(value)?.len();
+            entries += 1;
+            println!(
+                "{}",
+                serde_json::to_string(&JsonlEvent::Entry { key, value })?
             );
         }
-        Err(_) => {
-            exit_with_error(
-                ErrorKind::InvalidArgument,
-                &format!("Invalid schema version: {}", schema_version),
-            );
+    }
+
+    println!(
+        "{}",
+        serde_json::to_string(&JsonlEvent::Summary {
+            entries,
+            value_bytes,
+            elapsed_ms: start.elapsed().as_millis(),
+        })?
+    );
+
+    Ok(())
+}
+
+/// Build the single-document equivalent of the `begin`/`entry*`/`summary`
+/// JSONL stream, for formats with no line-oriented mode (TOML, MessagePack,
+/// CBOR). Entries keep the section's original key order the same way
+/// `stream_jsonl` visits them.
+fn buffer_entries(parsed: &std::collections::HashMap<String, Value>, section: &str) -> Result<Value> {
+    let start = Instant::now();
+    let mut value_bytes = 0usize;
+    let mut entries_map = Map::new();
+
+    if let Some(Value::Object(map)) = parsed.get(section) {
+        for (key, value) in map {
+            value_bytes += serde_json::to_string(value)?.len();
+            entries_map.insert(key.clone(), value.clone());
         }
     }
+
+    let entries = entries_map.len();
+    Ok(json!({
+        "schema_version": SCHEMA_VERSION,
+        "tool_version": TOOL_VERSION,
+        "game": "stellaris",
+        "section": section,
+        "entries": entries_map,
+        "summary": {
+            "entries": entries,
+            "value_bytes": value_bytes,
+            "elapsed_ms": start.elapsed().as_millis() as u64,
+        },
+    }))
+}
+
+/// Validate the requested schema version, returning a typed error instead of
+/// exiting directly so this stays usable as a library function.
+fn validate_schema_version(schema_version: &str) -> Result<(), CompanionError> {
+    match schema_version.parse::<u32>() {
+        Ok(v) if v == SCHEMA_VERSION => Ok(()),
+        Ok(v) => Err(CompanionError::SchemaVersionMismatch {
+            requested: v.to_string(),
+            supported: SCHEMA_VERSION,
+        }),
+        Err(_) => Err(CompanionError::InvalidArgument {
+            detail: format!("Invalid schema version: {}", schema_version),
+        }),
+    }
 }
 
 #[cfg(test)]
@@ -99,6 +360,129 @@ mod tests {
 
     #[test]
     fn test_schema_version() {
-        assert_eq!(SCHEMA_VERSION, 1);
+        assert_eq!(SCHEMA_VERSION, 2);
+    }
+
+    #[test]
+    fn test_jsonl_event_tagging() {
+        let begin = JsonlEvent::Begin {
+            schema_version: SCHEMA_VERSION,
+            tool_version: TOOL_VERSION,
+            game: "stellaris",
+            section: "country",
+        };
+        let value = serde_json::to_value(&begin).unwrap();
+        assert_eq!(value["type"], "begin");
+        assert_eq!(value["data"]["section"], "country");
+
+        let summary = JsonlEvent::Summary {
+            entries: 3,
+            value_bytes: 42,
+            elapsed_ms: 7,
+        };
+        let value = serde_json::to_value(&summary).unwrap();
+        assert_eq!(value["type"], "summary");
+        assert_eq!(value["data"]["entries"], 3);
+    }
+
+    #[test]
+    fn test_run_save_nonexistent_file() {
+        let result = run_save(
+            "nonexistent.sav",
+            "country",
+            "2",
+            Format::Jsonl,
+            None,
+            Encoding::default(),
+            false,
+            false,
+            false,
+        );
+        assert!(result.is_err(), "Should fail for nonexistent file");
+    }
+
+    #[test]
+    fn test_buffer_entries_via_events_matches_buffer_entries() {
+        let data = br#"country={1={name="Empire A" id=42 active=yes} 2={name="Empire B" id=7 active=no}}"#;
+        let tape = TextTape::from_slice(data).unwrap();
+
+        let (parsed, _decode_offset) =
+            crate::output::decode_gamestate(data).expect("should decode");
+        let buffered = buffer_entries(&parsed, "country").expect("should buffer");
+
+        let via_events =
+            buffer_entries_via_events(tape.utf8_reader(), "country").expect("should buffer");
+
+        assert_eq!(buffered["entries"], via_events["entries"]);
+        assert_eq!(buffered["summary"]["entries"], via_events["summary"]["entries"]);
+        assert_eq!(via_events["entries"]["1"]["id"], 42);
+        assert_eq!(via_events["entries"]["1"]["active"], true);
+        assert_eq!(via_events["entries"]["2"]["active"], false);
+    }
+
+    #[test]
+    fn test_buffer_entries_via_events_missing_section_is_empty() {
+        let data = br#"other_section={x=1}"#;
+        let tape = TextTape::from_slice(data).unwrap();
+        let document =
+            buffer_entries_via_events(tape.utf8_reader(), "country").expect("should buffer");
+        assert_eq!(document["entries"], json!({}));
+        assert_eq!(document["summary"]["entries"], 0);
+    }
+
+    #[test]
+    fn test_buffer_entries_via_events_toml_round_trips() {
+        let data = br#"country={1={name="Empire A"}}"#;
+        let tape = TextTape::from_slice(data).unwrap();
+        let document =
+            buffer_entries_via_events(tape.utf8_reader(), "country").expect("should buffer");
+        let mut bytes = Vec::new();
+        crate::serialize::for_format(Format::Toml)
+            .write(&document, &mut bytes)
+            .expect("should encode as toml");
+        let decoded: toml::Table = toml::from_str(std::str::from_utf8(&bytes).unwrap()).unwrap();
+        assert_eq!(decoded["section"].as_str(), Some("country"));
+        assert_eq!(decoded["entries"]["1"]["name"].as_str(), Some("Empire A"));
+    }
+
+    #[test]
+    fn test_buffer_entries_collects_section_as_one_document() {
+        let mut parsed = std::collections::HashMap::new();
+        parsed.insert(
+            "country".to_string(),
+            json!({"1": {"name": "Empire A"}, "2": {"name": "Empire B"}}),
+        );
+        let document = buffer_entries(&parsed, "country").expect("should buffer");
+        assert_eq!(document["section"], "country");
+        assert_eq!(document["entries"]["1"]["name"], "Empire A");
+        assert_eq!(document["summary"]["entries"], 2);
+    }
+
+    #[test]
+    fn test_buffer_entries_missing_section_is_empty() {
+        let parsed = std::collections::HashMap::new();
+        let document = buffer_entries(&parsed, "country").expect("should buffer");
+        assert_eq!(document["entries"], json!({}));
+        assert_eq!(document["summary"]["entries"], 0);
+    }
+
+    #[test]
+    fn test_buffer_entries_toml_round_trips() {
+        let mut parsed = std::collections::HashMap::new();
+        parsed.insert(
+            "country".to_string(),
+            json!({"1": {"name": "Empire A"}}),
+        );
+        let document = buffer_entries(&parsed, "country").expect("should buffer");
+        let mut bytes = Vec::new();
+        crate::serialize::for_format(Format::Toml)
+            .write(&document, &mut bytes)
+            .expect("should encode as toml");
+        let decoded: toml::Table = toml::from_str(std::str::from_utf8(&bytes).unwrap()).unwrap();
+        assert_eq!(decoded["section"].as_str(), Some("country"));
+        assert_eq!(
+            decoded["entries"]["1"]["name"].as_str(),
+            Some("Empire A")
+        );
     }
 }
@@ -3,14 +3,17 @@
 //! Loads and parses a save file once, then responds to JSON requests via stdin/stdout.
 //! This eliminates re-parsing overhead when making multiple queries against the same save.
 
-use crate::error::{ErrorKind, SCHEMA_VERSION, TOOL_VERSION};
+use crate::error::{CompanionError, ErrorKind, SCHEMA_VERSION, TOOL_VERSION};
+use crate::gamestate_bytes::GamestateBytes;
 use aho_corasick::AhoCorasick;
-use anyhow::{Context, Result};
-use jomini::text::de::from_windows1252_slice;
+use anyhow::Result;
+use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Map, Value};
 use std::collections::HashMap;
 use std::io::{self, BufRead, Write};
+use std::sync::{mpsc, Arc, Condvar, Mutex};
+use std::thread;
 
 /// Request types for session mode
 #[derive(Debug, Deserialize)]
@@ -51,20 +54,218 @@ enum Request {
         key: String,
         field: String,
     },
+    /// Typed successor to `GetDuplicateValues`: every occurrence of `field`
+    /// inside one entry, as real JSON values instead of strings.
+    GetMultiField {
+        section: String,
+        key: String,
+        field: String,
+    },
+    /// Re-parse a whole entry preserving every duplicate key, instead of
+    /// the "last one wins" whole-gamestate deserialization.
+    GetEntryFields {
+        section: String,
+        key: String,
+    },
     /// Get raw Clausewitz text for a single entry (for duplicate-key parsing in Python)
     GetEntryText {
         section: String,
         key: String,
     },
+    /// Like `GetEntryText`, but for a literal duplicate top-level key: every
+    /// occurrence's raw text, in order, instead of just the first.
+    GetAllEntryTexts {
+        section: String,
+        key: String,
+    },
+    /// Like `GetDuplicateValues`, but for a literal duplicate top-level key:
+    /// one values list per occurrence instead of just the first.
+    GetAllDuplicateValues {
+        section: String,
+        key: String,
+        field: String,
+    },
+    /// Filter/sort/project entries of a section server-side
+    QueryEntries {
+        section: String,
+        #[serde(default)]
+        filter: Option<FilterNode>,
+        #[serde(default)]
+        sort: Vec<SortKey>,
+        #[serde(default)]
+        limit: Option<usize>,
+        #[serde(default)]
+        fields: Option<Vec<String>>,
+    },
+    /// Ranked lookup against the inverted index built once at load time
+    Search {
+        terms: Vec<String>,
+        #[serde(default = "default_top_k")]
+        top_k: usize,
+        #[serde(default)]
+        section: Option<String>,
+    },
+    /// Predicate filter + sort + pagination over a section, so callers don't
+    /// have to pull a whole section via `iter_section` and filter it
+    /// client-side just to page through the results.
+    Query {
+        section: String,
+        #[serde(default)]
+        filters: Vec<QueryFilter>,
+        #[serde(default)]
+        sort: Option<SortKey>,
+        #[serde(default)]
+        offset: usize,
+        #[serde(default)]
+        limit: Option<usize>,
+        #[serde(default)]
+        fields: Option<Vec<String>>,
+    },
+    /// Evaluate a JSONPath expression against the parsed gamestate tree, for
+    /// arbitrary nested navigation the fixed ops don't anticipate. Named
+    /// `json_path` rather than `query` since that op name is already taken
+    /// by the filter/sort/paginate op above.
+    JsonPath {
+        path: String,
+    },
+    /// Generalizes `contains_kv`'s exact-match-only conjunction into a real
+    /// boolean expression language over a section's entries: AND/OR/NOT,
+    /// `(...)` grouping, comparison operators, and inclusive ranges
+    /// (`field lo TO hi`). See `parse_filter_expr`.
+    FilterEntries {
+        section: String,
+        filter: String,
+    },
+    /// Order a section's entry keys by one field, for leaderboard-style
+    /// queries (strongest empires, largest fleets) without pulling every
+    /// entry client-side just to sort it. See `sort_entries`.
+    SortEntries {
+        section: String,
+        field: String,
+        #[serde(default)]
+        order: SortDir,
+    },
+    /// Structural delta between this session's save and another `.sav` on
+    /// disk (e.g. the previous autosave), optionally scoped to one section.
+    /// See `diff_values`. Excluded from `Multi`, like `IterSection`/`Close`,
+    /// since it loads a second save rather than just querying the one
+    /// already held in memory.
+    Diff {
+        other_path: String,
+        #[serde(default)]
+        section: Option<String>,
+    },
+    /// Change feed for a live game: reports the delta, restricted to
+    /// `sections`, since the generation the caller last saw in `token`. See
+    /// `handle_poll`. Excluded from `Multi` for the same reason as `Diff` -
+    /// it touches mutable session-wide poll state, not just the parsed save.
+    Poll {
+        #[serde(default)]
+        token: u64,
+        #[serde(default)]
+        sections: Vec<String>,
+    },
     /// Batch multiple operations in a single request to reduce IPC overhead
     Multi {
         ops: Vec<MultiOp>,
+        /// Cap on how many threads the whole-save-scan ops in this batch
+        /// (`ExtractSections`, `CountKeys`, `ContainsKv`, `GetCountrySummaries`)
+        /// run across. Defaults to rayon's global pool (usually one thread
+        /// per core) when omitted.
+        #[serde(default)]
+        max_concurrency: Option<usize>,
     },
     Close,
 }
 
+/// Wraps a `Request` with an optional client-supplied correlation id, echoed
+/// back on the matching `SuccessResponse`/`ErrorResponse`. This lets a
+/// pipelining client fire several requests without waiting for each reply
+/// and still match replies up, since pooled ops can complete out of order.
+#[derive(Debug, Deserialize)]
+struct RequestEnvelope {
+    #[serde(default)]
+    id: Option<Value>,
+    #[serde(flatten)]
+    request: Request,
+}
+
+/// A leaf or combinator node in a `query_entries` filter AST. Leaves compare
+/// a (possibly dot-notation, e.g. `"owner.faction"`) field path against a
+/// literal value; combinators compose leaves/other combinators.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum FilterNode {
+    And { and: Vec<FilterNode> },
+    Or { or: Vec<FilterNode> },
+    Not { not: Box<FilterNode> },
+    Leaf {
+        field: String,
+        op: FilterOp,
+        value: Value,
+    },
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+enum FilterOp {
+    #[serde(rename = "=")]
+    Eq,
+    #[serde(rename = "!=")]
+    Ne,
+    #[serde(rename = "<")]
+    Lt,
+    #[serde(rename = "<=")]
+    Le,
+    #[serde(rename = ">")]
+    Gt,
+    #[serde(rename = ">=")]
+    Ge,
+}
+
+/// One AND-combined predicate of a `query` filter list: compares a
+/// (possibly dot-notation) field path against a literal value. Unlike
+/// `query_entries`'s `FilterNode`, there's no and/or/not nesting here -
+/// every filter in the list must match.
+#[derive(Debug, Deserialize)]
+struct QueryFilter {
+    field: String,
+    op: QueryFilterOp,
+    #[serde(default)]
+    value: Value,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum QueryFilterOp {
+    Eq,
+    Ne,
+    Gt,
+    Gte,
+    Lt,
+    Lte,
+    Contains,
+    Exists,
+}
+
+/// One key of a `query_entries` sort list, applied in order (first key is
+/// primary).
+#[derive(Debug, Deserialize)]
+struct SortKey {
+    field: String,
+    #[serde(default)]
+    dir: SortDir,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum SortDir {
+    #[default]
+    Asc,
+    Desc,
+}
+
 /// Operations that can be batched in a multi-op request.
-/// Note: IterSection and Close are excluded as they have special handling requirements.
+/// Note: IterSection, Close, Diff, and Poll are excluded as they have special handling requirements.
 #[derive(Debug, Deserialize)]
 #[serde(tag = "op", rename_all = "snake_case")]
 enum MultiOp {
@@ -98,10 +299,72 @@ enum MultiOp {
         key: String,
         field: String,
     },
+    GetMultiField {
+        section: String,
+        key: String,
+        field: String,
+    },
+    GetEntryFields {
+        section: String,
+        key: String,
+    },
     GetEntryText {
         section: String,
         key: String,
     },
+    GetAllEntryTexts {
+        section: String,
+        key: String,
+    },
+    GetAllDuplicateValues {
+        section: String,
+        key: String,
+        field: String,
+    },
+    QueryEntries {
+        section: String,
+        #[serde(default)]
+        filter: Option<FilterNode>,
+        #[serde(default)]
+        sort: Vec<SortKey>,
+        #[serde(default)]
+        limit: Option<usize>,
+        #[serde(default)]
+        fields: Option<Vec<String>>,
+    },
+    Search {
+        terms: Vec<String>,
+        #[serde(default = "default_top_k")]
+        top_k: usize,
+        #[serde(default)]
+        section: Option<String>,
+    },
+    Query {
+        section: String,
+        #[serde(default)]
+        filters: Vec<QueryFilter>,
+        #[serde(default)]
+        sort: Option<SortKey>,
+        #[serde(default)]
+        offset: usize,
+        #[serde(default)]
+        limit: Option<usize>,
+        #[serde(default)]
+        fields: Option<Vec<String>>,
+    },
+    JsonPath {
+        path: String,
+    },
+    FilterEntries {
+        section: String,
+        filter: String,
+    },
+    SortEntries {
+        section: String,
+        field: String,
+        #[serde(default)]
+        order: SortDir,
+    },
 }
 
 /// Default batch size for iter_section (100 entries per message)
@@ -109,6 +372,11 @@ fn default_batch_size() -> usize {
     100
 }
 
+/// Default number of ranked hits returned by `search`
+fn default_top_k() -> usize {
+    10
+}
+
 /// Successful response wrapper
 #[derive(Debug, Serialize)]
 struct SuccessResponse {
@@ -167,15 +435,82 @@ enum ResponseData {
         values: Vec<String>,
         found: bool,
     },
+    /// Legacy-shaped duplicate values for every top-level occurrence of a
+    /// duplicate key, one list per occurrence
+    AllDuplicateValues {
+        occurrences: Vec<Vec<String>>,
+        found: bool,
+    },
+    /// Typed, duplicate-preserving values for one field inside an entry
+    MultiFieldValues {
+        values: Vec<Value>,
+        found: bool,
+    },
+    /// A whole entry re-parsed with duplicate keys preserved as repeated items
+    EntryFields {
+        fields: Vec<EntryData>,
+        found: bool,
+    },
     /// Raw Clausewitz text for a single entry
     EntryText {
         text: String,
         found: bool,
     },
+    /// Raw Clausewitz text for every top-level occurrence of a duplicate key
+    AllEntryText {
+        texts: Vec<String>,
+        found: bool,
+    },
     /// Results from a multi-op batch request
     MultiResults {
         results: Vec<Value>,
     },
+    /// Results from a `query_entries` filter/sort/project operation
+    QueryResults {
+        entries: Vec<Value>,
+    },
+    /// Ranked hits from a `search` operation against the inverted index
+    SearchResults {
+        results: Vec<SearchHit>,
+    },
+    /// One page of results from a `query` filter/sort/paginate operation
+    QueryPage {
+        entries: Vec<Value>,
+        total: usize,
+        next_offset: Option<usize>,
+    },
+    /// Every node matched by a `json_path` expression
+    JsonPathResults {
+        matches: Vec<Value>,
+    },
+    /// Entry keys of a section ordered by `sort_entries`
+    SortedKeys {
+        keys: Vec<String>,
+    },
+    /// Structural delta produced by a `diff` operation
+    DiffResults {
+        changes: Vec<DiffRecord>,
+    },
+    /// Change-feed delta produced by a `poll` operation, plus the
+    /// generation token to pass back on the next poll
+    PollResult {
+        token: u64,
+        changes: Vec<DiffRecord>,
+    },
+}
+
+/// One ranked hit from `search`: an entry location plus which fields of it
+/// matched the query.
+#[derive(Debug, Serialize, Clone)]
+struct SearchHit {
+    section: String,
+    entry_key: String,
+    matched_fields: Vec<String>,
+    matching_terms: usize,
+    term_frequency: usize,
+    /// Sum of the edit distance each matched query term needed to reach its
+    /// closest indexed token (0 for every exact match). Lower is a better hit.
+    typo_count: usize,
 }
 
 #[derive(Debug, Serialize)]
@@ -216,34 +551,303 @@ impl ErrorResponse {
     }
 }
 
+/// Machine-readable classification for a single operation's failure, as
+/// opposed to `ErrorKind` (which classifies whole-session-fatal failures like
+/// a save that can't be loaded at all). Carried by `OpError` so clients can
+/// branch on `code` instead of matching substrings of `message`.
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum OpErrorCode {
+    SectionNotFound,
+    EntryNotFound,
+    BadRequest,
+    ParseFailed,
+    BinaryTokenUnknown,
+    /// `sort_entries` was asked to order by a field that resolves to a
+    /// structured (object/array) value on at least one entry, instead of a
+    /// scalar it could actually compare.
+    FieldNotSortable,
+    /// The request line's `op` field didn't match any known operation.
+    UnknownOp,
+    /// The request line was missing a field its `op` requires.
+    MissingField,
+    /// A field in the request line was present but the wrong JSON type
+    /// (e.g. a string where a number was expected).
+    InvalidFieldValue,
+    /// A `filter`/`filter_entries` expression failed to parse.
+    BadFilter,
+    Internal,
+}
+
+/// A single operation's failure: the `Err` side of `run_multi_op`, and what
+/// the handful of single-request handlers whose op can fail (as opposed to
+/// just returning `found: false`) write out directly.
+#[derive(Debug, Clone, Serialize)]
+struct OpError {
+    code: OpErrorCode,
+    message: String,
+}
+
+impl OpError {
+    fn bad_request(message: impl Into<String>) -> Self {
+        Self {
+            code: OpErrorCode::BadRequest,
+            message: message.into(),
+        }
+    }
+
+    fn bad_filter(message: impl Into<String>) -> Self {
+        Self {
+            code: OpErrorCode::BadFilter,
+            message: message.into(),
+        }
+    }
+
+    /// Classify a failure to deserialize a request line into the op-level
+    /// taxonomy, so a malformed request gets a structured `{"ok": false,
+    /// "code", "message"}` response like any other op failure instead of the
+    /// ad hoc session-fatal `ErrorResponse` shape.
+    fn from_request_parse_error(err: &serde_json::Error) -> Self {
+        let text = err.to_string();
+        let code = if text.contains("unknown variant") {
+            OpErrorCode::UnknownOp
+        } else if text.contains("missing field") {
+            OpErrorCode::MissingField
+        } else if text.contains("invalid type") || text.contains("invalid value") {
+            OpErrorCode::InvalidFieldValue
+        } else {
+            OpErrorCode::BadRequest
+        };
+        Self {
+            code,
+            message: format!("Failed to parse request: {}", err),
+        }
+    }
+}
+
+/// Classify a `CompanionError` raised while servicing one operation (e.g.
+/// re-parsing an isolated entry) into the op-level taxonomy.
+impl From<CompanionError> for OpError {
+    fn from(err: CompanionError) -> Self {
+        let code = match &err {
+            CompanionError::ParseError { .. } => OpErrorCode::ParseFailed,
+            CompanionError::UnknownToken { .. } => OpErrorCode::BinaryTokenUnknown,
+            CompanionError::InvalidArgument { .. } => OpErrorCode::BadRequest,
+            _ => OpErrorCode::Internal,
+        };
+        Self {
+            code,
+            message: err.to_string(),
+        }
+    }
+}
+
+/// Write a tagged per-operation failure: `{"ok": false, "code", "message"}`,
+/// distinct from `ErrorResponse` (which reports a whole-session-fatal error
+/// and always carries an `exit_code`/`schema_version`/`tool_version`).
+fn write_op_error(error: &OpError) -> io::Result<()> {
+    write_response(&json!({
+        "ok": false,
+        "code": error.code,
+        "message": error.message,
+    }))
+}
+
 /// Parsed save data held in memory for the session
 struct ParsedSave {
     gamestate: HashMap<String, Value>,
-    gamestate_bytes: Vec<u8>, // Keep for token scanning with Aho-Corasick
+    gamestate_bytes: GamestateBytes, // Keep for token scanning with Aho-Corasick
     meta: Option<HashMap<String, Value>>,
+    /// Inverted index (lowercase term -> postings) built once at load time so
+    /// `search` can rank entries without rescanning `gamestate_bytes`.
+    search_index: HashMap<String, Vec<Posting>>,
+    /// BK-tree over `search_index`'s distinct terms, letting `search` tolerate
+    /// typos in query words instead of requiring an exact token match.
+    term_bk_tree: BkTree,
 }
 
 impl ParsedSave {
-    /// Load and parse a .sav file
-    fn load(path: &str) -> Result<Self> {
-        let (gamestate_bytes, meta_bytes) = crate::commands::extract::read_sav_file(path)?;
+    /// Load and parse a .sav file. `tokens_path` is only consulted for
+    /// binary (ironman) saves; text saves ignore it entirely.
+    ///
+    /// When `cache_enabled`, a hit in `cache_dir` (or the default temp-dir
+    /// cache when `cache_dir` is `None`) skips decoding and index-building
+    /// entirely; a miss falls through to a full parse and writes a fresh
+    /// cache entry for next time.
+    fn load(
+        path: &str,
+        tokens_path: Option<&str>,
+        cache_dir: Option<&str>,
+        cache_enabled: bool,
+    ) -> Result<Self> {
+        let (gamestate_bytes, meta_bytes) = crate::commands::extract::read_sav_file(path, false)?;
+        let cache_dir = cache_dir
+            .map(std::path::PathBuf::from)
+            .unwrap_or_else(default_cache_dir);
 
-        let gamestate: HashMap<String, Value> = from_windows1252_slice(&gamestate_bytes)
-            .with_context(|| "Failed to parse gamestate")?;
+        if cache_enabled {
+            if let Some(cached) =
+                Self::load_from_cache(path, &gamestate_bytes, meta_bytes.as_deref(), &cache_dir)
+            {
+                eprintln!("[serve] Cache hit for {path}, skipping re-parse");
+                return Ok(cached);
+            }
+        }
 
-        let meta = if let Some(meta_bytes) = meta_bytes {
-            Some(from_windows1252_slice(&meta_bytes).with_context(|| "Failed to parse meta file")?)
+        let is_binary = crate::binary::looks_like_binary(&gamestate_bytes)
+            || meta_bytes
+                .as_deref()
+                .is_some_and(crate::binary::looks_like_binary);
+        let resolver = if is_binary {
+            let tokens_path = tokens_path.ok_or(CompanionError::InvalidArgument {
+                detail: "binary (ironman) save detected; pass --tokens <file> to decode it"
+                    .to_string(),
+            })?;
+            Some(crate::binary::TokenLookup::load(tokens_path)?)
         } else {
             None
         };
 
+        let (gamestate, _decode_offset) = Self::decode_section(&gamestate_bytes, resolver.as_ref())?;
+
+        let meta = meta_bytes
+            .as_ref()
+            .map(|bytes| Self::decode_section(bytes, resolver.as_ref()).map(|(meta, _)| meta))
+            .transpose()?;
+
+        let search_index = build_search_index(&gamestate);
+        let term_bk_tree = build_term_bk_tree(&search_index);
+
+        if cache_enabled {
+            Self::write_cache(
+                path,
+                &gamestate_bytes,
+                meta_bytes.as_deref(),
+                &gamestate,
+                &meta,
+                &search_index,
+                &cache_dir,
+            );
+        }
+
         Ok(Self {
             gamestate,
             gamestate_bytes,
             meta,
+            search_index,
+            term_bk_tree,
+        })
+    }
+
+    /// Look up and validate a cache entry for `path`. Returns `None` on any
+    /// miss (no entry, schema/tool-version mismatch, or stale fingerprint)
+    /// so the caller always has a full-parse fallback; cache problems are
+    /// never fatal to starting the session.
+    fn load_from_cache(
+        path: &str,
+        gamestate_bytes: &[u8],
+        meta_bytes: Option<&[u8]>,
+        cache_dir: &std::path::Path,
+    ) -> Option<Self> {
+        let cache_path = cache_file_path(cache_dir, path);
+        let file = std::fs::File::open(&cache_path).ok()?;
+        let entry: CacheEntry = serde_json::from_reader(std::io::BufReader::new(file)).ok()?;
+
+        if entry.tool_version != TOOL_VERSION || entry.cache_schema_version != CACHE_SCHEMA_VERSION
+        {
+            return None;
+        }
+
+        let metadata = std::fs::metadata(path).ok()?;
+        let modified_unix_secs = metadata
+            .modified()
+            .ok()?
+            .duration_since(std::time::UNIX_EPOCH)
+            .ok()?
+            .as_secs();
+        if entry.file_len != metadata.len() || entry.modified_unix_secs != modified_unix_secs {
+            return None;
+        }
+
+        if entry.content_hash != content_fingerprint(gamestate_bytes, meta_bytes) {
+            return None;
+        }
+
+        let term_bk_tree = build_term_bk_tree(&entry.search_index);
+        Some(Self {
+            gamestate: entry.gamestate,
+            gamestate_bytes: GamestateBytes::Owned(gamestate_bytes.to_vec()),
+            meta: entry.meta,
+            search_index: entry.search_index,
+            term_bk_tree,
         })
     }
 
+    /// Write a fresh cache entry for `path` after a full parse. Best-effort:
+    /// any IO failure (unwritable cache dir, race with another process,
+    /// etc.) is swallowed since a missing cache just means the next launch
+    /// re-parses instead of crashing this one.
+    #[allow(clippy::too_many_arguments)]
+    fn write_cache(
+        path: &str,
+        gamestate_bytes: &[u8],
+        meta_bytes: Option<&[u8]>,
+        gamestate: &HashMap<String, Value>,
+        meta: &Option<HashMap<String, Value>>,
+        search_index: &HashMap<String, Vec<Posting>>,
+        cache_dir: &std::path::Path,
+    ) {
+        let Ok(metadata) = std::fs::metadata(path) else {
+            return;
+        };
+        let Ok(modified_unix_secs) = metadata
+            .modified()
+            .and_then(|m| {
+                m.duration_since(std::time::UNIX_EPOCH)
+                    .map(|d| d.as_secs())
+                    .map_err(|_| std::io::Error::from(std::io::ErrorKind::InvalidData))
+            })
+        else {
+            return;
+        };
+
+        let entry = CacheEntry {
+            tool_version: TOOL_VERSION.to_string(),
+            cache_schema_version: CACHE_SCHEMA_VERSION,
+            file_len: metadata.len(),
+            modified_unix_secs,
+            content_hash: content_fingerprint(gamestate_bytes, meta_bytes),
+            gamestate: gamestate.clone(),
+            meta: meta.clone(),
+            search_index: search_index.clone(),
+        };
+
+        if std::fs::create_dir_all(cache_dir).is_err() {
+            return;
+        }
+        let cache_path = cache_file_path(cache_dir, path);
+        let Ok(file) = std::fs::File::create(&cache_path) else {
+            return;
+        };
+        if serde_json::to_writer(std::io::BufWriter::new(file), &entry).is_ok() {
+            eprintln!("[serve] Wrote cache entry for {path}");
+        }
+    }
+
+    /// Decode a single gamestate/meta body, dispatching to the binary or
+    /// text decoder based on a leading-byte sniff.
+    fn decode_section(
+        bytes: &[u8],
+        resolver: Option<&crate::binary::TokenLookup>,
+    ) -> Result<(HashMap<String, Value>, Option<usize>)> {
+        if crate::binary::looks_like_binary(bytes) {
+            let resolver = resolver.expect("binary section requires a loaded token resolver");
+            Ok((crate::binary::decode_binary(bytes, resolver)?, None))
+        } else {
+            Ok(crate::output::decode_gamestate(bytes)?)
+        }
+    }
+
     /// Extract specific sections from the parsed data
     fn extract_sections(&self, sections: &[String]) -> Value {
         let mut result = Map::new();
@@ -265,10 +869,110 @@ impl ParsedSave {
     }
 }
 
-/// Write a JSON line to stdout (protocol output)
+/// Bumped whenever `CacheEntry`'s shape changes in a way that isn't already
+/// covered by `TOOL_VERSION` (e.g. a field added/removed independently of a
+/// crate version bump), so a stale on-disk cache is never deserialized into
+/// a `ParsedSave` it no longer matches.
+const CACHE_SCHEMA_VERSION: u32 = 1;
+
+/// On-disk representation of a cached `ParsedSave`, written after a fresh
+/// parse and read back on the next `serve` launch against the same save.
+/// `term_bk_tree` is deliberately excluded: it's cheap to rebuild from
+/// `search_index` and skipping it keeps the cache file smaller.
+///
+/// Stored as JSON rather than a non-self-describing binary format because
+/// `gamestate`/`meta` are `serde_json::Value`, whose `Deserialize` impl
+/// requires a self-describing format (it calls `deserialize_any`); formats
+/// like bincode reject that. JSON keeps the cache readable with existing
+/// tooling and is still far cheaper than re-tokenizing the Clausewitz text.
+#[derive(Serialize, Deserialize)]
+struct CacheEntry {
+    tool_version: String,
+    cache_schema_version: u32,
+    file_len: u64,
+    modified_unix_secs: u64,
+    content_hash: u64,
+    gamestate: HashMap<String, Value>,
+    meta: Option<HashMap<String, Value>>,
+    search_index: HashMap<String, Vec<Posting>>,
+}
+
+/// FNV-1a over raw bytes, used both to fingerprint cache content and to name
+/// cache files by save path. Chosen over `DefaultHasher` so cache keys stay
+/// stable across Rust toolchain upgrades (the standard hasher's algorithm is
+/// an implementation detail, not a stability guarantee).
+fn fnv1a_hash(bytes: &[u8]) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+    const PRIME: u64 = 0x100000001b3;
+    let mut hash = OFFSET_BASIS;
+    for &byte in bytes {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(PRIME);
+    }
+    hash
+}
+
+/// Default on-disk cache directory when `--cache-dir` isn't given: a fixed
+/// subdirectory of the OS temp dir, shared across `serve` launches so
+/// repeated sessions against the same save reuse one cache.
+fn default_cache_dir() -> std::path::PathBuf {
+    std::env::temp_dir().join("stellaris-companion-cache")
+}
+
+/// Cache file path for `path`, keyed by the save's own path rather than its
+/// content so a repeated launch against the same file always looks in the
+/// same place; content changes are caught by `CacheEntry`'s stored
+/// fingerprint instead of by the file name.
+fn cache_file_path(cache_dir: &std::path::Path, path: &str) -> std::path::PathBuf {
+    cache_dir.join(format!("{:016x}.cache.json", fnv1a_hash(path.as_bytes())))
+}
+
+/// Fingerprint of a save's content, taken over `gamestate_bytes`/
+/// `meta_bytes` rather than the raw file so it matches regardless of
+/// whether those bytes came from a plain-text or binary-decoded save.
+fn content_fingerprint(gamestate_bytes: &[u8], meta_bytes: Option<&[u8]>) -> u64 {
+    let mut hash = fnv1a_hash(gamestate_bytes);
+    if let Some(meta_bytes) = meta_bytes {
+        hash ^= fnv1a_hash(meta_bytes).rotate_left(1);
+    }
+    hash
+}
+
+/// The correlation id of the request currently being answered, if the
+/// client supplied one. Set by `with_request_id` before a handler runs and
+/// read by `write_response`/`write_error` so pooled handlers don't need an
+/// `id` parameter threaded through every call site. Thread-local because
+/// pooled ops run on worker threads, each answering a different request.
+thread_local! {
+    static CURRENT_REQUEST_ID: std::cell::RefCell<Option<Value>> = const { std::cell::RefCell::new(None) };
+}
+
+/// Serializes every stdout write so two threads can never interleave
+/// partial JSON lines; `write_response` and the hand-rolled stream writers
+/// below all acquire it before touching stdout.
+static STDOUT_LOCK: Mutex<()> = Mutex::new(());
+
+/// Run `f` with `id` installed as the correlation id for whichever thread
+/// ends up writing its response, then clear it again.
+fn with_request_id<T>(id: Option<Value>, f: impl FnOnce() -> T) -> T {
+    CURRENT_REQUEST_ID.with(|cell| *cell.borrow_mut() = id);
+    let result = f();
+    CURRENT_REQUEST_ID.with(|cell| *cell.borrow_mut() = None);
+    result
+}
+
+/// Write a JSON line to stdout (protocol output), echoing back the current
+/// request's correlation id (if any) so pipelining clients can match replies.
 fn write_response<T: Serialize>(response: &T) -> io::Result<()> {
+    let mut value = serde_json::to_value(response).unwrap_or(Value::Null);
+    let id = CURRENT_REQUEST_ID.with(|cell| cell.borrow().clone());
+    if let (Some(id), Value::Object(map)) = (id, &mut value) {
+        map.insert("id".to_string(), id);
+    }
+
+    let _guard = STDOUT_LOCK.lock().unwrap();
     let mut stdout = io::stdout().lock();
-    serde_json::to_writer(&mut stdout, response)?;
+    serde_json::to_writer(&mut stdout, &value)?;
     stdout.write_all(b"\n")?;
     stdout.flush()?;
     Ok(())
@@ -277,6 +981,7 @@ fn write_response<T: Serialize>(response: &T) -> io::Result<()> {
 /// Write a stream entry directly without cloning the value.
 /// This avoids expensive deep clones of large Value trees.
 fn write_stream_entry(key: &str, value: &Value) -> io::Result<()> {
+    let _guard = STDOUT_LOCK.lock().unwrap();
     let mut stdout = io::stdout().lock();
     // Write the JSON structure directly, serializing value in place
     write!(stdout, r#"{{"ok":true,"entry":{{"key":"{}","value":"#, key)?;
@@ -289,6 +994,7 @@ fn write_stream_entry(key: &str, value: &Value) -> io::Result<()> {
 /// Write a batch of stream entries directly without cloning.
 /// Each entry's value is serialized in place to avoid deep clones.
 fn write_stream_batch(entries: &[(&str, &Value)]) -> io::Result<()> {
+    let _guard = STDOUT_LOCK.lock().unwrap();
     let mut stdout = io::stdout().lock();
     // Build JSON: {"ok":true,"entries":[{"key":"...","value":...},...]}
     stdout.write_all(b"{\"ok\":true,\"entries\":[")?;
@@ -484,7 +1190,15 @@ fn handle_contains_tokens(gamestate_bytes: &[u8], tokens: Vec<String>) -> io::Re
     let mut matches: HashMap<String, bool> = tokens.iter().map(|t| (t.clone(), false)).collect();
 
     if !tokens.is_empty() {
-        let ac = AhoCorasick::new(&tokens).expect("Failed to build Aho-Corasick automaton");
+        let ac = match AhoCorasick::new(&tokens) {
+            Ok(ac) => ac,
+            Err(e) => {
+                return write_op_error(&OpError::bad_request(format!(
+                    "Failed to build token automaton: {}",
+                    e
+                )));
+            }
+        };
 
         for mat in ac.find_iter(gamestate_bytes) {
             let pattern_idx = mat.pattern().as_usize();
@@ -584,865 +1298,4469 @@ fn handle_contains_kv(parsed: &ParsedSave, pairs: Vec<(String, String)>) -> io::
     })
 }
 
-/// Handle get_country_summaries operation - return lightweight country projections
-fn handle_get_country_summaries(parsed: &ParsedSave, fields: Vec<String>) -> io::Result<()> {
-    let mut countries: Vec<Value> = Vec::new();
+/// Resolve a dot-notation field path (e.g. `"owner.faction"`) against a JSON
+/// object, stepping through nested objects one segment at a time.
+fn resolve_path<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    let mut current = value;
+    for part in path.split('.') {
+        current = current.as_object()?.get(part)?;
+    }
+    Some(current)
+}
 
-    // Get the country section from gamestate
-    if let Some(Value::Object(country_map)) = parsed.gamestate.get("country") {
-        for (country_id, country_data) in country_map {
-            let mut summary = Map::new();
-            summary.insert("id".to_string(), json!(country_id));
+/// Compare two JSON scalars for `query_entries` filtering/sorting: numbers
+/// compare numerically, everything else coerces to a string the same way
+/// `handle_contains_kv` does for strings/bools.
+fn compare_values(a: &Value, b: &Value) -> Option<std::cmp::Ordering> {
+    match (a, b) {
+        (Value::Number(x), Value::Number(y)) => x.as_f64()?.partial_cmp(&y.as_f64()?),
+        _ => scalar_to_string(a)?.partial_cmp(&scalar_to_string(b)?),
+    }
+}
 
-            // Extract only the requested fields
-            if let Value::Object(country_obj) = country_data {
-                for field in &fields {
-                    if let Some(value) = country_obj.get(field) {
-                        summary.insert(field.clone(), value.clone());
-                    }
-                }
-            }
+/// Coerce a JSON scalar to the same string representation `handle_contains_kv`
+/// uses for its key=value matching (`true`/`false` as `"yes"`/`"no"`).
+fn scalar_to_string(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Bool(b) => Some(if *b { "yes" } else { "no" }.to_string()),
+        Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
 
-            countries.push(Value::Object(summary));
+/// Evaluate a `query_entries` filter AST against a single entry.
+fn eval_filter(entry: &Value, node: &FilterNode) -> bool {
+    use std::cmp::Ordering;
+
+    match node {
+        FilterNode::And { and } => and.iter().all(|n| eval_filter(entry, n)),
+        FilterNode::Or { or } => or.iter().any(|n| eval_filter(entry, n)),
+        FilterNode::Not { not } => !eval_filter(entry, not),
+        FilterNode::Leaf { field, op, value } => {
+            let Some(actual) = resolve_path(entry, field) else {
+                return false;
+            };
+            let ordering = compare_values(actual, value);
+            match op {
+                FilterOp::Eq => ordering == Some(Ordering::Equal),
+                FilterOp::Ne => ordering != Some(Ordering::Equal),
+                FilterOp::Lt => ordering == Some(Ordering::Less),
+                FilterOp::Le => matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+                FilterOp::Gt => ordering == Some(Ordering::Greater),
+                FilterOp::Ge => matches!(ordering, Some(Ordering::Greater | Ordering::Equal)),
+            }
         }
     }
+}
 
-    write_response(&SuccessResponse {
-        ok: true,
-        data: ResponseData::CountrySummaries { countries },
-    })
+/// Project a single entry the same way `handle_get_entries` does: the full
+/// value under `_value` by default, or selected top-level fields when
+/// `fields` is given.
+fn project_entry(key: &str, entry_value: &Value, fields: &Option<Vec<String>>) -> Value {
+    if let Some(field_list) = fields {
+        if let Value::Object(entry_obj) = entry_value {
+            let mut projected_obj = Map::new();
+            projected_obj.insert("_key".to_string(), json!(key));
+            for field in field_list {
+                if let Some(field_value) = entry_obj.get(field) {
+                    projected_obj.insert(field.clone(), field_value.clone());
+                }
+            }
+            return Value::Object(projected_obj);
+        }
+    }
+    let mut obj = Map::new();
+    obj.insert("_key".to_string(), json!(key));
+    obj.insert("_value".to_string(), entry_value.clone());
+    Value::Object(obj)
 }
 
-/// Handle get_duplicate_values operation - extract all values for a field with duplicate keys
-///
-/// This is needed because jomini's JSON-style deserialization collapses duplicate keys,
-/// but Stellaris save files use duplicate keys for list-like structures (e.g., traits="x"
-/// appearing multiple times for a leader).
-///
-/// This function scans the raw gamestate bytes to find the entry and extracts all values
-/// for the specified field using byte-level parsing.
-fn handle_get_duplicate_values(
-    gamestate_bytes: &[u8],
-    section: String,
-    key: String,
-    field: String,
-) -> io::Result<()> {
-    // Strategy:
-    // 1. Find the section start (e.g., "leaders={")
-    // 2. Find the specific entry by key (e.g., "\n\t12345=")
-    // 3. Extract all values for the field (e.g., traits="value")
+/// Filter, stable-sort, limit, and project entries of a section. Shared by
+/// the single-request `query_entries` op and its `MultiOp` counterpart.
+fn query_entries(
+    parsed: &ParsedSave,
+    section: &str,
+    filter: &Option<FilterNode>,
+    sort: &[SortKey],
+    limit: Option<usize>,
+    fields: &Option<Vec<String>>,
+) -> Vec<Value> {
+    let mut matches: Vec<(&String, &Value)> = match parsed.gamestate.get(section) {
+        Some(Value::Object(map)) => map
+            .iter()
+            .filter(|entry| match filter {
+                Some(f) => eval_filter(entry.1, f),
+                None => true,
+            })
+            .collect(),
+        _ => Vec::new(),
+    };
 
-    let mut values: Vec<String> = Vec::new();
-    let mut found = false;
+    // Stable-sort by each key in reverse order, so the first key in `sort`
+    // ends up the primary ordering (each later sort_by only breaks ties left
+    // by the previous one, since Rust's sort_by is stable).
+    for key in sort.iter().rev() {
+        matches.sort_by(|a, b| {
+            let ordering = resolve_path(a.1, &key.field)
+                .zip(resolve_path(b.1, &key.field))
+                .and_then(|(x, y)| compare_values(x, y))
+                .unwrap_or(std::cmp::Ordering::Equal);
+            match key.dir {
+                SortDir::Asc => ordering,
+                SortDir::Desc => ordering.reverse(),
+            }
+        });
+    }
 
-    // Convert to string for searching (save files are Windows-1252 encoded, mostly ASCII-compatible)
-    let content = String::from_utf8_lossy(gamestate_bytes);
+    if let Some(limit) = limit {
+        matches.truncate(limit);
+    }
 
-    // Find section start: section={
-    let section_pattern = format!("\n{}=", section);
-    if let Some(section_start) = content.find(&section_pattern) {
-        // Find the opening brace
-        let section_content_start = match content[section_start..].find('{') {
-            Some(pos) => section_start + pos + 1,
-            None => {
-                return write_response(&SuccessResponse {
-                    ok: true,
-                    data: ResponseData::DuplicateValues {
-                        values,
-                        found: false,
-                    },
-                });
-            }
-        };
+    matches
+        .into_iter()
+        .map(|(key, entry_value)| project_entry(key, entry_value, fields))
+        .collect()
+}
 
-        // Look for the entry: \n\t<key>=
-        // Note: keys at top level of section are tab-indented once
-        let entry_patterns = [
-            format!("\n\t{}=\n\t{{", key), // Standard format with newline before brace
-            format!("\n\t{}={{", key),     // Compact format without newline
-            format!("\n\t{} =", key),      // With space before equals
-        ];
+/// Evaluate a flat, AND-combined `query` filter list against a single entry.
+fn eval_query_filters(entry: &Value, filters: &[QueryFilter]) -> bool {
+    filters.iter().all(|f| eval_query_filter(entry, f))
+}
 
-        let mut entry_start: Option<usize> = None;
-        for pattern in &entry_patterns {
-            if let Some(pos) = content[section_content_start..].find(pattern) {
-                entry_start = Some(section_content_start + pos);
-                break;
+/// Evaluate a single `query` filter against an entry. `exists` only checks
+/// presence of the field; every other op requires the field to be present.
+fn eval_query_filter(entry: &Value, filter: &QueryFilter) -> bool {
+    use std::cmp::Ordering;
+
+    let actual = resolve_path(entry, &filter.field);
+    if filter.op == QueryFilterOp::Exists {
+        return actual.is_some();
+    }
+    let Some(actual) = actual else {
+        return false;
+    };
+
+    match filter.op {
+        QueryFilterOp::Contains => match actual {
+            Value::Array(items) => items
+                .iter()
+                .any(|item| compare_values(item, &filter.value) == Some(Ordering::Equal)),
+            Value::String(s) => filter
+                .value
+                .as_str()
+                .is_some_and(|needle| s.contains(needle)),
+            _ => false,
+        },
+        _ => {
+            let ordering = compare_values(actual, &filter.value);
+            match filter.op {
+                QueryFilterOp::Eq => ordering == Some(Ordering::Equal),
+                QueryFilterOp::Ne => ordering != Some(Ordering::Equal),
+                QueryFilterOp::Gt => ordering == Some(Ordering::Greater),
+                QueryFilterOp::Gte => matches!(ordering, Some(Ordering::Greater | Ordering::Equal)),
+                QueryFilterOp::Lt => ordering == Some(Ordering::Less),
+                QueryFilterOp::Lte => matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+                QueryFilterOp::Contains | QueryFilterOp::Exists => unreachable!(),
             }
         }
+    }
+}
 
-        if let Some(start) = entry_start {
-            found = true;
-
-            // Find the entry's content by counting braces
-            let entry_content = &content[start..];
-            let mut brace_count = 0;
-            let mut entry_end = entry_content.len();
-            let mut in_entry = false;
+/// Filter, sort, page, and project entries of a section for the `query` op.
+/// Shared by the single-request `query` op and its `MultiOp` counterpart.
+fn query_page(
+    parsed: &ParsedSave,
+    section: &str,
+    filters: &[QueryFilter],
+    sort: &Option<SortKey>,
+    offset: usize,
+    limit: Option<usize>,
+    fields: &Option<Vec<String>>,
+) -> (Vec<Value>, usize, Option<usize>) {
+    let mut matches: Vec<(&String, &Value)> = match parsed.gamestate.get(section) {
+        Some(Value::Object(map)) => map
+            .iter()
+            .filter(|entry| eval_query_filters(entry.1, filters))
+            .collect(),
+        _ => Vec::new(),
+    };
 
-            for (i, ch) in entry_content.chars().enumerate() {
-                if ch == '{' {
-                    brace_count += 1;
-                    in_entry = true;
-                } else if ch == '}' {
-                    brace_count -= 1;
-                    if in_entry && brace_count == 0 {
-                        entry_end = i + 1;
-                        break;
-                    }
-                }
+    if let Some(key) = sort {
+        matches.sort_by(|a, b| {
+            let ordering = resolve_path(a.1, &key.field)
+                .zip(resolve_path(b.1, &key.field))
+                .and_then(|(x, y)| compare_values(x, y))
+                .unwrap_or(std::cmp::Ordering::Equal);
+            match key.dir {
+                SortDir::Asc => ordering,
+                SortDir::Desc => ordering.reverse(),
             }
+        });
+    }
 
-            let entry_block = &entry_content[..entry_end];
+    let total = matches.len();
+    let page: Vec<(&String, &Value)> = matches
+        .into_iter()
+        .skip(offset)
+        .take(limit.unwrap_or(usize::MAX))
+        .collect();
+    let next_offset = (offset + page.len() < total).then(|| offset + page.len());
 
-            // Extract all values for the field: field="value"
-            // Pattern: field="<value>"
-            let field_pattern = format!("{}=\"", field);
-            let mut search_pos = 0;
+    let entries = page
+        .into_iter()
+        .map(|(key, entry_value)| project_entry(key, entry_value, fields))
+        .collect();
 
-            while let Some(field_start) = entry_block[search_pos..].find(&field_pattern) {
-                let value_start = search_pos + field_start + field_pattern.len();
-                if let Some(value_end) = entry_block[value_start..].find('"') {
-                    let value = &entry_block[value_start..value_start + value_end];
-                    values.push(value.to_string());
-                    search_pos = value_start + value_end + 1;
-                } else {
-                    break;
-                }
-            }
-        }
-    }
+    (entries, total, next_offset)
+}
 
+/// Handle query operation - server-side predicate filter/sort/page/project
+/// over a section, returning `total`/`next_offset` so callers can page
+/// through large sections (e.g. every fleet) without pulling them all at once.
+fn handle_query(
+    parsed: &ParsedSave,
+    section: String,
+    filters: Vec<QueryFilter>,
+    sort: Option<SortKey>,
+    offset: usize,
+    limit: Option<usize>,
+    fields: Option<Vec<String>>,
+) -> io::Result<()> {
+    let (entries, total, next_offset) =
+        query_page(parsed, &section, &filters, &sort, offset, limit, &fields);
     write_response(&SuccessResponse {
         ok: true,
-        data: ResponseData::DuplicateValues { values, found },
+        data: ResponseData::QueryPage {
+            entries,
+            total,
+            next_offset,
+        },
     })
 }
 
-/// Helper to extract duplicate values from raw bytes with optional cached section offset.
-/// Returns (values, found, section_end_for_caching).
-fn extract_duplicate_values(
-    content: &str,
-    section: &str,
-    key: &str,
-    field: &str,
-    cached_section_start: Option<usize>,
-) -> (Vec<String>, bool, Option<usize>) {
-    let mut values: Vec<String> = Vec::new();
+/// One step of a parsed `json_path` expression; see `parse_json_path`.
+#[derive(Debug, PartialEq)]
+enum PathStep {
+    /// `.key`
+    Child(String),
+    /// `.*` or `[*]`
+    Wildcard,
+    /// `[n]`
+    Index(usize),
+    /// `..key`
+    RecursiveDescent(String),
+    /// `[?(@.field OP value)]` or `[?(@ OP value)]` (empty field)
+    Filter {
+        field: String,
+        op: JsonPathFilterOp,
+        value: Value,
+    },
+}
 
-    // Find section start (use cache if available)
-    let section_content_start = if let Some(start) = cached_section_start {
-        start
-    } else {
-        let section_pattern = format!("\n{}=", section);
-        if let Some(section_start) = content.find(&section_pattern) {
-            match content[section_start..].find('{') {
-                Some(pos) => section_start + pos + 1,
-                None => return (values, false, None),
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum JsonPathFilterOp {
+    Eq,
+    Ne,
+    Gt,
+    Gte,
+    Lt,
+    Lte,
+}
+
+/// Parse a JSONPath string (`$.country[*].name`, `$..fleet`, ...) into a
+/// sequence of selector steps. Supports the subset of JSONPath this crate's
+/// tree actually needs: root `$`, child access, wildcard, array index,
+/// recursive descent, and `[?(@.field OP value)]` filter predicates.
+fn parse_json_path(path: &str) -> std::result::Result<Vec<PathStep>, String> {
+    let trimmed = path.trim();
+    if !trimmed.starts_with('$') {
+        return Err("JSONPath must start with `$`".to_string());
+    }
+    let chars: Vec<char> = trimmed.chars().collect();
+    let mut i = 1; // skip '$'
+    let mut steps = Vec::new();
+
+    while i < chars.len() {
+        if chars[i] == '.' && chars.get(i + 1) == Some(&'.') {
+            i += 2;
+            steps.push(PathStep::RecursiveDescent(read_json_path_ident(
+                &chars, &mut i,
+            )?));
+        } else if chars[i] == '.' {
+            i += 1;
+            if chars.get(i) == Some(&'*') {
+                i += 1;
+                steps.push(PathStep::Wildcard);
+            } else {
+                steps.push(PathStep::Child(read_json_path_ident(&chars, &mut i)?));
             }
+        } else if chars[i] == '[' {
+            let close = chars[i..]
+                .iter()
+                .position(|&c| c == ']')
+                .map(|offset| i + offset)
+                .ok_or_else(|| "unterminated `[` in JSONPath".to_string())?;
+            let inner: String = chars[i + 1..close].iter().collect();
+            steps.push(parse_json_path_bracket(&inner)?);
+            i = close + 1;
         } else {
-            return (values, false, None);
+            return Err(format!("unexpected character `{}` in JSONPath", chars[i]));
         }
-    };
+    }
 
-    // Look for the entry: \n\t<key>=
-    let entry_patterns = [
-        format!("\n\t{}=\n\t{{", key),
-        format!("\n\t{}={{", key),
-        format!("\n\t{} =", key),
+    Ok(steps)
+}
+
+fn read_json_path_ident(chars: &[char], i: &mut usize) -> std::result::Result<String, String> {
+    let start = *i;
+    while *i < chars.len() && (chars[*i].is_alphanumeric() || chars[*i] == '_') {
+        *i += 1;
+    }
+    if *i == start {
+        return Err("expected an identifier in JSONPath".to_string());
+    }
+    Ok(chars[start..*i].iter().collect())
+}
+
+fn parse_json_path_bracket(inner: &str) -> std::result::Result<PathStep, String> {
+    let inner = inner.trim();
+    if inner == "*" {
+        return Ok(PathStep::Wildcard);
+    }
+    if let Ok(index) = inner.parse::<usize>() {
+        return Ok(PathStep::Index(index));
+    }
+    if let Some(predicate) = inner.strip_prefix("?(").and_then(|s| s.strip_suffix(')')) {
+        return parse_json_path_filter(predicate.trim());
+    }
+    Err(format!("unsupported `[{}]` selector in JSONPath", inner))
+}
+
+/// Parse a `@.field OP value` (or bare `@ OP value`) filter predicate body.
+fn parse_json_path_filter(predicate: &str) -> std::result::Result<PathStep, String> {
+    let rest = predicate
+        .strip_prefix('@')
+        .ok_or_else(|| "filter predicates must reference `@`".to_string())?;
+    let rest = rest.strip_prefix('.').unwrap_or(rest);
+
+    const OPS: &[(&str, JsonPathFilterOp)] = &[
+        ("==", JsonPathFilterOp::Eq),
+        ("!=", JsonPathFilterOp::Ne),
+        (">=", JsonPathFilterOp::Gte),
+        ("<=", JsonPathFilterOp::Lte),
+        (">", JsonPathFilterOp::Gt),
+        ("<", JsonPathFilterOp::Lt),
     ];
+    let (field, op, value_text) = OPS
+        .iter()
+        .find_map(|(token, op)| rest.split_once(token).map(|(f, v)| (f, *op, v)))
+        .ok_or_else(|| format!("unsupported filter operator in `{}`", predicate))?;
 
-    let mut entry_start: Option<usize> = None;
-    for pattern in &entry_patterns {
-        if let Some(pos) = content[section_content_start..].find(pattern) {
-            entry_start = Some(section_content_start + pos);
-            break;
+    Ok(PathStep::Filter {
+        field: field.trim().to_string(),
+        op,
+        value: parse_json_path_literal(value_text.trim()),
+    })
+}
+
+/// Parse a filter predicate's comparison literal: a quoted string, `true`/
+/// `false`, a number, or (as a fallback) the bare text as a string.
+fn parse_json_path_literal(text: &str) -> Value {
+    let quoted = text
+        .strip_prefix('"')
+        .and_then(|s| s.strip_suffix('"'))
+        .or_else(|| text.strip_prefix('\'').and_then(|s| s.strip_suffix('\'')));
+    if let Some(quoted) = quoted {
+        return json!(quoted);
+    }
+    match text {
+        "true" => json!(true),
+        "false" => json!(false),
+        _ => text
+            .parse::<f64>()
+            .map(|n| json!(n))
+            .unwrap_or_else(|_| json!(text)),
+    }
+}
+
+/// Every descendant of `node` (its own fields/elements, recursively, plus
+/// `node` itself), for `..key` recursive descent: JSONPath's `$..key`
+/// matches `key` at any depth, including directly on the node it starts from.
+fn collect_json_path_descendants(node: &Value) -> Vec<&Value> {
+    let mut out = vec![node];
+    let mut stack = vec![node];
+    while let Some(current) = stack.pop() {
+        match current {
+            Value::Object(map) => {
+                for child in map.values() {
+                    out.push(child);
+                    stack.push(child);
+                }
+            }
+            Value::Array(arr) => {
+                for child in arr {
+                    out.push(child);
+                    stack.push(child);
+                }
+            }
+            _ => {}
         }
     }
+    out
+}
 
-    let Some(start) = entry_start else {
-        return (values, false, Some(section_content_start));
+fn eval_json_path_filter(node: &Value, field: &str, op: JsonPathFilterOp, value: &Value) -> bool {
+    use std::cmp::Ordering;
+
+    let actual = if field.is_empty() {
+        Some(node)
+    } else {
+        resolve_path(node, field)
     };
+    let Some(actual) = actual else {
+        return false;
+    };
+    let ordering = compare_values(actual, value);
+    match op {
+        JsonPathFilterOp::Eq => ordering == Some(Ordering::Equal),
+        JsonPathFilterOp::Ne => ordering != Some(Ordering::Equal),
+        JsonPathFilterOp::Gt => ordering == Some(Ordering::Greater),
+        JsonPathFilterOp::Gte => matches!(ordering, Some(Ordering::Greater | Ordering::Equal)),
+        JsonPathFilterOp::Lt => ordering == Some(Ordering::Less),
+        JsonPathFilterOp::Lte => matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+    }
+}
 
-    // Find the entry's content by counting braces
-    let entry_content = &content[start..];
-    let mut brace_count = 0;
-    let mut entry_end = entry_content.len();
-    let mut in_entry = false;
-
-    for (i, ch) in entry_content.chars().enumerate() {
-        if ch == '{' {
-            brace_count += 1;
-            in_entry = true;
-        } else if ch == '}' {
-            brace_count -= 1;
-            if in_entry && brace_count == 0 {
-                entry_end = i + 1;
-                break;
-            }
+/// Fold one JSONPath step over the current working node-set, producing the
+/// next one. Recursive descent and filter steps can both turn one node into
+/// several (or zero), the same way each step of a dot-notation path narrows
+/// `resolve_path`'s single current node.
+fn apply_json_path_step<'a>(nodes: Vec<&'a Value>, step: &PathStep) -> Vec<&'a Value> {
+    match step {
+        PathStep::Child(name) => nodes
+            .into_iter()
+            .filter_map(|node| node.as_object()?.get(name))
+            .collect(),
+        PathStep::Wildcard => nodes
+            .into_iter()
+            .flat_map(|node| -> Box<dyn Iterator<Item = &'a Value>> {
+                match node {
+                    Value::Object(map) => Box::new(map.values()),
+                    Value::Array(arr) => Box::new(arr.iter()),
+                    _ => Box::new(std::iter::empty()),
+                }
+            })
+            .collect(),
+        PathStep::Index(index) => nodes
+            .into_iter()
+            .filter_map(|node| node.as_array()?.get(*index))
+            .collect(),
+        PathStep::RecursiveDescent(name) => nodes
+            .into_iter()
+            .flat_map(collect_json_path_descendants)
+            .filter_map(|node| node.as_object()?.get(name))
+            .collect(),
+        PathStep::Filter { field, op, value } => nodes
+            .into_iter()
+            .flat_map(|node| -> Box<dyn Iterator<Item = &'a Value>> {
+                match node {
+                    Value::Array(arr) => Box::new(arr.iter()),
+                    _ => Box::new(std::iter::once(node)),
+                }
+            })
+            .filter(|candidate| eval_json_path_filter(candidate, field, *op, value))
+            .collect(),
+    }
+}
+
+/// Evaluate a JSONPath expression against the parsed gamestate tree,
+/// returning every matching node. `$` itself is the gamestate map (the same
+/// root `get_entry`/`extract_sections` index into), so the first step after
+/// it must be a child/wildcard/recursive-descent selector, not an index or
+/// filter (those only make sense once a step has produced an array).
+fn eval_json_path(
+    gamestate: &HashMap<String, Value>,
+    path: &str,
+) -> std::result::Result<Vec<Value>, String> {
+    let mut steps = parse_json_path(path)?.into_iter();
+
+    let mut nodes: Vec<&Value> = match steps.next() {
+        None => return Ok(vec![json!(gamestate)]),
+        Some(PathStep::Child(name)) => gamestate.get(&name).into_iter().collect(),
+        Some(PathStep::Wildcard) => gamestate.values().collect(),
+        Some(PathStep::RecursiveDescent(name)) => gamestate
+            .values()
+            .flat_map(collect_json_path_descendants)
+            .filter_map(|node| node.as_object().and_then(|m| m.get(&name)))
+            .collect(),
+        Some(PathStep::Index(_)) | Some(PathStep::Filter { .. }) => {
+            return Err(
+                "the first JSONPath step after `$` must be `.key`, `.*`, `[*]`, or `..key`"
+                    .to_string(),
+            );
         }
+    };
+
+    for step in steps {
+        nodes = apply_json_path_step(nodes, &step);
+    }
+
+    Ok(nodes.into_iter().cloned().collect())
+}
+
+/// Handle the `json_path` operation.
+fn handle_json_path(parsed: &ParsedSave, path: String) -> io::Result<()> {
+    match eval_json_path(&parsed.gamestate, &path) {
+        Ok(matches) => write_response(&SuccessResponse {
+            ok: true,
+            data: ResponseData::JsonPathResults { matches },
+        }),
+        Err(message) => write_op_error(&OpError::bad_request(message)),
     }
+}
 
-    let entry_block = &entry_content[..entry_end];
+/// Comparison operator for a `filter_entries` leaf condition.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FilterEntriesOp {
+    Eq,
+    Ne,
+    Gt,
+    Gte,
+    Lt,
+    Lte,
+}
 
-    // Extract all values for the field: field="value"
-    let field_pattern = format!("{}=\"", field);
-    let mut search_pos = 0;
+/// A token of a `filter_entries` expression string, produced by
+/// `tokenize_filter_expr`. `Word` covers both field names and bare (i.e.
+/// unquoted) values - the parser decides which based on grammar position.
+#[derive(Debug, Clone, PartialEq)]
+enum FilterExprToken {
+    LParen,
+    RParen,
+    And,
+    Or,
+    Not,
+    To,
+    Op(FilterEntriesOp),
+    Word { text: String, quoted: bool },
+}
 
-    while let Some(field_start) = entry_block[search_pos..].find(&field_pattern) {
-        let value_start = search_pos + field_start + field_pattern.len();
-        if let Some(value_end) = entry_block[value_start..].find('"') {
-            let value = &entry_block[value_start..value_start + value_end];
-            values.push(value.to_string());
-            search_pos = value_start + value_end + 1;
-        } else {
-            break;
+/// Split a `filter_entries` expression string into tokens. Keywords
+/// (`AND`/`OR`/`NOT`/`TO`) are matched case-insensitively; everything else
+/// unquoted is a bare `Word`, and `"..."` is a quoted `Word` (so a value like
+/// `"10"` stays a string instead of being parsed as a number).
+fn tokenize_filter_expr(input: &str) -> std::result::Result<Vec<FilterExprToken>, String> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut i = 0;
+    let mut tokens = Vec::new();
+
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+        match c {
+            '(' => {
+                tokens.push(FilterExprToken::LParen);
+                i += 1;
+            }
+            ')' => {
+                tokens.push(FilterExprToken::RParen);
+                i += 1;
+            }
+            '"' => {
+                i += 1;
+                let start = i;
+                while i < chars.len() && chars[i] != '"' {
+                    i += 1;
+                }
+                if i >= chars.len() {
+                    return Err("unterminated string literal in filter expression".to_string());
+                }
+                let text: String = chars[start..i].iter().collect();
+                i += 1;
+                tokens.push(FilterExprToken::Word { text, quoted: true });
+            }
+            '!' if chars.get(i + 1) == Some(&'=') => {
+                tokens.push(FilterExprToken::Op(FilterEntriesOp::Ne));
+                i += 2;
+            }
+            '>' if chars.get(i + 1) == Some(&'=') => {
+                tokens.push(FilterExprToken::Op(FilterEntriesOp::Gte));
+                i += 2;
+            }
+            '<' if chars.get(i + 1) == Some(&'=') => {
+                tokens.push(FilterExprToken::Op(FilterEntriesOp::Lte));
+                i += 2;
+            }
+            '=' => {
+                tokens.push(FilterExprToken::Op(FilterEntriesOp::Eq));
+                i += 1;
+            }
+            '>' => {
+                tokens.push(FilterExprToken::Op(FilterEntriesOp::Gt));
+                i += 1;
+            }
+            '<' => {
+                tokens.push(FilterExprToken::Op(FilterEntriesOp::Lt));
+                i += 1;
+            }
+            _ => {
+                let start = i;
+                while i < chars.len()
+                    && !chars[i].is_whitespace()
+                    && !matches!(chars[i], '(' | ')' | '=' | '!' | '<' | '>' | '"')
+                {
+                    i += 1;
+                }
+                if i == start {
+                    return Err(format!(
+                        "unexpected character `{}` in filter expression",
+                        c
+                    ));
+                }
+                let word: String = chars[start..i].iter().collect();
+                match word.to_uppercase().as_str() {
+                    "AND" => tokens.push(FilterExprToken::And),
+                    "OR" => tokens.push(FilterExprToken::Or),
+                    "NOT" => tokens.push(FilterExprToken::Not),
+                    "TO" => tokens.push(FilterExprToken::To),
+                    _ => tokens.push(FilterExprToken::Word {
+                        text: word,
+                        quoted: false,
+                    }),
+                }
+            }
         }
     }
 
-    (values, true, Some(section_content_start))
+    Ok(tokens)
 }
 
-/// Handle get_entry_text operation - extract raw Clausewitz text for a single entry
-///
-/// This is needed for cases where Python needs to parse duplicate keys (like relation={})
-/// that can't be represented in JSON. Instead of searching the entire gamestate in Python,
-/// this returns just the entry's raw text for targeted regex parsing.
-fn handle_get_entry_text(gamestate_bytes: &[u8], section: String, key: String) -> io::Result<()> {
-    let content = String::from_utf8_lossy(gamestate_bytes);
-    let (text, found) = extract_entry_text(&content, &section, &key, None);
+/// Coerce a bare (unquoted) `filter_entries` value token the same way
+/// `scalar_to_string` coerces bools for comparison: `yes`/`no` become JSON
+/// booleans, anything that parses as a number becomes a JSON number, and
+/// everything else stays a string. Quoted tokens skip all of this.
+fn parse_filter_entries_value(text: &str, quoted: bool) -> Value {
+    if quoted {
+        return json!(text);
+    }
+    match text {
+        "yes" => json!(true),
+        "no" => json!(false),
+        _ => text
+            .parse::<f64>()
+            .map(|n| json!(n))
+            .unwrap_or_else(|_| json!(text)),
+    }
+}
 
-    write_response(&SuccessResponse {
-        ok: true,
-        data: ResponseData::EntryText { text, found },
-    })
+/// AST node for a parsed `filter_entries` expression (see `parse_filter_expr`).
+#[derive(Debug, PartialEq)]
+enum FilterExprNode {
+    And(Box<FilterExprNode>, Box<FilterExprNode>),
+    Or(Box<FilterExprNode>, Box<FilterExprNode>),
+    Not(Box<FilterExprNode>),
+    Condition {
+        field: String,
+        op: FilterEntriesOp,
+        value: Value,
+    },
+    /// `field lo TO hi`: matches when `lo <= field <= hi`.
+    Range {
+        field: String,
+        low: Value,
+        high: Value,
+    },
 }
 
-/// Helper to extract raw entry text with optional cached section offset.
-/// Returns (text, found, section_end_for_caching).
-fn extract_entry_text(
-    content: &str,
-    section: &str,
-    key: &str,
-    cached_section_start: Option<usize>,
-) -> (String, bool) {
-    // Find section start (use cache if available)
-    let section_content_start = if let Some(start) = cached_section_start {
-        start
-    } else {
-        let section_pattern = format!("\n{}=", section);
-        if let Some(section_start) = content.find(&section_pattern) {
-            match content[section_start..].find('{') {
-                Some(pos) => section_start + pos + 1,
-                None => return (String::new(), false),
-            }
-        } else {
-            return (String::new(), false);
+/// Recursive-descent parser for `filter_entries` expressions. AND binds
+/// tighter than OR (`a AND b OR c` is `(a AND b) OR c`); NOT and `(...)`
+/// both bind tighter still, same as a conventional boolean expression
+/// language.
+struct FilterExprParser<'a> {
+    tokens: &'a [FilterExprToken],
+    pos: usize,
+}
+
+impl<'a> FilterExprParser<'a> {
+    fn parse(tokens: &'a [FilterExprToken]) -> std::result::Result<FilterExprNode, String> {
+        let mut parser = Self { tokens, pos: 0 };
+        let node = parser.parse_or()?;
+        if parser.pos != parser.tokens.len() {
+            return Err("unexpected trailing tokens in filter expression".to_string());
         }
-    };
+        Ok(node)
+    }
 
-    // Look for the entry: \n\t<key>=
-    let entry_patterns = [
-        format!("\n\t{}=\n\t{{", key),
-        format!("\n\t{}={{", key),
-        format!("\n\t{} =", key),
-    ];
+    fn peek(&self) -> Option<&FilterExprToken> {
+        self.tokens.get(self.pos)
+    }
 
-    let mut entry_start: Option<usize> = None;
-    for pattern in &entry_patterns {
-        if let Some(pos) = content[section_content_start..].find(pattern) {
-            entry_start = Some(section_content_start + pos);
-            break;
+    fn advance(&mut self) -> Option<&FilterExprToken> {
+        let token = self.tokens.get(self.pos);
+        if token.is_some() {
+            self.pos += 1;
         }
+        token
     }
 
-    let Some(start) = entry_start else {
-        return (String::new(), false);
-    };
+    fn parse_or(&mut self) -> std::result::Result<FilterExprNode, String> {
+        let mut node = self.parse_and()?;
+        while matches!(self.peek(), Some(FilterExprToken::Or)) {
+            self.pos += 1;
+            let rhs = self.parse_and()?;
+            node = FilterExprNode::Or(Box::new(node), Box::new(rhs));
+        }
+        Ok(node)
+    }
 
-    // Find the entry's content by counting braces
-    let entry_content = &content[start..];
-    let mut brace_count = 0;
-    let mut entry_end = entry_content.len();
-    let mut in_entry = false;
-
-    for (i, ch) in entry_content.chars().enumerate() {
-        if ch == '{' {
-            brace_count += 1;
-            in_entry = true;
-        } else if ch == '}' {
-            brace_count -= 1;
-            if in_entry && brace_count == 0 {
-                entry_end = i + 1;
-                break;
-            }
+    fn parse_and(&mut self) -> std::result::Result<FilterExprNode, String> {
+        let mut node = self.parse_unary()?;
+        while matches!(self.peek(), Some(FilterExprToken::And)) {
+            self.pos += 1;
+            let rhs = self.parse_unary()?;
+            node = FilterExprNode::And(Box::new(node), Box::new(rhs));
         }
+        Ok(node)
     }
 
-    let entry_block = &entry_content[..entry_end];
-    (entry_block.to_string(), true)
-}
+    fn parse_unary(&mut self) -> std::result::Result<FilterExprNode, String> {
+        if matches!(self.peek(), Some(FilterExprToken::Not)) {
+            self.pos += 1;
+            return Ok(FilterExprNode::Not(Box::new(self.parse_unary()?)));
+        }
+        self.parse_atom()
+    }
 
-/// Handle multi-op batch request - execute multiple operations in one request
-/// to reduce IPC round-trip overhead.
-///
-/// Returns results in the same order as the input operations.
-fn handle_multi_op(parsed: &ParsedSave, ops: Vec<MultiOp>) -> io::Result<()> {
-    let mut results: Vec<Value> = Vec::with_capacity(ops.len());
+    fn parse_atom(&mut self) -> std::result::Result<FilterExprNode, String> {
+        if matches!(self.peek(), Some(FilterExprToken::LParen)) {
+            self.pos += 1;
+            let node = self.parse_or()?;
+            return match self.advance() {
+                Some(FilterExprToken::RParen) => Ok(node),
+                other => Err(format!("expected `)` in filter expression, found {:?}", other)),
+            };
+        }
+        self.parse_condition()
+    }
 
-    // Cache for section offsets in get_duplicate_values (section_name -> content_start_offset)
-    let mut section_offset_cache: HashMap<String, usize> = HashMap::new();
-    let content = String::from_utf8_lossy(&parsed.gamestate_bytes);
+    fn parse_condition(&mut self) -> std::result::Result<FilterExprNode, String> {
+        let field = match self.advance() {
+            Some(FilterExprToken::Word { text, .. }) => text.clone(),
+            other => return Err(format!("expected a field name, found {:?}", other)),
+        };
 
-    for op in ops {
-        let result = match op {
-            MultiOp::ExtractSections { sections } => {
-                let data = parsed.extract_sections(&sections);
-                json!({ "data": data })
+        match self.advance() {
+            Some(FilterExprToken::Op(op)) => {
+                let op = *op;
+                let value = self.parse_value()?;
+                Ok(FilterExprNode::Condition { field, op, value })
             }
-            MultiOp::GetEntry { section, key } => {
-                if let Some(Value::Object(map)) = parsed.gamestate.get(&section) {
-                    if let Some(entry_value) = map.get(&key) {
-                        json!({ "entry": entry_value, "found": true })
-                    } else {
-                        json!({ "entry": Value::Null, "found": false })
+            Some(FilterExprToken::Word { text, quoted }) => {
+                let low = parse_filter_entries_value(text, *quoted);
+                match self.advance() {
+                    Some(FilterExprToken::To) => {}
+                    other => {
+                        return Err(format!(
+                            "expected `TO` in range filter, found {:?}",
+                            other
+                        ))
                     }
-                } else {
-                    json!({ "entry": Value::Null, "found": false })
                 }
+                let high = self.parse_value()?;
+                Ok(FilterExprNode::Range { field, low, high })
             }
-            MultiOp::GetEntries {
-                section,
-                keys,
-                fields,
-            } => {
-                let mut entries: Vec<Value> = Vec::new();
-                if let Some(Value::Object(map)) = parsed.gamestate.get(&section) {
-                    for key in &keys {
-                        if let Some(entry_value) = map.get(key) {
-                            let projected = if let Some(ref field_list) = fields {
-                                if let Value::Object(entry_obj) = entry_value {
-                                    let mut projected_obj = Map::new();
-                                    projected_obj.insert("_key".to_string(), json!(key));
-                                    for field in field_list {
-                                        if let Some(field_value) = entry_obj.get(field) {
-                                            projected_obj
-                                                .insert(field.clone(), field_value.clone());
-                                        }
-                                    }
-                                    Value::Object(projected_obj)
-                                } else {
-                                    let mut obj = Map::new();
-                                    obj.insert("_key".to_string(), json!(key));
-                                    obj.insert("_value".to_string(), entry_value.clone());
-                                    Value::Object(obj)
-                                }
-                            } else {
-                                let mut obj = Map::new();
-                                obj.insert("_key".to_string(), json!(key));
-                                obj.insert("_value".to_string(), entry_value.clone());
-                                Value::Object(obj)
-                            };
-                            entries.push(projected);
-                        }
-                    }
-                }
-                json!({ "entries": entries })
+            other => Err(format!(
+                "expected an operator or a range value after field, found {:?}",
+                other
+            )),
+        }
+    }
+
+    fn parse_value(&mut self) -> std::result::Result<Value, String> {
+        match self.advance() {
+            Some(FilterExprToken::Word { text, quoted }) => {
+                Ok(parse_filter_entries_value(text, *quoted))
             }
-            MultiOp::CountKeys { keys } => {
-                use std::collections::HashSet;
-                let key_set: HashSet<&str> = keys.iter().map(|s| s.as_str()).collect();
-                let mut counts: HashMap<String, usize> =
-                    keys.iter().map(|k| (k.clone(), 0)).collect();
+            other => Err(format!("expected a value, found {:?}", other)),
+        }
+    }
+}
 
-                fn traverse(
-                    value: &Value,
-                    key_set: &HashSet<&str>,
-                    counts: &mut HashMap<String, usize>,
-                ) {
-                    match value {
-                        Value::Object(map) => {
-                            for (k, v) in map {
-                                if key_set.contains(k.as_str()) {
-                                    *counts.get_mut(k.as_str()).unwrap() += 1;
-                                }
-                                traverse(v, key_set, counts);
-                            }
-                        }
-                        Value::Array(arr) => {
-                            for v in arr {
-                                traverse(v, key_set, counts);
-                            }
-                        }
-                        _ => {}
-                    }
-                }
+/// Parse a `filter_entries` expression string into its AST.
+fn parse_filter_expr(input: &str) -> std::result::Result<FilterExprNode, String> {
+    let tokens = tokenize_filter_expr(input)?;
+    if tokens.is_empty() {
+        return Err("filter expression must not be empty".to_string());
+    }
+    FilterExprParser::parse(&tokens)
+}
 
-                for section in parsed.gamestate.values() {
-                    traverse(section, &key_set, &mut counts);
-                }
-                json!({ "counts": counts })
+/// Evaluate a parsed `filter_entries` expression against one entry. Leaves
+/// resolve their field with `resolve_path` (the same dot-notation lookup
+/// `query`/`query_entries` use) and compare with `compare_values`, so numeric
+/// comparisons parse both sides as `f64` when possible and otherwise fall
+/// back to string equality; a missing field is always `false`.
+fn eval_filter_expr(entry: &Value, node: &FilterExprNode) -> bool {
+    use std::cmp::Ordering;
+
+    match node {
+        FilterExprNode::And(a, b) => eval_filter_expr(entry, a) && eval_filter_expr(entry, b),
+        FilterExprNode::Or(a, b) => eval_filter_expr(entry, a) || eval_filter_expr(entry, b),
+        FilterExprNode::Not(a) => !eval_filter_expr(entry, a),
+        FilterExprNode::Condition { field, op, value } => {
+            let Some(actual) = resolve_path(entry, field) else {
+                return false;
+            };
+            let ordering = compare_values(actual, value);
+            match op {
+                FilterEntriesOp::Eq => ordering == Some(Ordering::Equal),
+                FilterEntriesOp::Ne => ordering != Some(Ordering::Equal),
+                FilterEntriesOp::Gt => ordering == Some(Ordering::Greater),
+                FilterEntriesOp::Gte => matches!(ordering, Some(Ordering::Greater | Ordering::Equal)),
+                FilterEntriesOp::Lt => ordering == Some(Ordering::Less),
+                FilterEntriesOp::Lte => matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
             }
-            MultiOp::ContainsTokens { tokens } => {
-                let mut matches: HashMap<String, bool> =
-                    tokens.iter().map(|t| (t.clone(), false)).collect();
-                if !tokens.is_empty() {
-                    let ac =
-                        AhoCorasick::new(&tokens).expect("Failed to build Aho-Corasick automaton");
-                    for mat in ac.find_iter(&parsed.gamestate_bytes) {
-                        let pattern_idx = mat.pattern().as_usize();
-                        if pattern_idx < tokens.len() {
-                            matches.insert(tokens[pattern_idx].clone(), true);
-                        }
-                    }
+        }
+        FilterExprNode::Range { field, low, high } => {
+            let Some(actual) = resolve_path(entry, field) else {
+                return false;
+            };
+            let above_low = compare_values(actual, low).is_some_and(|o| o != Ordering::Less);
+            let below_high = compare_values(actual, high).is_some_and(|o| o != Ordering::Greater);
+            above_low && below_high
+        }
+    }
+}
+
+/// Filter a section's entries by a parsed `filter_entries` expression,
+/// returning each match's key and full value (the same `{_key, _value}`
+/// shape `query_entries` projects entries into with no `fields` selection).
+fn filter_entries(parsed: &ParsedSave, section: &str, filter: &FilterExprNode) -> Vec<Value> {
+    match parsed.gamestate.get(section) {
+        Some(Value::Object(map)) => map
+            .iter()
+            .filter(|(_, value)| eval_filter_expr(value, filter))
+            .map(|(key, value)| project_entry(key, value, &None))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Handle the `filter_entries` operation: parse the filter string once, then
+/// evaluate it against every entry of `section`.
+fn handle_filter_entries(parsed: &ParsedSave, section: String, filter: String) -> io::Result<()> {
+    match parse_filter_expr(&filter) {
+        Ok(ast) => {
+            let entries = filter_entries(parsed, &section, &ast);
+            write_response(&SuccessResponse {
+                ok: true,
+                data: ResponseData::QueryResults { entries },
+            })
+        }
+        Err(message) => write_op_error(&OpError::bad_filter(message)),
+    }
+}
+
+/// Order a section's entry keys by one field. Numeric when every entry that
+/// has the field holds a JSON number, lexicographic (via `scalar_to_string`)
+/// otherwise; entries missing the field always sort last, regardless of
+/// `order`. Errors instead of silently mis-sorting if any entry's field
+/// resolves to a structured (object/array) value rather than a scalar.
+fn sort_entries(
+    parsed: &ParsedSave,
+    section: &str,
+    field: &str,
+    order: SortDir,
+) -> std::result::Result<Vec<String>, OpError> {
+    let Some(Value::Object(map)) = parsed.gamestate.get(section) else {
+        return Ok(Vec::new());
+    };
+
+    let entries: Vec<(&String, Option<&Value>)> = map
+        .iter()
+        .map(|(key, value)| (key, resolve_path(value, field)))
+        .collect();
+
+    if let Some((key, _)) = entries
+        .iter()
+        .find(|(_, value)| matches!(value, Some(Value::Object(_) | Value::Array(_))))
+    {
+        return Err(OpError {
+            code: OpErrorCode::FieldNotSortable,
+            message: format!(
+                "field `{}` is not sortable: entry `{}` holds a structured value, not a scalar",
+                field, key
+            ),
+        });
+    }
+
+    let (mut present, missing): (Vec<_>, Vec<_>) =
+        entries.into_iter().partition(|(_, value)| value.is_some());
+
+    let all_numeric = present
+        .iter()
+        .all(|(_, value)| matches!(value, Some(Value::Number(_))));
+
+    present.sort_by(|(_, a), (_, b)| {
+        let (a, b) = (a.unwrap(), b.unwrap());
+        let ordering = if all_numeric {
+            a.as_f64()
+                .unwrap_or(0.0)
+                .partial_cmp(&b.as_f64().unwrap_or(0.0))
+                .unwrap_or(std::cmp::Ordering::Equal)
+        } else {
+            scalar_to_string(a)
+                .unwrap_or_default()
+                .cmp(&scalar_to_string(b).unwrap_or_default())
+        };
+        match order {
+            SortDir::Asc => ordering,
+            SortDir::Desc => ordering.reverse(),
+        }
+    });
+
+    let mut keys: Vec<String> = present.into_iter().map(|(key, _)| key.clone()).collect();
+    keys.extend(missing.into_iter().map(|(key, _)| key.clone()));
+    Ok(keys)
+}
+
+/// Handle the `sort_entries` operation.
+fn handle_sort_entries(
+    parsed: &ParsedSave,
+    section: String,
+    field: String,
+    order: SortDir,
+) -> io::Result<()> {
+    match sort_entries(parsed, &section, &field, order) {
+        Ok(keys) => write_response(&SuccessResponse {
+            ok: true,
+            data: ResponseData::SortedKeys { keys },
+        }),
+        Err(e) => write_op_error(&e),
+    }
+}
+
+/// What happened to a path between the two saves a `diff` op compared.
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum DiffKind {
+    Added,
+    Removed,
+    Changed,
+}
+
+/// One structural difference found by `diff_values`: a dotted path (section,
+/// then nested keys) plus the old/new value, whichever side the `kind`
+/// applies to.
+#[derive(Debug, Clone, Serialize)]
+struct DiffRecord {
+    path: String,
+    kind: DiffKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    before: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    after: Option<Value>,
+}
+
+/// Canonicalize an array to a sorted multiset of its elements' JSON text, so
+/// two arrays holding the same elements in a different order compare equal.
+fn array_multiset(items: &[Value]) -> Vec<String> {
+    let mut rendered: Vec<String> = items
+        .iter()
+        .map(|v| serde_json::to_string(v).unwrap_or_default())
+        .collect();
+    rendered.sort();
+    rendered
+}
+
+/// Recursively compare `before` and `after` at `path`, appending a record to
+/// `out` for every addition, removal, or changed scalar leaf. Objects are
+/// walked key by key (so an unrelated sibling never shows up as "changed");
+/// arrays are compared as multisets (element counts, ignoring order), so
+/// reordering alone isn't reported as a change.
+fn diff_values(path: &str, before: Option<&Value>, after: Option<&Value>, out: &mut Vec<DiffRecord>) {
+    match (before, after) {
+        (None, None) => {}
+        (None, Some(value)) => out.push(DiffRecord {
+            path: path.to_string(),
+            kind: DiffKind::Added,
+            before: None,
+            after: Some(value.clone()),
+        }),
+        (Some(value), None) => out.push(DiffRecord {
+            path: path.to_string(),
+            kind: DiffKind::Removed,
+            before: Some(value.clone()),
+            after: None,
+        }),
+        (Some(before), Some(after)) => match (before, after) {
+            (Value::Object(before_map), Value::Object(after_map)) => {
+                let mut keys: Vec<&String> = before_map.keys().chain(after_map.keys()).collect();
+                keys.sort();
+                keys.dedup();
+                for key in keys {
+                    let child_path = if path.is_empty() {
+                        key.clone()
+                    } else {
+                        format!("{}.{}", path, key)
+                    };
+                    diff_values(&child_path, before_map.get(key), after_map.get(key), out);
                 }
-                json!({ "matches": matches })
             }
-            MultiOp::ContainsKv { pairs } => {
-                use std::collections::HashSet;
-                let mut key_to_values: HashMap<String, HashSet<String>> = HashMap::new();
-                for (key, value) in &pairs {
-                    key_to_values
-                        .entry(key.clone())
-                        .or_default()
-                        .insert(value.clone());
+            (Value::Array(before_items), Value::Array(after_items)) => {
+                if array_multiset(before_items) != array_multiset(after_items) {
+                    out.push(DiffRecord {
+                        path: path.to_string(),
+                        kind: DiffKind::Changed,
+                        before: Some(Value::Array(before_items.clone())),
+                        after: Some(Value::Array(after_items.clone())),
+                    });
                 }
-                let mut matches: HashMap<String, bool> = pairs
-                    .iter()
-                    .map(|(k, v)| (format!("{}={}", k, v), false))
-                    .collect();
+            }
+            (before, after) if before == after => {}
+            (before, after) => out.push(DiffRecord {
+                path: path.to_string(),
+                kind: DiffKind::Changed,
+                before: Some(before.clone()),
+                after: Some(after.clone()),
+            }),
+        },
+    }
+}
 
-                fn traverse_kv(
-                    value: &Value,
-                    key_to_values: &HashMap<String, HashSet<String>>,
-                    matches: &mut HashMap<String, bool>,
-                ) {
-                    match value {
-                        Value::Object(map) => {
-                            for (k, v) in map {
-                                if let Some(target_values) = key_to_values.get(k.as_str()) {
-                                    let value_str = match v {
-                                        Value::String(s) => Some(s.as_str()),
-                                        Value::Number(_) => None,
-                                        Value::Bool(b) => {
-                                            if *b {
-                                                Some("yes")
-                                            } else {
-                                                Some("no")
-                                            }
-                                        }
-                                        _ => None,
-                                    };
-                                    if let Some(vs) = value_str {
-                                        if target_values.contains(vs) {
-                                            matches.insert(format!("{}={}", k, vs), true);
-                                        }
-                                    }
-                                    if let Value::Number(n) = v {
-                                        let num_str = n.to_string();
-                                        if target_values.contains(&num_str) {
-                                            matches.insert(format!("{}={}", k, num_str), true);
-                                        }
-                                    }
-                                }
-                                traverse_kv(v, key_to_values, matches);
-                            }
-                        }
-                        Value::Array(arr) => {
-                            for v in arr {
-                                traverse_kv(v, key_to_values, matches);
-                            }
-                        }
-                        _ => {}
-                    }
-                }
+/// Structural delta between two gamestates, optionally scoped to one
+/// section. Powers the `diff` op's "what changed between two autosaves" use
+/// case (new wars, leaders who died, planets lost) without the client
+/// re-reading both files and computing it itself.
+fn diff_gamestates(
+    before: &HashMap<String, Value>,
+    after: &HashMap<String, Value>,
+    section: Option<&str>,
+) -> Vec<DiffRecord> {
+    let mut out = Vec::new();
+    match section {
+        Some(section) => diff_values(section, before.get(section), after.get(section), &mut out),
+        None => {
+            let mut sections: Vec<&String> = before.keys().chain(after.keys()).collect();
+            sections.sort();
+            sections.dedup();
+            for section in sections {
+                diff_values(section, before.get(section), after.get(section), &mut out);
+            }
+        }
+    }
+    out
+}
 
-                for section in parsed.gamestate.values() {
-                    traverse_kv(section, &key_to_values, &mut matches);
-                }
-                json!({ "matches": matches })
+/// Handle the `diff` operation: load `other_path` as a second save and
+/// report the structural delta against the one already held by this
+/// session.
+fn handle_diff(
+    parsed: &ParsedSave,
+    other_path: String,
+    section: Option<String>,
+    tokens_path: Option<&str>,
+    cache_dir: Option<&str>,
+    cache_enabled: bool,
+) -> io::Result<()> {
+    match ParsedSave::load(&other_path, tokens_path, cache_dir, cache_enabled) {
+        Ok(other) => {
+            let changes = diff_gamestates(&parsed.gamestate, &other.gamestate, section.as_deref());
+            write_response(&SuccessResponse {
+                ok: true,
+                data: ResponseData::DiffResults { changes },
+            })
+        }
+        Err(e) => write_op_error(&OpError::bad_request(format!(
+            "failed to load {}: {:#}",
+            other_path, e
+        ))),
+    }
+}
+
+/// Mutable state behind the `poll` op's change feed: the generation counter
+/// bumped on every detected reload, the on-disk fingerprint (len + mtime)
+/// that triggers one, and just enough history (the gamestate as of the
+/// current and immediately preceding generation) to diff against without
+/// re-reading the file on every poll.
+struct PollState {
+    generation: u64,
+    file_len: u64,
+    modified_unix_secs: u64,
+    gamestate: HashMap<String, Value>,
+    previous_gamestate: Option<HashMap<String, Value>>,
+}
+
+impl PollState {
+    /// Seed poll state from the save already loaded for this session, so the
+    /// first poll has a fingerprint to compare future reloads against.
+    fn new(path: &str, gamestate: HashMap<String, Value>) -> Self {
+        let (file_len, modified_unix_secs) = file_fingerprint(path).unwrap_or((0, 0));
+        Self {
+            generation: 1,
+            file_len,
+            modified_unix_secs,
+            gamestate,
+            previous_gamestate: None,
+        }
+    }
+}
+
+/// `(file size, mtime in unix seconds)`, used by `poll` to detect an
+/// on-disk save change without re-reading or re-parsing its contents.
+fn file_fingerprint(path: &str) -> Option<(u64, u64)> {
+    let metadata = std::fs::metadata(path).ok()?;
+    let modified_unix_secs = metadata
+        .modified()
+        .ok()?
+        .duration_since(std::time::UNIX_EPOCH)
+        .ok()?
+        .as_secs();
+    Some((metadata.len(), modified_unix_secs))
+}
+
+/// What a `poll` caller on `token` should be told about, given the state's
+/// current generation: an empty delta if `token` is already caught up or no
+/// reload has happened yet, otherwise the restricted delta (whole save if
+/// `sections` is empty) between the previous and current generation.
+fn poll_changes(state: &PollState, token: u64, sections: &[String]) -> Vec<DiffRecord> {
+    if token >= state.generation {
+        return Vec::new();
+    }
+    let Some(previous) = &state.previous_gamestate else {
+        return Vec::new();
+    };
+    if sections.is_empty() {
+        diff_gamestates(previous, &state.gamestate, None)
+    } else {
+        sections
+            .iter()
+            .flat_map(|section| diff_gamestates(previous, &state.gamestate, Some(section)))
+            .collect()
+    }
+}
+
+/// Handle the `poll` op: if `path`'s on-disk fingerprint has moved since the
+/// last poll, re-parse it (bypassing the save cache, since a hot-reload must
+/// reflect what's on disk right now) and bump the generation counter. Then
+/// report the delta restricted to `sections` (the whole save if empty) if
+/// the caller's `token` is behind the current generation, or an empty delta
+/// if it's already caught up.
+fn handle_poll(
+    poll_state: &Mutex<PollState>,
+    path: &str,
+    tokens_path: Option<&str>,
+    cache_dir: Option<&str>,
+    token: u64,
+    sections: Vec<String>,
+) -> io::Result<()> {
+    let mut state = poll_state.lock().unwrap();
+
+    if let Some((file_len, modified_unix_secs)) = file_fingerprint(path) {
+        if file_len != state.file_len || modified_unix_secs != state.modified_unix_secs {
+            if let Ok(reloaded) = ParsedSave::load(path, tokens_path, cache_dir, false) {
+                let previous = std::mem::replace(&mut state.gamestate, reloaded.gamestate);
+                state.previous_gamestate = Some(previous);
+                state.generation += 1;
             }
-            MultiOp::GetCountrySummaries { fields } => {
-                let mut countries: Vec<Value> = Vec::new();
-                if let Some(Value::Object(country_map)) = parsed.gamestate.get("country") {
-                    for (country_id, country_data) in country_map {
-                        let mut summary = Map::new();
-                        summary.insert("id".to_string(), json!(country_id));
-                        if let Value::Object(country_obj) = country_data {
-                            for field in &fields {
-                                if let Some(value) = country_obj.get(field) {
-                                    summary.insert(field.clone(), value.clone());
-                                }
-                            }
-                        }
-                        countries.push(Value::Object(summary));
-                    }
-                }
-                json!({ "countries": countries })
+            state.file_len = file_len;
+            state.modified_unix_secs = modified_unix_secs;
+        }
+    }
+
+    let changes = poll_changes(&state, token, &sections);
+
+    write_response(&SuccessResponse {
+        ok: true,
+        data: ResponseData::PollResult {
+            token: state.generation,
+            changes,
+        },
+    })
+}
+
+/// Handle query_entries operation - server-side filter/sort/limit/project
+/// over a section, so callers don't have to pull every entry via
+/// `iter_section` just to discard most of them client-side.
+fn handle_query_entries(
+    parsed: &ParsedSave,
+    section: String,
+    filter: Option<FilterNode>,
+    sort: Vec<SortKey>,
+    limit: Option<usize>,
+    fields: Option<Vec<String>>,
+) -> io::Result<()> {
+    let entries = query_entries(parsed, &section, &filter, &sort, limit, &fields);
+    write_response(&SuccessResponse {
+        ok: true,
+        data: ResponseData::QueryResults { entries },
+    })
+}
+
+/// One occurrence of a term in an entry's field, recorded by
+/// `build_search_index` so `search` can look entries up by term instead of
+/// rescanning `gamestate_bytes`. `position` is the term's index within its
+/// value's tokenization, used to score how close together matched terms
+/// land within the same value (see `search_entries`).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Posting {
+    section: String,
+    entry_key: String,
+    field_path: String,
+    position: usize,
+}
+
+/// Split a leaf value into lowercase search terms the same way on indexing
+/// and query sides, so a query term always matches the postings it should.
+fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|s| !s.is_empty())
+        .map(|s| s.to_lowercase())
+        .collect()
+}
+
+/// Build the inverted index once at load time: walk every section's
+/// entries, tokenize each string/number leaf, and record a posting keyed by
+/// term so `search` can union postings instead of re-scanning the tree.
+fn build_search_index(gamestate: &HashMap<String, Value>) -> HashMap<String, Vec<Posting>> {
+    let mut index: HashMap<String, Vec<Posting>> = HashMap::new();
+    for (section, value) in gamestate {
+        if let Value::Object(entries) = value {
+            for (entry_key, entry_value) in entries {
+                index_leaf(section, entry_key, "", entry_value, &mut index);
+            }
+        }
+    }
+    index
+}
+
+/// Recursively walk one entry's value tree, indexing every string/number
+/// leaf under its dot-notation field path (the same notation `query_entries`
+/// filters/sorts use).
+fn index_leaf(
+    section: &str,
+    entry_key: &str,
+    path: &str,
+    value: &Value,
+    index: &mut HashMap<String, Vec<Posting>>,
+) {
+    match value {
+        Value::Object(map) => {
+            for (field, child) in map {
+                let field_path = if path.is_empty() {
+                    field.clone()
+                } else {
+                    format!("{}.{}", path, field)
+                };
+                index_leaf(section, entry_key, &field_path, child, index);
+            }
+        }
+        Value::Array(arr) => {
+            for child in arr {
+                index_leaf(section, entry_key, path, child, index);
+            }
+        }
+        Value::String(s) => {
+            for (position, term) in tokenize(s).into_iter().enumerate() {
+                index.entry(term).or_default().push(Posting {
+                    section: section.to_string(),
+                    entry_key: entry_key.to_string(),
+                    field_path: path.to_string(),
+                    position,
+                });
+            }
+        }
+        Value::Number(n) => {
+            index.entry(n.to_string()).or_default().push(Posting {
+                section: section.to_string(),
+                entry_key: entry_key.to_string(),
+                field_path: path.to_string(),
+                position: 0,
+            });
+        }
+        _ => {}
+    }
+}
+
+/// Classic Wagner-Fischer edit distance between two strings, used to rate
+/// how close an indexed token is to a (possibly misspelled) query word.
+fn levenshtein(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let (n, m) = (a.len(), b.len());
+
+    let mut prev: Vec<usize> = (0..=m).collect();
+    let mut curr = vec![0usize; m + 1];
+    for i in 1..=n {
+        curr[0] = i;
+        for j in 1..=m {
+            let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+            curr[j] = (prev[j] + 1).min(curr[j - 1] + 1).min(prev[j - 1] + cost);
+        }
+        std::mem::swap(&mut prev, &mut curr);
+    }
+    prev[m]
+}
+
+/// How many typos a query word of this length is allowed before we stop
+/// treating a candidate token as a match: exact-only for short words (where
+/// an edit changes the meaning too much), growing as the word gets longer.
+fn typo_budget(word_len: usize) -> usize {
+    match word_len {
+        0..=4 => 0,
+        5..=8 => 1,
+        _ => 2,
+    }
+}
+
+/// A BK-tree over the index's distinct terms, so a query word can retrieve
+/// every indexed token within an edit-distance budget in roughly
+/// O(log vocabulary) distance computations instead of scanning every term.
+struct BkTree {
+    root: Option<Box<BkNode>>,
+}
+
+struct BkNode {
+    term: String,
+    children: HashMap<usize, Box<BkNode>>,
+}
+
+impl BkTree {
+    fn new() -> Self {
+        Self { root: None }
+    }
+
+    fn insert(&mut self, term: String) {
+        match &mut self.root {
+            None => self.root = Some(Box::new(BkNode::new(term))),
+            Some(root) => root.insert(term),
+        }
+    }
+
+    /// Every indexed term within `budget` edits of `query`, paired with its
+    /// distance (0 for an exact match).
+    fn fuzzy_matches(&self, query: &str, budget: usize) -> Vec<(String, usize)> {
+        let mut out = Vec::new();
+        if let Some(root) = &self.root {
+            root.collect(query, budget, &mut out);
+        }
+        out
+    }
+}
+
+impl BkNode {
+    fn new(term: String) -> Self {
+        Self {
+            term,
+            children: HashMap::new(),
+        }
+    }
+
+    fn insert(&mut self, term: String) {
+        let distance = levenshtein(&self.term, &term);
+        if distance == 0 {
+            return; // already indexed
+        }
+        match self.children.get_mut(&distance) {
+            Some(child) => child.insert(term),
+            None => {
+                self.children.insert(distance, Box::new(BkNode::new(term)));
+            }
+        }
+    }
+
+    fn collect(&self, query: &str, budget: usize, out: &mut Vec<(String, usize)>) {
+        let distance = levenshtein(&self.term, query);
+        if distance <= budget {
+            out.push((self.term.clone(), distance));
+        }
+        // Triangle inequality: any match under a child edge can only be
+        // within [distance - budget, distance + budget] of this node.
+        let lo = distance.saturating_sub(budget);
+        let hi = distance + budget;
+        for (edge, child) in &self.children {
+            if *edge >= lo && *edge <= hi {
+                child.collect(query, budget, out);
+            }
+        }
+    }
+}
+
+/// Build a BK-tree over every distinct term in the index, for `search_entries`
+/// to do typo-tolerant lookups against.
+fn build_term_bk_tree(index: &HashMap<String, Vec<Posting>>) -> BkTree {
+    let mut tree = BkTree::new();
+    for term in index.keys() {
+        tree.insert(term.clone());
+    }
+    tree
+}
+
+/// Expand each query term to every indexed token within its typo budget, look
+/// up their postings, and group hits by `(section, entry_key)`. Ranked by
+/// number of distinct matching query words first, then ascending total typo
+/// count, then by how tightly the matched words cluster within a single
+/// value (smallest token-position span), then by raw term frequency.
+fn search_entries(
+    index: &HashMap<String, Vec<Posting>>,
+    term_bk_tree: &BkTree,
+    terms: &[String],
+    top_k: usize,
+    section: &Option<String>,
+) -> Vec<SearchHit> {
+    use std::collections::HashSet;
+
+    struct Grouped {
+        matched_terms: HashSet<String>,
+        term_frequency: usize,
+        typo_count: usize,
+        matched_fields: HashSet<String>,
+        // field_path -> positions of every matched token seen in that field
+        field_positions: HashMap<String, Vec<usize>>,
+    }
+
+    let mut grouped: HashMap<(String, String), Grouped> = HashMap::new();
+
+    for term in terms {
+        let term = term.to_lowercase();
+        if term.is_empty() {
+            continue;
+        }
+        let budget = typo_budget(term.chars().count());
+        for (candidate, distance) in term_bk_tree.fuzzy_matches(&term, budget) {
+            let Some(postings) = index.get(&candidate) else {
+                continue;
+            };
+            for posting in postings {
+                if let Some(wanted) = section {
+                    if &posting.section != wanted {
+                        continue;
+                    }
+                }
+                let group = grouped
+                    .entry((posting.section.clone(), posting.entry_key.clone()))
+                    .or_insert_with(|| Grouped {
+                        matched_terms: HashSet::new(),
+                        term_frequency: 0,
+                        typo_count: 0,
+                        matched_fields: HashSet::new(),
+                        field_positions: HashMap::new(),
+                    });
+                if group.matched_terms.insert(term.clone()) {
+                    group.typo_count += distance;
+                }
+                group.term_frequency += 1;
+                group.matched_fields.insert(posting.field_path.clone());
+                group
+                    .field_positions
+                    .entry(posting.field_path.clone())
+                    .or_default()
+                    .push(posting.position);
+            }
+        }
+    }
+
+    let mut hits: Vec<(SearchHit, usize)> = grouped
+        .into_iter()
+        .map(|((section, entry_key), group)| {
+            let mut matched_fields: Vec<String> = group.matched_fields.into_iter().collect();
+            matched_fields.sort();
+            // Smallest span of matched token positions within any one value;
+            // a field with fewer than two recorded positions can't tell us
+            // anything about proximity, so it doesn't contribute.
+            let proximity = group
+                .field_positions
+                .values()
+                .filter(|positions| positions.len() > 1)
+                .map(|positions| {
+                    let min = *positions.iter().min().unwrap();
+                    let max = *positions.iter().max().unwrap();
+                    max - min
+                })
+                .min()
+                .unwrap_or(0);
+            (
+                SearchHit {
+                    section,
+                    entry_key,
+                    matched_fields,
+                    matching_terms: group.matched_terms.len(),
+                    term_frequency: group.term_frequency,
+                    typo_count: group.typo_count,
+                },
+                proximity,
+            )
+        })
+        .collect();
+
+    hits.sort_by(|(a, a_proximity), (b, b_proximity)| {
+        b.matching_terms
+            .cmp(&a.matching_terms)
+            .then(a.typo_count.cmp(&b.typo_count))
+            .then(a_proximity.cmp(b_proximity))
+            .then(b.term_frequency.cmp(&a.term_frequency))
+    });
+    hits.truncate(top_k);
+    hits.into_iter().map(|(hit, _)| hit).collect()
+}
+
+/// Handle search operation - ranked lookup against the inverted index built
+/// once at load time, so a query doesn't have to rescan the whole save.
+fn handle_search(
+    parsed: &ParsedSave,
+    terms: Vec<String>,
+    top_k: usize,
+    section: Option<String>,
+) -> io::Result<()> {
+    let results = search_entries(
+        &parsed.search_index,
+        &parsed.term_bk_tree,
+        &terms,
+        top_k,
+        &section,
+    );
+    write_response(&SuccessResponse {
+        ok: true,
+        data: ResponseData::SearchResults { results },
+    })
+}
+
+/// Handle get_country_summaries operation - return lightweight country projections
+fn handle_get_country_summaries(parsed: &ParsedSave, fields: Vec<String>) -> io::Result<()> {
+    let mut countries: Vec<Value> = Vec::new();
+
+    // Get the country section from gamestate
+    if let Some(Value::Object(country_map)) = parsed.gamestate.get("country") {
+        for (country_id, country_data) in country_map {
+            let mut summary = Map::new();
+            summary.insert("id".to_string(), json!(country_id));
+
+            // Extract only the requested fields
+            if let Value::Object(country_obj) = country_data {
+                for field in &fields {
+                    if let Some(value) = country_obj.get(field) {
+                        summary.insert(field.clone(), value.clone());
+                    }
+                }
+            }
+
+            countries.push(Value::Object(summary));
+        }
+    }
+
+    write_response(&SuccessResponse {
+        ok: true,
+        data: ResponseData::CountrySummaries { countries },
+    })
+}
+
+/// Find the byte offset just past the opening `{` of `section`'s block
+/// within `gamestate_bytes`, reusing a previously found offset when one is
+/// cached (so `handle_multi_op` doesn't rescan a multi-hundred-MB gamestate
+/// once per batched op against the same section).
+fn find_section_content_start(
+    gamestate_bytes: &[u8],
+    section: &str,
+    cached: Option<usize>,
+) -> Option<usize> {
+    if let Some(start) = cached {
+        return Some(start);
+    }
+    let section_pattern = format!("\n{}=", section);
+    let section_start = find_bytes(gamestate_bytes, section_pattern.as_bytes())?;
+    let brace = find_bytes(&gamestate_bytes[section_start..], b"{")?;
+    Some(section_start + brace + 1)
+}
+
+/// Plain byte-slice search (the raw-byte equivalent of `str::find` for a
+/// literal pattern), used so entry lookup never has to go through a lossy
+/// UTF-8 decode of the gamestate bytes first.
+fn find_bytes(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    if needle.is_empty() || haystack.len() < needle.len() {
+        return None;
+    }
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+/// Find the raw byte span (including the braces) of one entry's value block
+/// within a section, given the section's content start. Brace matching is
+/// quote- and escape-aware, so braces inside a quoted string (e.g.
+/// `name="Fortress {Alpha}"`) don't throw off the depth count the way the
+/// old `char`-counting scan did.
+fn find_entry_span(
+    gamestate_bytes: &[u8],
+    section_content_start: usize,
+    key: &str,
+) -> Option<std::ops::Range<usize>> {
+    let section_bytes = &gamestate_bytes[section_content_start..];
+    // Keys at the top level of a section are tab-indented once; try the
+    // formatting variants Stellaris actually emits.
+    let entry_patterns = [
+        format!("\n\t{}=\n\t{{", key),
+        format!("\n\t{}={{", key),
+        format!("\n\t{} =", key),
+    ];
+    let entry_start = entry_patterns
+        .iter()
+        .find_map(|pattern| find_bytes(section_bytes, pattern.as_bytes()))
+        .map(|relative| section_content_start + relative)?;
+
+    let brace_open = entry_start + find_bytes(&gamestate_bytes[entry_start..], b"{")?;
+
+    let mut depth = 0usize;
+    let mut in_quotes = false;
+    let mut i = brace_open;
+    while i < gamestate_bytes.len() {
+        match gamestate_bytes[i] {
+            b'\\' if in_quotes => i += 1, // skip the escaped byte
+            b'"' => in_quotes = !in_quotes,
+            b'{' if !in_quotes => depth += 1,
+            b'}' if !in_quotes => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(brace_open..i + 1);
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+/// Every occurrence of `key` at the top level of a section, found by
+/// repeating `find_entry_span` from just after each previous match. Needed
+/// because Stellaris sometimes repeats the same key as a literal duplicate
+/// top-level entry (not just a duplicate field inside one entry), and
+/// `find_entry_span` alone only ever returns the first.
+fn find_entry_spans(
+    gamestate_bytes: &[u8],
+    section_content_start: usize,
+    key: &str,
+) -> Vec<std::ops::Range<usize>> {
+    let mut spans = Vec::new();
+    let mut search_start = section_content_start;
+    while let Some(span) = find_entry_span(gamestate_bytes, search_start, key) {
+        search_start = span.end;
+        spans.push(span);
+    }
+    spans
+}
+
+/// Re-parse an isolated entry block (`{ ... }`) with jomini's mid-level
+/// `TextTape` reader instead of the whole-gamestate serde deserialization,
+/// so duplicate keys survive as repeated `(name, value)` pairs in original
+/// order instead of "last one wins" (see the note in `edge_cases.rs`).
+/// `entry_bytes` is wrapped with a synthetic key so the tape parses it as a
+/// single top-level field.
+fn parse_entry_with_duplicates(entry_bytes: &[u8]) -> Result<Vec<(String, Value)>, CompanionError> {
+    let mut wrapped = Vec::with_capacity(entry_bytes.len() + 6);
+    wrapped.extend_from_slice(b"entry=");
+    wrapped.extend_from_slice(entry_bytes);
+
+    let tape = jomini::TextTape::from_slice(&wrapped).map_err(|e| CompanionError::ParseError {
+        source: anyhow::Error::new(e),
+        decode_offset: None,
+    })?;
+    let reader = tape.windows1252_reader();
+
+    let Some((_key, _op, entry_value)) = reader.fields().next() else {
+        return Ok(Vec::new());
+    };
+    let object = entry_value
+        .read_object()
+        .map_err(|e| CompanionError::ParseError {
+            source: anyhow::Error::new(e),
+            decode_offset: None,
+        })?;
+
+    Ok(object
+        .fields()
+        .map(|(key, _op, value)| (key.read_string(), text_value_to_json(value)))
+        .collect())
+}
+
+/// Recursively convert one jomini text-reader value into JSON, preserving
+/// duplicate object keys (which `text_value_to_json` can't drop the way
+/// `HashMap<String, Value>` deserialization does) as an ordered array of
+/// `{"key": ..., "value": ...}` pairs instead of a JSON object.
+fn text_value_to_json(
+    value: jomini::text::ValueReader<'_, '_, jomini::text::Windows1252Encoding>,
+) -> Value {
+    if let Ok(object) = value.read_object() {
+        return Value::Array(
+            object
+                .fields()
+                .map(|(key, _op, child)| {
+                    json!({ "key": key.read_string(), "value": text_value_to_json(child) })
+                })
+                .collect(),
+        );
+    }
+    if let Ok(array) = value.read_array() {
+        return Value::Array(array.values().map(text_value_to_json).collect());
+    }
+    match value.read_scalar() {
+        Ok(scalar) => crate::output::scalar_to_json(&scalar),
+        Err(_) => Value::Null,
+    }
+}
+
+/// Walk a value produced by `text_value_to_json`, collecting every value
+/// whose key matches `field` at any depth. Nested objects are represented
+/// as an array of `{"key", "value"}` pairs (see `text_value_to_json`), so
+/// this recurses into those pairs instead of treating them as opaque data,
+/// matching how the original substring scan searched the whole entry block
+/// regardless of nesting.
+fn collect_field_occurrences(value: &Value, field: &str, out: &mut Vec<Value>) {
+    let Value::Array(items) = value else {
+        return;
+    };
+    for item in items {
+        if let Value::Object(map) = item {
+            if let (Some(Value::String(k)), Some(v)) = (map.get("key"), map.get("value")) {
+                if k == field {
+                    out.push(v.clone());
+                }
+                collect_field_occurrences(v, field, out);
+                continue;
+            }
+        }
+        collect_field_occurrences(item, field, out);
+    }
+}
+
+/// Every occurrence of `field` inside a single `section -> key` entry, at
+/// any nesting depth, typed and in original order, plus the section
+/// content-start offset for `handle_multi_op`'s cache. Shared by
+/// `get_duplicate_values` (legacy, stringified) and `get_multi_field` (typed).
+fn multi_field_values(
+    gamestate_bytes: &[u8],
+    section: &str,
+    key: &str,
+    field: &str,
+    cached_section_start: Option<usize>,
+) -> Result<(Vec<Value>, bool, Option<usize>), CompanionError> {
+    let Some(section_start) =
+        find_section_content_start(gamestate_bytes, section, cached_section_start)
+    else {
+        return Ok((Vec::new(), false, None));
+    };
+    let Some(span) = find_entry_span(gamestate_bytes, section_start, key) else {
+        return Ok((Vec::new(), false, Some(section_start)));
+    };
+    let fields = parse_entry_with_duplicates(&gamestate_bytes[span])?;
+    let mut values = Vec::new();
+    for (name, value) in &fields {
+        if name == field {
+            values.push(value.clone());
+        }
+        collect_field_occurrences(value, field, &mut values);
+    }
+    Ok((values, true, Some(section_start)))
+}
+
+/// Like `multi_field_values`, but for a literal duplicate top-level key:
+/// every occurrence's `field` values, one list per occurrence, instead of
+/// stopping at the first match. Shared by `get_all_duplicate_values`
+/// (legacy, stringified) and its `MultiOp` counterpart.
+fn multi_field_values_all(
+    gamestate_bytes: &[u8],
+    section: &str,
+    key: &str,
+    field: &str,
+    cached_section_start: Option<usize>,
+) -> Result<(Vec<Vec<Value>>, bool, Option<usize>), CompanionError> {
+    let Some(section_start) =
+        find_section_content_start(gamestate_bytes, section, cached_section_start)
+    else {
+        return Ok((Vec::new(), false, None));
+    };
+    let spans = find_entry_spans(gamestate_bytes, section_start, key);
+    if spans.is_empty() {
+        return Ok((Vec::new(), false, Some(section_start)));
+    }
+
+    let mut occurrences = Vec::with_capacity(spans.len());
+    for span in spans {
+        let fields = parse_entry_with_duplicates(&gamestate_bytes[span])?;
+        let mut values = Vec::new();
+        for (name, value) in &fields {
+            if name == field {
+                values.push(value.clone());
+            }
+            collect_field_occurrences(value, field, &mut values);
+        }
+        occurrences.push(values);
+    }
+    Ok((occurrences, true, Some(section_start)))
+}
+
+/// Every top-level field of a single `section -> key` entry, with duplicate
+/// keys preserved as repeated `EntryData` items instead of collapsed by the
+/// whole-gamestate deserialization. Shared by `get_entry_fields` and its
+/// `MultiOp` counterpart.
+fn entry_fields(
+    gamestate_bytes: &[u8],
+    section: &str,
+    key: &str,
+    cached_section_start: Option<usize>,
+) -> Result<(Vec<EntryData>, bool, Option<usize>), CompanionError> {
+    let Some(section_start) =
+        find_section_content_start(gamestate_bytes, section, cached_section_start)
+    else {
+        return Ok((Vec::new(), false, None));
+    };
+    let Some(span) = find_entry_span(gamestate_bytes, section_start, key) else {
+        return Ok((Vec::new(), false, Some(section_start)));
+    };
+    let fields = parse_entry_with_duplicates(&gamestate_bytes[span])?;
+    let entries = fields
+        .into_iter()
+        .map(|(key, value)| EntryData { key, value })
+        .collect();
+    Ok((entries, true, Some(section_start)))
+}
+
+/// Coerce a typed JSON value back to the flat string form the legacy
+/// `get_duplicate_values` op returns, so reimplementing it on top of
+/// `multi_field_values` doesn't change its response shape.
+fn value_to_display_string(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Bool(b) => if *b { "yes" } else { "no" }.to_string(),
+        Value::Number(n) => n.to_string(),
+        other => other.to_string(),
+    }
+}
+
+/// Handle get_duplicate_values operation - extract all values for a field with duplicate keys
+///
+/// This is needed because jomini's JSON-style deserialization collapses duplicate keys,
+/// but Stellaris save files use duplicate keys for list-like structures (e.g., traits="x"
+/// appearing multiple times for a leader). Re-parses the isolated entry via jomini's
+/// `TextTape` (see `multi_field_values`) instead of a `field="value"` substring scan, so
+/// nested braces inside quoted strings and non-UTF8 Windows-1252 bytes no longer throw off
+/// the extraction.
+fn handle_get_duplicate_values(
+    gamestate_bytes: &[u8],
+    section: String,
+    key: String,
+    field: String,
+) -> io::Result<()> {
+    match multi_field_values(gamestate_bytes, &section, &key, &field, None) {
+        Ok((values, found, _)) => {
+            let values: Vec<String> = values.iter().map(value_to_display_string).collect();
+            write_response(&SuccessResponse {
+                ok: true,
+                data: ResponseData::DuplicateValues { values, found },
+            })
+        }
+        Err(e) => write_op_error(&OpError::from(e)),
+    }
+}
+
+/// Handle get_all_duplicate_values operation - like `get_duplicate_values`,
+/// but for a literal duplicate top-level key: one values list per
+/// occurrence instead of just the first, so Python doesn't have to re-run
+/// regexes over the section to recover the siblings.
+fn handle_get_all_duplicate_values(
+    gamestate_bytes: &[u8],
+    section: String,
+    key: String,
+    field: String,
+) -> io::Result<()> {
+    match multi_field_values_all(gamestate_bytes, &section, &key, &field, None) {
+        Ok((occurrences, found, _)) => {
+            let occurrences: Vec<Vec<String>> = occurrences
+                .iter()
+                .map(|values| values.iter().map(value_to_display_string).collect())
+                .collect();
+            write_response(&SuccessResponse {
+                ok: true,
+                data: ResponseData::AllDuplicateValues { occurrences, found },
+            })
+        }
+        Err(e) => write_op_error(&OpError::from(e)),
+    }
+}
+
+/// Handle get_multi_field operation - the typed successor to
+/// `get_duplicate_values`: every occurrence of `field` inside a single entry,
+/// returned as real JSON values (numbers/bools/strings) instead of strings.
+fn handle_get_multi_field(
+    gamestate_bytes: &[u8],
+    section: String,
+    key: String,
+    field: String,
+) -> io::Result<()> {
+    match multi_field_values(gamestate_bytes, &section, &key, &field, None) {
+        Ok((values, found, _)) => write_response(&SuccessResponse {
+            ok: true,
+            data: ResponseData::MultiFieldValues { values, found },
+        }),
+        Err(e) => write_op_error(&OpError::from(e)),
+    }
+}
+
+/// Handle get_entry_fields operation - re-parse a whole entry preserving
+/// every duplicate key as its own `EntryData` item, so Python callers no
+/// longer need the `get_entry_text` raw-text fallback just to recover
+/// duplicate-key list structures (e.g. `relation={...}` blocks).
+fn handle_get_entry_fields(gamestate_bytes: &[u8], section: String, key: String) -> io::Result<()> {
+    match entry_fields(gamestate_bytes, &section, &key, None) {
+        Ok((fields, found, _)) => write_response(&SuccessResponse {
+            ok: true,
+            data: ResponseData::EntryFields { fields, found },
+        }),
+        Err(e) => write_op_error(&OpError::from(e)),
+    }
+}
+
+/// Handle get_entry_text operation - extract raw Clausewitz text for a single entry
+///
+/// This is needed for cases where Python needs to parse duplicate keys (like relation={})
+/// that can't be represented in JSON. Instead of searching the entire gamestate in Python,
+/// this returns just the entry's raw text for targeted regex parsing.
+fn handle_get_entry_text(gamestate_bytes: &[u8], section: String, key: String) -> io::Result<()> {
+    let content = String::from_utf8_lossy(gamestate_bytes);
+    let (text, found) = extract_entry_text(&content, &section, &key, None);
+
+    write_response(&SuccessResponse {
+        ok: true,
+        data: ResponseData::EntryText { text, found },
+    })
+}
+
+/// Handle get_all_entry_texts operation - like `get_entry_text`, but for a
+/// literal duplicate top-level key: every occurrence's raw text, in order,
+/// instead of just the first.
+fn handle_get_all_entry_texts(
+    gamestate_bytes: &[u8],
+    section: String,
+    key: String,
+) -> io::Result<()> {
+    let content = String::from_utf8_lossy(gamestate_bytes);
+    let (texts, found) = extract_entry_text_all(&content, &section, &key, None);
+
+    write_response(&SuccessResponse {
+        ok: true,
+        data: ResponseData::AllEntryText { texts, found },
+    })
+}
+
+/// Helper to extract raw entry text with optional cached section offset.
+/// Returns (text, found). Delegates to `extract_entry_text_all` and keeps
+/// just the first occurrence, for callers that only care about one entry.
+fn extract_entry_text(
+    content: &str,
+    section: &str,
+    key: &str,
+    cached_section_start: Option<usize>,
+) -> (String, bool) {
+    let (mut texts, found) = extract_entry_text_all(content, section, key, cached_section_start);
+    if found {
+        (texts.remove(0), true)
+    } else {
+        (String::new(), false)
+    }
+}
+
+/// Extract the raw Clausewitz text of every top-level occurrence of `key`
+/// in `section`, in order. Brace matching is quote- and escape-aware, so
+/// braces inside a quoted string (flavor text, a custom empire name, GUI
+/// markup) don't end the block early the way a literal brace count would.
+fn extract_entry_text_all(
+    content: &str,
+    section: &str,
+    key: &str,
+    cached_section_start: Option<usize>,
+) -> (Vec<String>, bool) {
+    // Find section start (use cache if available)
+    let section_content_start = if let Some(start) = cached_section_start {
+        start
+    } else {
+        let section_pattern = format!("\n{}=", section);
+        if let Some(section_start) = content.find(&section_pattern) {
+            match content[section_start..].find('{') {
+                Some(pos) => section_start + pos + 1,
+                None => return (Vec::new(), false),
+            }
+        } else {
+            return (Vec::new(), false);
+        }
+    };
+
+    // Look for the entry: \n\t<key>=
+    let entry_patterns = [
+        format!("\n\t{}=\n\t{{", key),
+        format!("\n\t{}={{", key),
+        format!("\n\t{} =", key),
+    ];
+
+    let bytes = content.as_bytes();
+    let mut texts = Vec::new();
+    let mut search_start = section_content_start;
+
+    loop {
+        let mut entry_start: Option<usize> = None;
+        for pattern in &entry_patterns {
+            if let Some(pos) = content[search_start..].find(pattern) {
+                entry_start = Some(search_start + pos);
+                break;
+            }
+        }
+        let Some(start) = entry_start else {
+            break;
+        };
+
+        // Find the entry's content by counting braces, ignoring any that
+        // fall inside a `"`-quoted string and honoring `\"` escapes there.
+        let mut brace_count = 0usize;
+        let mut in_entry = false;
+        let mut in_quotes = false;
+        let mut entry_end = None;
+        let mut i = start;
+        while i < bytes.len() {
+            match bytes[i] {
+                b'\\' if in_quotes => i += 1,
+                b'"' => in_quotes = !in_quotes,
+                b'{' if !in_quotes => {
+                    brace_count += 1;
+                    in_entry = true;
+                }
+                b'}' if !in_quotes => {
+                    brace_count -= 1;
+                    if in_entry && brace_count == 0 {
+                        entry_end = Some(i + 1);
+                        break;
+                    }
+                }
+                _ => {}
+            }
+            i += 1;
+        }
+
+        let Some(end) = entry_end else {
+            break;
+        };
+
+        texts.push(content[start..end].to_string());
+        search_start = end;
+    }
+
+    let found = !texts.is_empty();
+    (texts, found)
+}
+
+/// Tag a `run_multi_op` outcome the way a batch result is always shaped:
+/// `{"ok": true, ...}` on success, `{"ok": false, "code", "message"}` on a
+/// failed op - so one failing op doesn't abort the rest of the batch or
+/// produce an ambiguous `null`.
+fn tag_multi_op_result(result: Result<Value, OpError>) -> Value {
+    match result {
+        Ok(Value::Object(mut map)) => {
+            map.insert("ok".to_string(), json!(true));
+            Value::Object(map)
+        }
+        Ok(other) => other,
+        Err(e) => json!({ "ok": false, "code": e.code, "message": e.message }),
+    }
+}
+
+/// When a batch contains both `CountKeys` and `ContainsKv`, each otherwise
+/// walks the whole gamestate tree independently; fuse them into a single
+/// traversal computing the union of every such op's targets in one pass.
+/// Returns `(key -> occurrence count, "key=value" -> matched)`; a pair
+/// absent from the second map simply wasn't found (same default as the
+/// standalone ops).
+fn fused_count_and_kv_scan(
+    parsed: &ParsedSave,
+    ops: &[MultiOp],
+) -> (HashMap<String, usize>, HashMap<String, bool>) {
+    use std::collections::HashSet;
+
+    let mut count_keys: HashSet<&str> = HashSet::new();
+    let mut key_to_values: HashMap<String, HashSet<String>> = HashMap::new();
+    for op in ops {
+        match op {
+            MultiOp::CountKeys { keys } => {
+                count_keys.extend(keys.iter().map(|k| k.as_str()));
+            }
+            MultiOp::ContainsKv { pairs } => {
+                for (key, value) in pairs {
+                    key_to_values
+                        .entry(key.clone())
+                        .or_default()
+                        .insert(value.clone());
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut counts: HashMap<String, usize> =
+        count_keys.iter().map(|k| (k.to_string(), 0)).collect();
+    let mut matches: HashMap<String, bool> = HashMap::new();
+
+    fn traverse(
+        value: &Value,
+        count_keys: &HashSet<&str>,
+        key_to_values: &HashMap<String, HashSet<String>>,
+        counts: &mut HashMap<String, usize>,
+        matches: &mut HashMap<String, bool>,
+    ) {
+        match value {
+            Value::Object(map) => {
+                for (k, v) in map {
+                    if count_keys.contains(k.as_str()) {
+                        *counts.get_mut(k.as_str()).unwrap() += 1;
+                    }
+                    if let Some(target_values) = key_to_values.get(k.as_str()) {
+                        let value_str = match v {
+                            Value::String(s) => Some(s.clone()),
+                            Value::Bool(b) => Some(if *b { "yes" } else { "no" }.to_string()),
+                            Value::Number(n) => Some(n.to_string()),
+                            _ => None,
+                        };
+                        if let Some(vs) = value_str {
+                            if target_values.contains(&vs) {
+                                matches.insert(format!("{}={}", k, vs), true);
+                            }
+                        }
+                    }
+                    traverse(v, count_keys, key_to_values, counts, matches);
+                }
+            }
+            Value::Array(arr) => {
+                for v in arr {
+                    traverse(v, count_keys, key_to_values, counts, matches);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    for section in parsed.gamestate.values() {
+        traverse(section, &count_keys, &key_to_values, &mut counts, &mut matches);
+    }
+
+    (counts, matches)
+}
+
+/// Key two `MultiOp`s as the same sub-op iff they're the same variant with
+/// identical fields, via `Debug` (cheaper than adding `Eq`/`Hash` to every
+/// type a `MultiOp` field can hold, some of which carry `f64`s).
+fn multi_op_dedup_key(op: &MultiOp) -> String {
+    format!("{:?}", op)
+}
+
+/// Handle multi-op batch request - execute multiple operations in one request
+/// to reduce IPC round-trip overhead.
+///
+/// Returns results in the same order as the input operations, regardless of
+/// how each op was actually dispatched:
+/// - Sub-ops that are exact duplicates of an earlier one in the batch (same
+///   variant, same fields) compute once; every repeat just clones the first
+///   occurrence's result instead of re-running the scan.
+/// - `CountKeys`+`ContainsKv` together fuse into one `fused_count_and_kv_scan`
+///   pass instead of two independent full-tree walks.
+/// - The remaining CPU-bound, full-tree scans (`ExtractSections`, any
+///   unfused `CountKeys`/`ContainsKv`, `GetCountrySummaries`) run across a
+///   rayon thread pool, since `parsed` is immutable for the whole batch and
+///   `&ParsedSave` is therefore `Sync`. `max_concurrency` caps that pool to a
+///   scoped one of the given size instead of rayon's global default.
+/// - Everything else (entry/section lookups that share `section_offset_cache`
+///   to avoid re-scanning a section from the start) runs sequentially, so
+///   repeated ops against the same section already share that one scan.
+fn handle_multi_op(
+    parsed: &ParsedSave,
+    ops: Vec<MultiOp>,
+    max_concurrency: Option<usize>,
+) -> io::Result<()> {
+    let mut section_offset_cache: HashMap<String, usize> = HashMap::new();
+    let content = String::from_utf8_lossy(&parsed.gamestate_bytes);
+
+    // `duplicate_of[idx]` points a repeated sub-op back at the earlier index
+    // that will actually compute its (shared) result.
+    let mut first_seen: HashMap<String, usize> = HashMap::new();
+    let duplicate_of: Vec<Option<usize>> = ops
+        .iter()
+        .enumerate()
+        .map(|(idx, op)| {
+            let key = multi_op_dedup_key(op);
+            match first_seen.get(&key) {
+                Some(&first_idx) => Some(first_idx),
+                None => {
+                    first_seen.insert(key, idx);
+                    None
+                }
+            }
+        })
+        .collect();
+
+    let has_count_keys = ops.iter().any(|op| matches!(op, MultiOp::CountKeys { .. }));
+    let has_contains_kv = ops.iter().any(|op| matches!(op, MultiOp::ContainsKv { .. }));
+    let fused = (has_count_keys && has_contains_kv).then(|| fused_count_and_kv_scan(parsed, &ops));
+
+    let mut slots: Vec<Option<Value>> = (0..ops.len()).map(|_| None).collect();
+    let mut parallel_items: Vec<(usize, MultiOp)> = Vec::new();
+    let mut sequential_items: Vec<(usize, MultiOp)> = Vec::new();
+
+    for (idx, op) in ops.into_iter().enumerate() {
+        if duplicate_of[idx].is_some() {
+            continue;
+        }
+        match (&op, &fused) {
+            (MultiOp::CountKeys { keys }, Some((counts, _))) => {
+                let projected: HashMap<String, usize> = keys
+                    .iter()
+                    .map(|k| (k.clone(), counts.get(k).copied().unwrap_or(0)))
+                    .collect();
+                slots[idx] = Some(json!({ "ok": true, "counts": projected }));
+            }
+            (MultiOp::ContainsKv { pairs }, Some((_, matches))) => {
+                let projected: HashMap<String, bool> = pairs
+                    .iter()
+                    .map(|(k, v)| {
+                        let pair_key = format!("{}={}", k, v);
+                        let found = matches.get(&pair_key).copied().unwrap_or(false);
+                        (pair_key, found)
+                    })
+                    .collect();
+                slots[idx] = Some(json!({ "ok": true, "matches": projected }));
+            }
+            (
+                MultiOp::ExtractSections { .. }
+                | MultiOp::CountKeys { .. }
+                | MultiOp::ContainsKv { .. }
+                | MultiOp::GetCountrySummaries { .. },
+                _,
+            ) => parallel_items.push((idx, op)),
+            _ => sequential_items.push((idx, op)),
+        }
+    }
+
+    let run_parallel = |items: Vec<(usize, MultiOp)>| -> Vec<(usize, Value)> {
+        items
+            .into_par_iter()
+            .map(|(idx, op)| {
+                let mut local_cache = HashMap::new();
+                let tagged =
+                    tag_multi_op_result(run_multi_op(parsed, op, &content, &mut local_cache));
+                (idx, tagged)
+            })
+            .collect()
+    };
+    let scoped_pool = max_concurrency
+        .filter(|&n| n > 0)
+        .and_then(|n| rayon::ThreadPoolBuilder::new().num_threads(n).build().ok());
+    let parallel_results = match &scoped_pool {
+        Some(pool) => pool.install(|| run_parallel(parallel_items)),
+        None => run_parallel(parallel_items),
+    };
+    for (idx, value) in parallel_results {
+        slots[idx] = Some(value);
+    }
+
+    for (idx, op) in sequential_items {
+        let tagged =
+            tag_multi_op_result(run_multi_op(parsed, op, &content, &mut section_offset_cache));
+        slots[idx] = Some(tagged);
+    }
+
+    for (idx, first_idx) in duplicate_of.iter().enumerate() {
+        if let Some(first_idx) = first_idx {
+            slots[idx] = slots[*first_idx].clone();
+        }
+    }
+
+    let results: Vec<Value> = slots
+        .into_iter()
+        .map(|slot| {
+            slot.expect("every op index is filled by fused/parallel/sequential/duplicate dispatch")
+        })
+        .collect();
+
+    write_response(&SuccessResponse {
+        ok: true,
+        data: ResponseData::MultiResults { results },
+    })
+}
+
+/// Run a single `MultiOp` and return its result data, or an `OpError` if the
+/// op itself failed (as opposed to a legitimate "not found" result, which
+/// stays `Ok` with `found: false`). Split out of `handle_multi_op` so each
+/// arm can use `?` to propagate a failure without aborting the rest of the
+/// batch - `handle_multi_op` tags the outcome instead of unwrapping it.
+fn run_multi_op(
+    parsed: &ParsedSave,
+    op: MultiOp,
+    content: &str,
+    section_offset_cache: &mut HashMap<String, usize>,
+) -> Result<Value, OpError> {
+    let result = match op {
+            MultiOp::ExtractSections { sections } => {
+                let data = parsed.extract_sections(&sections);
+                json!({ "data": data })
+            }
+            MultiOp::GetEntry { section, key } => {
+                if let Some(Value::Object(map)) = parsed.gamestate.get(&section) {
+                    if let Some(entry_value) = map.get(&key) {
+                        json!({ "entry": entry_value, "found": true })
+                    } else {
+                        json!({ "entry": Value::Null, "found": false })
+                    }
+                } else {
+                    json!({ "entry": Value::Null, "found": false })
+                }
+            }
+            MultiOp::GetEntries {
+                section,
+                keys,
+                fields,
+            } => {
+                let mut entries: Vec<Value> = Vec::new();
+                if let Some(Value::Object(map)) = parsed.gamestate.get(&section) {
+                    for key in &keys {
+                        if let Some(entry_value) = map.get(key) {
+                            let projected = if let Some(ref field_list) = fields {
+                                if let Value::Object(entry_obj) = entry_value {
+                                    let mut projected_obj = Map::new();
+                                    projected_obj.insert("_key".to_string(), json!(key));
+                                    for field in field_list {
+                                        if let Some(field_value) = entry_obj.get(field) {
+                                            projected_obj
+                                                .insert(field.clone(), field_value.clone());
+                                        }
+                                    }
+                                    Value::Object(projected_obj)
+                                } else {
+                                    let mut obj = Map::new();
+                                    obj.insert("_key".to_string(), json!(key));
+                                    obj.insert("_value".to_string(), entry_value.clone());
+                                    Value::Object(obj)
+                                }
+                            } else {
+                                let mut obj = Map::new();
+                                obj.insert("_key".to_string(), json!(key));
+                                obj.insert("_value".to_string(), entry_value.clone());
+                                Value::Object(obj)
+                            };
+                            entries.push(projected);
+                        }
+                    }
+                }
+                json!({ "entries": entries })
+            }
+            MultiOp::CountKeys { keys } => {
+                use std::collections::HashSet;
+                let key_set: HashSet<&str> = keys.iter().map(|s| s.as_str()).collect();
+                let mut counts: HashMap<String, usize> =
+                    keys.iter().map(|k| (k.clone(), 0)).collect();
+
+                fn traverse(
+                    value: &Value,
+                    key_set: &HashSet<&str>,
+                    counts: &mut HashMap<String, usize>,
+                ) {
+                    match value {
+                        Value::Object(map) => {
+                            for (k, v) in map {
+                                if key_set.contains(k.as_str()) {
+                                    *counts.get_mut(k.as_str()).unwrap() += 1;
+                                }
+                                traverse(v, key_set, counts);
+                            }
+                        }
+                        Value::Array(arr) => {
+                            for v in arr {
+                                traverse(v, key_set, counts);
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+
+                for section in parsed.gamestate.values() {
+                    traverse(section, &key_set, &mut counts);
+                }
+                json!({ "counts": counts })
+            }
+            MultiOp::ContainsTokens { tokens } => {
+                let mut matches: HashMap<String, bool> =
+                    tokens.iter().map(|t| (t.clone(), false)).collect();
+                if !tokens.is_empty() {
+                    let ac = AhoCorasick::new(&tokens).map_err(|e| {
+                        OpError::bad_request(format!(
+                            "Failed to build token automaton: {}",
+                            e
+                        ))
+                    })?;
+                    for mat in ac.find_iter(&parsed.gamestate_bytes[..]) {
+                        let pattern_idx = mat.pattern().as_usize();
+                        if pattern_idx < tokens.len() {
+                            matches.insert(tokens[pattern_idx].clone(), true);
+                        }
+                    }
+                }
+                json!({ "matches": matches })
+            }
+            MultiOp::ContainsKv { pairs } => {
+                use std::collections::HashSet;
+                let mut key_to_values: HashMap<String, HashSet<String>> = HashMap::new();
+                for (key, value) in &pairs {
+                    key_to_values
+                        .entry(key.clone())
+                        .or_default()
+                        .insert(value.clone());
+                }
+                let mut matches: HashMap<String, bool> = pairs
+                    .iter()
+                    .map(|(k, v)| (format!("{}={}", k, v), false))
+                    .collect();
+
+                fn traverse_kv(
+                    value: &Value,
+                    key_to_values: &HashMap<String, HashSet<String>>,
+                    matches: &mut HashMap<String, bool>,
+                ) {
+                    match value {
+                        Value::Object(map) => {
+                            for (k, v) in map {
+                                if let Some(target_values) = key_to_values.get(k.as_str()) {
+                                    let value_str = match v {
+                                        Value::String(s) => Some(s.as_str()),
+                                        Value::Number(_) => None,
+                                        Value::Bool(b) => {
+                                            if *b {
+                                                Some("yes")
+                                            } else {
+                                                Some("no")
+                                            }
+                                        }
+                                        _ => None,
+                                    };
+                                    if let Some(vs) = value_str {
+                                        if target_values.contains(vs) {
+                                            matches.insert(format!("{}={}", k, vs), true);
+                                        }
+                                    }
+                                    if let Value::Number(n) = v {
+                                        let num_str = n.to_string();
+                                        if target_values.contains(&num_str) {
+                                            matches.insert(format!("{}={}", k, num_str), true);
+                                        }
+                                    }
+                                }
+                                traverse_kv(v, key_to_values, matches);
+                            }
+                        }
+                        Value::Array(arr) => {
+                            for v in arr {
+                                traverse_kv(v, key_to_values, matches);
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+
+                for section in parsed.gamestate.values() {
+                    traverse_kv(section, &key_to_values, &mut matches);
+                }
+                json!({ "matches": matches })
+            }
+            MultiOp::GetCountrySummaries { fields } => {
+                let mut countries: Vec<Value> = Vec::new();
+                if let Some(Value::Object(country_map)) = parsed.gamestate.get("country") {
+                    for (country_id, country_data) in country_map {
+                        let mut summary = Map::new();
+                        summary.insert("id".to_string(), json!(country_id));
+                        if let Value::Object(country_obj) = country_data {
+                            for field in &fields {
+                                if let Some(value) = country_obj.get(field) {
+                                    summary.insert(field.clone(), value.clone());
+                                }
+                            }
+                        }
+                        countries.push(Value::Object(summary));
+                    }
+                }
+                json!({ "countries": countries })
+            }
+            MultiOp::GetDuplicateValues {
+                section,
+                key,
+                field,
+            } => {
+                // Use cached section offset if available to avoid re-scanning 84MB gamestate
+                let cached_offset = section_offset_cache.get(&section).copied();
+                let (values, found, new_offset) = multi_field_values(
+                    &parsed.gamestate_bytes,
+                    &section,
+                    &key,
+                    &field,
+                    cached_offset,
+                )
+                .map_err(OpError::from)?;
+                if let Some(offset) = new_offset {
+                    section_offset_cache.insert(section.clone(), offset);
+                }
+                let values: Vec<String> = values.iter().map(value_to_display_string).collect();
+                json!({ "values": values, "found": found })
+            }
+            MultiOp::GetMultiField {
+                section,
+                key,
+                field,
+            } => {
+                let cached_offset = section_offset_cache.get(&section).copied();
+                let (values, found, new_offset) = multi_field_values(
+                    &parsed.gamestate_bytes,
+                    &section,
+                    &key,
+                    &field,
+                    cached_offset,
+                )
+                .map_err(OpError::from)?;
+                if let Some(offset) = new_offset {
+                    section_offset_cache.insert(section.clone(), offset);
+                }
+                json!({ "values": values, "found": found })
+            }
+            MultiOp::GetEntryFields { section, key } => {
+                let cached_offset = section_offset_cache.get(&section).copied();
+                let (fields, found, new_offset) =
+                    entry_fields(&parsed.gamestate_bytes, &section, &key, cached_offset)
+                        .map_err(OpError::from)?;
+                if let Some(offset) = new_offset {
+                    section_offset_cache.insert(section.clone(), offset);
+                }
+                json!({ "fields": fields, "found": found })
+            }
+            MultiOp::GetEntryText { section, key } => {
+                let (text, found) = extract_entry_text(content, &section, &key, None);
+                json!({ "text": text, "found": found })
+            }
+            MultiOp::GetAllEntryTexts { section, key } => {
+                let (texts, found) = extract_entry_text_all(content, &section, &key, None);
+                json!({ "texts": texts, "found": found })
+            }
+            MultiOp::GetAllDuplicateValues {
+                section,
+                key,
+                field,
+            } => {
+                let cached_offset = section_offset_cache.get(&section).copied();
+                let (occurrences, found, new_offset) = multi_field_values_all(
+                    &parsed.gamestate_bytes,
+                    &section,
+                    &key,
+                    &field,
+                    cached_offset,
+                )
+                .map_err(OpError::from)?;
+                if let Some(offset) = new_offset {
+                    section_offset_cache.insert(section.clone(), offset);
+                }
+                let occurrences: Vec<Vec<String>> = occurrences
+                    .iter()
+                    .map(|values| values.iter().map(value_to_display_string).collect())
+                    .collect();
+                json!({ "occurrences": occurrences, "found": found })
+            }
+            MultiOp::QueryEntries {
+                section,
+                filter,
+                sort,
+                limit,
+                fields,
+            } => {
+                let entries = query_entries(parsed, &section, &filter, &sort, limit, &fields);
+                json!({ "entries": entries })
+            }
+            MultiOp::Search {
+                terms,
+                top_k,
+                section,
+            } => {
+                let results = search_entries(
+                    &parsed.search_index,
+                    &parsed.term_bk_tree,
+                    &terms,
+                    top_k,
+                    &section,
+                );
+                json!({ "results": results })
+            }
+            MultiOp::Query {
+                section,
+                filters,
+                sort,
+                offset,
+                limit,
+                fields,
+            } => {
+                let (entries, total, next_offset) =
+                    query_page(parsed, &section, &filters, &sort, offset, limit, &fields);
+                json!({ "entries": entries, "total": total, "next_offset": next_offset })
+            }
+            MultiOp::JsonPath { path } => {
+                let matches = eval_json_path(&parsed.gamestate, &path).map_err(OpError::bad_request)?;
+                json!({ "matches": matches })
+            }
+            MultiOp::FilterEntries { section, filter } => {
+                let ast = parse_filter_expr(&filter).map_err(OpError::bad_filter)?;
+                let entries = filter_entries(parsed, &section, &ast);
+                json!({ "entries": entries })
+            }
+            MultiOp::SortEntries {
+                section,
+                field,
+                order,
+            } => {
+                let keys = sort_entries(parsed, &section, &field, order)?;
+                json!({ "keys": keys })
+            }
+    };
+    Ok(result)
+}
+
+/// A job submitted to the `WorkerPool`: run a read-only op and write its
+/// response, with the correlation id already installed.
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// A small fixed-size pool of worker threads sharing one job queue, used to
+/// dispatch read-only ops against a shared, immutable `Arc<ParsedSave>` so
+/// independent queries can complete out of order instead of serializing
+/// behind the stdin loop.
+struct WorkerPool {
+    sender: mpsc::Sender<Job>,
+    /// (outstanding job count, notified when it drops to zero) so `barrier`
+    /// can wait for every previously submitted job to finish.
+    pending: Arc<(Mutex<usize>, Condvar)>,
+}
+
+impl WorkerPool {
+    /// Spawn `size` worker threads sharing one job queue.
+    fn new(size: usize) -> Self {
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let pending = Arc::new((Mutex::new(0usize), Condvar::new()));
+
+        for _ in 0..size {
+            let receiver = Arc::clone(&receiver);
+            thread::spawn(move || loop {
+                let job = receiver.lock().unwrap().recv();
+                match job {
+                    Ok(job) => job(),
+                    Err(_) => break, // sender dropped, pool shutting down
+                }
+            });
+        }
+
+        Self { sender, pending }
+    }
+
+    /// Submit a job to run on whichever worker is free next.
+    fn submit(&self, job: Job) {
+        {
+            let (count, _) = &*self.pending;
+            *count.lock().unwrap() += 1;
+        }
+
+        let pending = Arc::clone(&self.pending);
+        let wrapped: Job = Box::new(move || {
+            job();
+            let (count, cvar) = &*pending;
+            let mut count = count.lock().unwrap();
+            *count -= 1;
+            if *count == 0 {
+                cvar.notify_all();
+            }
+        });
+
+        // If every worker has panicked and the channel is closed, run the
+        // job inline rather than silently dropping the response.
+        if let Err(mpsc::SendError(wrapped)) = self.sender.send(wrapped) {
+            wrapped();
+        }
+    }
+
+    /// Block until every previously submitted job has finished, so the
+    /// caller can write to stdout without interleaving with a pooled
+    /// response still in flight.
+    fn barrier(&self) {
+        let (count, cvar) = &*self.pending;
+        let guard = count.lock().unwrap();
+        let _guard = cvar.wait_while(guard, |count| *count > 0).unwrap();
+    }
+}
+
+/// Default worker pool size: the number of available cores, capped so a
+/// pathological host doesn't spawn an unbounded number of threads.
+fn worker_pool_size() -> usize {
+    std::thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(4)
+        .min(8)
+}
+
+/// Main serve loop
+pub fn run(
+    path: &str,
+    tokens_path: Option<&str>,
+    cache_dir: Option<&str>,
+    cache_enabled: bool,
+) -> Result<()> {
+    // Log startup to stderr (stdout is reserved for protocol)
+    eprintln!(
+        "[serve] Loading save file: {} (tool_version={})",
+        path, TOOL_VERSION
+    );
+
+    // Load and parse the save file once
+    let parsed = match ParsedSave::load(path, tokens_path, cache_dir, cache_enabled) {
+        Ok(p) => {
+            eprintln!("[serve] Save loaded successfully, entering request loop");
+            p
+        }
+        Err(e) => {
+            // Write error response and exit directly (don't propagate to main error handler)
+            let message = format!("{:#}", e);
+            let kind = e
+                .chain()
+                .find_map(|cause| cause.downcast_ref::<CompanionError>())
+                .map(CompanionError::kind)
+                .unwrap_or(ErrorKind::ParseError);
+            let exit_code = kind.exit_code();
+            let _ = write_error(kind.error_type(), &message, exit_code);
+            std::process::exit(exit_code);
+        }
+    };
+    let poll_state = Mutex::new(PollState::new(path, parsed.gamestate.clone()));
+    let parsed = Arc::new(parsed);
+    let pool = WorkerPool::new(worker_pool_size());
+
+    // Enter stdin read loop
+    let stdin = io::stdin();
+    let reader = stdin.lock();
+
+    for line in reader.lines() {
+        let line = match line {
+            Ok(l) => l,
+            Err(e) => {
+                eprintln!("[serve] Error reading stdin: {}", e);
+                break;
+            }
+        };
+
+        // Empty line or EOF
+        if line.is_empty() {
+            continue;
+        }
+
+        // Parse the request
+        let envelope: RequestEnvelope = match serde_json::from_str(&line) {
+            Ok(r) => r,
+            Err(e) => {
+                let _ = write_op_error(&OpError::from_request_parse_error(&e));
+                continue;
+            }
+        };
+        let RequestEnvelope { id, request } = envelope;
+
+        // Read-only ops are dispatched to the worker pool so independent
+        // queries can complete out of order; everything else (streaming and
+        // session lifecycle) stays on the main thread so it's never
+        // interleaved with a pooled response still in flight.
+        let result: io::Result<()> = match request {
+            Request::ExtractSections { sections } => {
+                let parsed = Arc::clone(&parsed);
+                pool.submit(Box::new(move || {
+                    with_request_id(id, || {
+                        if let Err(e) = handle_extract_sections(&parsed, sections) {
+                            eprintln!("[serve] Error writing response: {}", e);
+                        }
+                    });
+                }));
+                Ok(())
+            }
+            Request::GetEntry { section, key } => {
+                let parsed = Arc::clone(&parsed);
+                pool.submit(Box::new(move || {
+                    with_request_id(id, || {
+                        if let Err(e) = handle_get_entry(&parsed, section, key) {
+                            eprintln!("[serve] Error writing response: {}", e);
+                        }
+                    });
+                }));
+                Ok(())
+            }
+            Request::GetEntries {
+                section,
+                keys,
+                fields,
+            } => {
+                let parsed = Arc::clone(&parsed);
+                pool.submit(Box::new(move || {
+                    with_request_id(id, || {
+                        if let Err(e) = handle_get_entries(&parsed, section, keys, fields) {
+                            eprintln!("[serve] Error writing response: {}", e);
+                        }
+                    });
+                }));
+                Ok(())
+            }
+            Request::CountKeys { keys } => {
+                let parsed = Arc::clone(&parsed);
+                pool.submit(Box::new(move || {
+                    with_request_id(id, || {
+                        if let Err(e) = handle_count_keys(&parsed, keys) {
+                            eprintln!("[serve] Error writing response: {}", e);
+                        }
+                    });
+                }));
+                Ok(())
+            }
+            Request::ContainsTokens { tokens } => {
+                let parsed = Arc::clone(&parsed);
+                pool.submit(Box::new(move || {
+                    with_request_id(id, || {
+                        if let Err(e) = handle_contains_tokens(&parsed.gamestate_bytes, tokens) {
+                            eprintln!("[serve] Error writing response: {}", e);
+                        }
+                    });
+                }));
+                Ok(())
+            }
+            Request::ContainsKv { pairs } => {
+                let parsed = Arc::clone(&parsed);
+                pool.submit(Box::new(move || {
+                    with_request_id(id, || {
+                        if let Err(e) = handle_contains_kv(&parsed, pairs) {
+                            eprintln!("[serve] Error writing response: {}", e);
+                        }
+                    });
+                }));
+                Ok(())
+            }
+            Request::GetCountrySummaries { fields } => {
+                let parsed = Arc::clone(&parsed);
+                pool.submit(Box::new(move || {
+                    with_request_id(id, || {
+                        if let Err(e) = handle_get_country_summaries(&parsed, fields) {
+                            eprintln!("[serve] Error writing response: {}", e);
+                        }
+                    });
+                }));
+                Ok(())
+            }
+            Request::Search {
+                terms,
+                top_k,
+                section,
+            } => {
+                let parsed = Arc::clone(&parsed);
+                pool.submit(Box::new(move || {
+                    with_request_id(id, || {
+                        if let Err(e) = handle_search(&parsed, terms, top_k, section) {
+                            eprintln!("[serve] Error writing response: {}", e);
+                        }
+                    });
+                }));
+                Ok(())
+            }
+            Request::GetDuplicateValues {
+                section,
+                key,
+                field,
+            } => with_request_id(id, || {
+                handle_get_duplicate_values(&parsed.gamestate_bytes, section, key, field)
+            }),
+            Request::GetMultiField {
+                section,
+                key,
+                field,
+            } => with_request_id(id, || {
+                handle_get_multi_field(&parsed.gamestate_bytes, section, key, field)
+            }),
+            Request::GetEntryFields { section, key } => with_request_id(id, || {
+                handle_get_entry_fields(&parsed.gamestate_bytes, section, key)
+            }),
+            Request::GetEntryText { section, key } => with_request_id(id, || {
+                handle_get_entry_text(&parsed.gamestate_bytes, section, key)
+            }),
+            Request::GetAllEntryTexts { section, key } => with_request_id(id, || {
+                handle_get_all_entry_texts(&parsed.gamestate_bytes, section, key)
+            }),
+            Request::GetAllDuplicateValues {
+                section,
+                key,
+                field,
+            } => with_request_id(id, || {
+                handle_get_all_duplicate_values(&parsed.gamestate_bytes, section, key, field)
+            }),
+            Request::QueryEntries {
+                section,
+                filter,
+                sort,
+                limit,
+                fields,
+            } => with_request_id(id, || {
+                handle_query_entries(&parsed, section, filter, sort, limit, fields)
+            }),
+            Request::Query {
+                section,
+                filters,
+                sort,
+                offset,
+                limit,
+                fields,
+            } => with_request_id(id, || {
+                handle_query(&parsed, section, filters, sort, offset, limit, fields)
+            }),
+            Request::JsonPath { path } => {
+                with_request_id(id, || handle_json_path(&parsed, path))
+            }
+            Request::FilterEntries { section, filter } => {
+                with_request_id(id, || handle_filter_entries(&parsed, section, filter))
+            }
+            Request::SortEntries {
+                section,
+                field,
+                order,
+            } => with_request_id(id, || handle_sort_entries(&parsed, section, field, order)),
+            Request::Multi { ops, max_concurrency } => {
+                with_request_id(id, || handle_multi_op(&parsed, ops, max_concurrency))
+            }
+            Request::Diff {
+                other_path,
+                section,
+            } => {
+                // Loads a second save from disk, so run it on the main
+                // thread like IterSection rather than the pool.
+                pool.barrier();
+                with_request_id(id, || {
+                    handle_diff(
+                        &parsed,
+                        other_path,
+                        section,
+                        tokens_path,
+                        cache_dir,
+                        cache_enabled,
+                    )
+                })
+            }
+            Request::Poll { token, sections } => {
+                // Mutates shared poll state and may reload the save, so run
+                // it on the main thread like Diff rather than the pool.
+                pool.barrier();
+                with_request_id(id, || {
+                    handle_poll(&poll_state, path, tokens_path, cache_dir, token, sections)
+                })
+            }
+            Request::IterSection {
+                section,
+                batch_size,
+            } => {
+                // Wait for any in-flight pooled responses to be written
+                // first so stream entries are never interleaved with them.
+                pool.barrier();
+                with_request_id(id, || handle_iter_section(&parsed, section, batch_size))
+            }
+            Request::Close => {
+                pool.barrier();
+                eprintln!("[serve] Received close request, shutting down");
+                with_request_id(id, || {
+                    write_response(&SuccessResponse {
+                        ok: true,
+                        data: ResponseData::Closed { closed: true },
+                    })
+                })?;
+                break;
+            }
+        };
+
+        if let Err(e) = result {
+            eprintln!("[serve] Error writing response: {}", e);
+            break;
+        }
+    }
+
+    // Let any pooled jobs still writing their response finish before the
+    // session ends (and the process exits).
+    pool.barrier();
+    eprintln!("[serve] Session ended");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_parsing() {
+        let json = r#"{"op": "extract_sections", "sections": ["meta", "player"]}"#;
+        let req: Request = serde_json::from_str(json).unwrap();
+        match req {
+            Request::ExtractSections { sections } => {
+                assert_eq!(sections, vec!["meta", "player"]);
+            }
+            _ => panic!("Wrong request type"),
+        }
+    }
+
+    #[test]
+    fn test_iter_section_request() {
+        let json = r#"{"op": "iter_section", "section": "country"}"#;
+        let req: Request = serde_json::from_str(json).unwrap();
+        match req {
+            Request::IterSection {
+                section,
+                batch_size,
+            } => {
+                assert_eq!(section, "country");
+                assert_eq!(batch_size, 100); // Default batch size
+            }
+            _ => panic!("Wrong request type"),
+        }
+    }
+
+    #[test]
+    fn test_iter_section_request_with_batch_size() {
+        let json = r#"{"op": "iter_section", "section": "country", "batch_size": 50}"#;
+        let req: Request = serde_json::from_str(json).unwrap();
+        match req {
+            Request::IterSection {
+                section,
+                batch_size,
+            } => {
+                assert_eq!(section, "country");
+                assert_eq!(batch_size, 50);
+            }
+            _ => panic!("Wrong request type"),
+        }
+    }
+
+    #[test]
+    fn test_get_entry_request() {
+        let json = r#"{"op": "get_entry", "section": "country", "key": "0"}"#;
+        let req: Request = serde_json::from_str(json).unwrap();
+        match req {
+            Request::GetEntry { section, key } => {
+                assert_eq!(section, "country");
+                assert_eq!(key, "0");
+            }
+            _ => panic!("Wrong request type"),
+        }
+    }
+
+    #[test]
+    fn test_close_request() {
+        let json = r#"{"op": "close"}"#;
+        let req: Request = serde_json::from_str(json).unwrap();
+        assert!(matches!(req, Request::Close));
+    }
+
+    #[test]
+    fn test_get_entries_request() {
+        let json = r#"{"op": "get_entries", "section": "country", "keys": ["0", "1", "2"]}"#;
+        let req: Request = serde_json::from_str(json).unwrap();
+        match req {
+            Request::GetEntries {
+                section,
+                keys,
+                fields,
+            } => {
+                assert_eq!(section, "country");
+                assert_eq!(keys, vec!["0", "1", "2"]);
+                assert!(fields.is_none());
+            }
+            _ => panic!("Wrong request type"),
+        }
+    }
+
+    #[test]
+    fn test_get_entries_request_with_fields() {
+        let json = r#"{"op": "get_entries", "section": "country", "keys": ["0"], "fields": ["name", "type"]}"#;
+        let req: Request = serde_json::from_str(json).unwrap();
+        match req {
+            Request::GetEntries {
+                section,
+                keys,
+                fields,
+            } => {
+                assert_eq!(section, "country");
+                assert_eq!(keys, vec!["0"]);
+                assert_eq!(fields, Some(vec!["name".to_string(), "type".to_string()]));
+            }
+            _ => panic!("Wrong request type"),
+        }
+    }
+
+    #[test]
+    fn test_count_keys_request() {
+        let json = r#"{"op": "count_keys", "keys": ["name", "type", "flag"]}"#;
+        let req: Request = serde_json::from_str(json).unwrap();
+        match req {
+            Request::CountKeys { keys } => {
+                assert_eq!(keys, vec!["name", "type", "flag"]);
+            }
+            _ => panic!("Wrong request type"),
+        }
+    }
+
+    #[test]
+    fn test_error_response_serialization() {
+        let err = ErrorResponse::new("SectionNotFound", "Section 'foo' not found", 2);
+        let json = serde_json::to_string(&err).unwrap();
+        assert!(json.contains(r#""ok":false"#));
+        assert!(json.contains(r#""error":"SectionNotFound""#));
+    }
+
+    #[test]
+    fn test_contains_tokens_request() {
+        let json = r#"{"op": "contains_tokens", "tokens": ["country", "fleet", "xyz123"]}"#;
+        let req: Request = serde_json::from_str(json).unwrap();
+        match req {
+            Request::ContainsTokens { tokens } => {
+                assert_eq!(tokens, vec!["country", "fleet", "xyz123"]);
+            }
+            _ => panic!("Wrong request type"),
+        }
+    }
+
+    #[test]
+    fn test_get_country_summaries_request() {
+        let json = r#"{"op": "get_country_summaries", "fields": ["name", "type", "flag"]}"#;
+        let req: Request = serde_json::from_str(json).unwrap();
+        match req {
+            Request::GetCountrySummaries { fields } => {
+                assert_eq!(fields, vec!["name", "type", "flag"]);
+            }
+            _ => panic!("Wrong request type"),
+        }
+    }
+
+    #[test]
+    fn test_contains_kv_request() {
+        let json =
+            r#"{"op": "contains_kv", "pairs": [["war_in_heaven", "yes"], ["version", "3"]]}"#;
+        let req: Request = serde_json::from_str(json).unwrap();
+        match req {
+            Request::ContainsKv { pairs } => {
+                assert_eq!(pairs.len(), 2);
+                assert_eq!(pairs[0], ("war_in_heaven".to_string(), "yes".to_string()));
+                assert_eq!(pairs[1], ("version".to_string(), "3".to_string()));
+            }
+            _ => panic!("Wrong request type"),
+        }
+    }
+
+    #[test]
+    fn test_get_duplicate_values_request() {
+        let json = r#"{"op": "get_duplicate_values", "section": "leaders", "key": "123", "field": "traits"}"#;
+        let req: Request = serde_json::from_str(json).unwrap();
+        match req {
+            Request::GetDuplicateValues {
+                section,
+                key,
+                field,
+            } => {
+                assert_eq!(section, "leaders");
+                assert_eq!(key, "123");
+                assert_eq!(field, "traits");
             }
-            MultiOp::GetDuplicateValues {
+            _ => panic!("Wrong request type"),
+        }
+    }
+
+    #[test]
+    fn test_get_multi_field_request() {
+        let json = r#"{"op": "get_multi_field", "section": "leaders", "key": "123", "field": "traits"}"#;
+        let req: Request = serde_json::from_str(json).unwrap();
+        match req {
+            Request::GetMultiField {
                 section,
                 key,
                 field,
             } => {
-                // Use cached section offset if available to avoid re-scanning 84MB gamestate
-                let cached_offset = section_offset_cache.get(&section).copied();
-                let (values, found, new_offset) =
-                    extract_duplicate_values(&content, &section, &key, &field, cached_offset);
-                // Cache the section offset for future ops in this batch
-                if let Some(offset) = new_offset {
-                    section_offset_cache.insert(section.clone(), offset);
-                }
-                json!({ "values": values, "found": found })
+                assert_eq!(section, "leaders");
+                assert_eq!(key, "123");
+                assert_eq!(field, "traits");
             }
-            MultiOp::GetEntryText { section, key } => {
-                let (text, found) = extract_entry_text(&content, &section, &key, None);
-                json!({ "text": text, "found": found })
+            _ => panic!("Wrong request type"),
+        }
+    }
+
+    #[test]
+    fn test_get_entry_fields_request() {
+        let json = r#"{"op": "get_entry_fields", "section": "leaders", "key": "123"}"#;
+        let req: Request = serde_json::from_str(json).unwrap();
+        match req {
+            Request::GetEntryFields { section, key } => {
+                assert_eq!(section, "leaders");
+                assert_eq!(key, "123");
             }
-        };
-        results.push(result);
+            _ => panic!("Wrong request type"),
+        }
     }
 
-    write_response(&SuccessResponse {
-        ok: true,
-        data: ResponseData::MultiResults { results },
-    })
-}
+    #[test]
+    fn test_find_entry_span_is_quote_aware() {
+        let gamestate = b"\nleaders=\n{\n\t123=\n\t{\n\t\tname=\"Fortress {Alpha}\"\n\t\ttraits={\n\t\t\ttrait=\"trait_x\"\n\t\t}\n\t}\n}\n";
+        let section_start = find_section_content_start(gamestate, "leaders", None).unwrap();
+        let span = find_entry_span(gamestate, section_start, "123").unwrap();
+        let entry = &gamestate[span];
+        assert!(entry.starts_with(b"{"));
+        assert!(entry.ends_with(b"}"));
+        assert!(String::from_utf8_lossy(entry).contains("trait_x"));
+    }
 
-/// Main serve loop
-pub fn run(path: &str) -> Result<()> {
-    // Log startup to stderr (stdout is reserved for protocol)
-    eprintln!(
-        "[serve] Loading save file: {} (tool_version={})",
-        path, TOOL_VERSION
-    );
+    #[test]
+    fn test_multi_field_values_extracts_duplicates() {
+        let gamestate = b"\nleaders=\n{\n\t123=\n\t{\n\t\ttraits={\n\t\t\ttrait=\"trait_x\"\n\t\t\ttrait=\"trait_y\"\n\t\t}\n\t}\n}\n";
+        let (values, found, _) = multi_field_values(gamestate, "leaders", "123", "trait", None)
+            .expect("well-formed entry should parse");
+        assert!(found);
+        assert_eq!(values, vec![json!("trait_x"), json!("trait_y")]);
+    }
 
-    // Load and parse the save file once
-    let parsed = match ParsedSave::load(path) {
-        Ok(p) => {
-            eprintln!("[serve] Save loaded successfully, entering request loop");
-            p
-        }
-        Err(e) => {
-            // Write error response and exit directly (don't propagate to main error handler)
-            let message = format!("{:#}", e);
-            let exit_code =
-                if message.contains("Failed to open file") || message.contains("No such file") {
-                    ErrorKind::FileNotFound.exit_code()
-                } else {
-                    ErrorKind::ParseError.exit_code()
-                };
-            let _ = write_error("ParseError", &message, exit_code);
-            std::process::exit(exit_code);
-        }
-    };
+    #[test]
+    fn test_find_entry_spans_returns_every_duplicate_key_occurrence() {
+        let gamestate = b"\nrelations=\n{\n\trelation=\n\t{\n\t\tcountry=1\n\t}\n\trelation=\n\t{\n\t\tcountry=2\n\t}\n}\n";
+        let section_start = find_section_content_start(gamestate, "relations", None).unwrap();
+        let spans = find_entry_spans(gamestate, section_start, "relation");
+        assert_eq!(spans.len(), 2);
+        assert!(String::from_utf8_lossy(&gamestate[spans[0].clone()]).contains("country=1"));
+        assert!(String::from_utf8_lossy(&gamestate[spans[1].clone()]).contains("country=2"));
+    }
 
-    // Enter stdin read loop
-    let stdin = io::stdin();
-    let reader = stdin.lock();
+    #[test]
+    fn test_multi_field_values_all_extracts_one_list_per_occurrence() {
+        let gamestate = b"\nrelations=\n{\n\trelation=\n\t{\n\t\ttrait=\"a\"\n\t}\n\trelation=\n\t{\n\t\ttrait=\"b\"\n\t\ttrait=\"c\"\n\t}\n}\n";
+        let (occurrences, found, _) =
+            multi_field_values_all(gamestate, "relations", "relation", "trait", None)
+                .expect("well-formed entries should parse");
+        assert!(found);
+        assert_eq!(occurrences, vec![vec![json!("a")], vec![json!("b"), json!("c")]]);
+    }
 
-    for line in reader.lines() {
-        let line = match line {
-            Ok(l) => l,
-            Err(e) => {
-                eprintln!("[serve] Error reading stdin: {}", e);
-                break;
-            }
-        };
+    #[test]
+    fn test_extract_entry_text_ignores_braces_inside_quotes() {
+        let content = "\nleaders=\n{\n\t123=\n\t{\n\t\tname=\"Fortress {Alpha}\"\n\t}\n}\n";
+        let (text, found) = extract_entry_text(content, "leaders", "123", None);
+        assert!(found);
+        assert!(text.ends_with("}\n") || text.ends_with('}'));
+        assert!(text.contains("Fortress {Alpha}"));
+    }
 
-        // Empty line or EOF
-        if line.is_empty() {
-            continue;
-        }
+    #[test]
+    fn test_extract_entry_text_all_returns_every_occurrence() {
+        let content =
+            "\nrelations=\n{\n\trelation=\n\t{\n\t\tcountry=1\n\t}\n\trelation=\n\t{\n\t\tcountry=2\n\t}\n}\n";
+        let (texts, found) = extract_entry_text_all(content, "relations", "relation", None);
+        assert!(found);
+        assert_eq!(texts.len(), 2);
+        assert!(texts[0].contains("country=1"));
+        assert!(texts[1].contains("country=2"));
+    }
 
-        // Parse the request
-        let request: Request = match serde_json::from_str(&line) {
-            Ok(r) => r,
-            Err(e) => {
-                let _ = write_error(
-                    "InvalidRequest",
-                    &format!("Failed to parse request: {}", e),
-                    ErrorKind::InvalidArgument.exit_code(),
-                );
-                continue;
+    #[test]
+    fn test_get_all_entry_texts_request_parses() {
+        let json = r#"{"op": "get_all_entry_texts", "section": "relations", "key": "relation"}"#;
+        let req: Request = serde_json::from_str(json).unwrap();
+        match req {
+            Request::GetAllEntryTexts { section, key } => {
+                assert_eq!(section, "relations");
+                assert_eq!(key, "relation");
             }
-        };
+            _ => panic!("Wrong request type"),
+        }
+    }
 
-        // Handle the request
-        let result = match request {
-            Request::ExtractSections { sections } => handle_extract_sections(&parsed, sections),
-            Request::IterSection {
-                section,
-                batch_size,
-            } => handle_iter_section(&parsed, section, batch_size),
-            Request::GetEntry { section, key } => handle_get_entry(&parsed, section, key),
-            Request::GetEntries {
-                section,
-                keys,
-                fields,
-            } => handle_get_entries(&parsed, section, keys, fields),
-            Request::CountKeys { keys } => handle_count_keys(&parsed, keys),
-            Request::ContainsTokens { tokens } => {
-                handle_contains_tokens(&parsed.gamestate_bytes, tokens)
-            }
-            Request::ContainsKv { pairs } => handle_contains_kv(&parsed, pairs),
-            Request::GetCountrySummaries { fields } => {
-                handle_get_country_summaries(&parsed, fields)
-            }
-            Request::GetDuplicateValues {
+    #[test]
+    fn test_get_all_duplicate_values_request_parses() {
+        let json = r#"{"op": "get_all_duplicate_values", "section": "relations", "key": "relation", "field": "trait"}"#;
+        let req: Request = serde_json::from_str(json).unwrap();
+        match req {
+            Request::GetAllDuplicateValues {
                 section,
                 key,
                 field,
-            } => handle_get_duplicate_values(&parsed.gamestate_bytes, section, key, field),
-            Request::GetEntryText { section, key } => {
-                handle_get_entry_text(&parsed.gamestate_bytes, section, key)
-            }
-            Request::Multi { ops } => handle_multi_op(&parsed, ops),
-            Request::Close => {
-                eprintln!("[serve] Received close request, shutting down");
-                write_response(&SuccessResponse {
-                    ok: true,
-                    data: ResponseData::Closed { closed: true },
-                })?;
-                break;
+            } => {
+                assert_eq!(section, "relations");
+                assert_eq!(key, "relation");
+                assert_eq!(field, "trait");
             }
+            _ => panic!("Wrong request type"),
+        }
+    }
+
+    #[test]
+    fn test_op_error_from_companion_error_maps_parse_failed() {
+        let err = CompanionError::ParseError {
+            source: anyhow::anyhow!("boom"),
+            decode_offset: None,
         };
+        let op_err = OpError::from(err);
+        assert!(matches!(op_err.code, OpErrorCode::ParseFailed));
+    }
 
-        if let Err(e) = result {
-            eprintln!("[serve] Error writing response: {}", e);
-            break;
+    #[test]
+    fn test_op_error_from_companion_error_maps_unknown_token() {
+        let err = CompanionError::UnknownToken { token_id: 0x1234 };
+        let op_err = OpError::from(err);
+        assert!(matches!(op_err.code, OpErrorCode::BinaryTokenUnknown));
+    }
+
+    #[test]
+    fn test_op_error_from_companion_error_maps_invalid_argument_to_bad_request() {
+        let err = CompanionError::InvalidArgument {
+            detail: "bad field".to_string(),
+        };
+        let op_err = OpError::from(err);
+        assert!(matches!(op_err.code, OpErrorCode::BadRequest));
+    }
+
+    #[test]
+    fn test_request_parse_error_classifies_unknown_op() {
+        let err = serde_json::from_str::<Request>(r#"{"op": "not_a_real_op"}"#).unwrap_err();
+        let op_err = OpError::from_request_parse_error(&err);
+        assert!(matches!(op_err.code, OpErrorCode::UnknownOp));
+    }
+
+    #[test]
+    fn test_request_parse_error_classifies_missing_field() {
+        let err = serde_json::from_str::<Request>(r#"{"op": "get_entry", "section": "country"}"#)
+            .unwrap_err();
+        let op_err = OpError::from_request_parse_error(&err);
+        assert!(matches!(op_err.code, OpErrorCode::MissingField));
+    }
+
+    #[test]
+    fn test_request_parse_error_classifies_invalid_field_value() {
+        let err = serde_json::from_str::<Request>(
+            r#"{"op": "get_entry", "section": "country", "key": 5}"#,
+        )
+        .unwrap_err();
+        let op_err = OpError::from_request_parse_error(&err);
+        assert!(matches!(op_err.code, OpErrorCode::InvalidFieldValue));
+    }
+
+    #[test]
+    fn test_run_multi_op_tags_get_entry_as_plain_success_value() {
+        let mut gamestate = HashMap::new();
+        gamestate.insert(
+            "leaders".to_string(),
+            json!({"123": {"name": "Test Leader"}}),
+        );
+        let parsed = ParsedSave {
+            gamestate,
+            gamestate_bytes: GamestateBytes::Owned(Vec::new()),
+            meta: None,
+            search_index: HashMap::new(),
+            term_bk_tree: BkTree::new(),
+        };
+        let mut cache = HashMap::new();
+        let op = MultiOp::GetEntry {
+            section: "leaders".to_string(),
+            key: "123".to_string(),
+        };
+        let value = run_multi_op(&parsed, op, "", &mut cache).expect("get_entry should succeed");
+        assert_eq!(value["found"], json!(true));
+    }
+
+    #[test]
+    fn test_multi_op_dedup_key_matches_identical_ops_only() {
+        let a = MultiOp::GetEntry {
+            section: "country".to_string(),
+            key: "0".to_string(),
+        };
+        let b = MultiOp::GetEntry {
+            section: "country".to_string(),
+            key: "0".to_string(),
+        };
+        let c = MultiOp::GetEntry {
+            section: "country".to_string(),
+            key: "1".to_string(),
+        };
+        assert_eq!(multi_op_dedup_key(&a), multi_op_dedup_key(&b));
+        assert_ne!(multi_op_dedup_key(&a), multi_op_dedup_key(&c));
+    }
+
+    #[test]
+    fn test_multi_request_parses_max_concurrency() {
+        let json = r#"{"op": "multi", "max_concurrency": 4, "ops": [{"op": "count_keys", "keys": ["name"]}]}"#;
+        let req: Request = serde_json::from_str(json).unwrap();
+        match req {
+            Request::Multi {
+                ops,
+                max_concurrency,
+            } => {
+                assert_eq!(ops.len(), 1);
+                assert_eq!(max_concurrency, Some(4));
+            }
+            _ => panic!("Wrong request type"),
         }
     }
 
-    eprintln!("[serve] Session ended");
-    Ok(())
-}
+    #[test]
+    fn test_fused_count_and_kv_scan_matches_standalone_ops() {
+        let mut gamestate = HashMap::new();
+        gamestate.insert(
+            "country".to_string(),
+            json!({
+                "0": {"name": "Empire A", "ethos": "materialist"},
+                "1": {"name": "Empire B", "ethos": "xenophile"},
+            }),
+        );
+        let parsed = ParsedSave {
+            gamestate,
+            gamestate_bytes: GamestateBytes::Owned(Vec::new()),
+            meta: None,
+            search_index: HashMap::new(),
+            term_bk_tree: BkTree::new(),
+        };
+        let ops = vec![
+            MultiOp::CountKeys {
+                keys: vec!["name".to_string(), "missing_key".to_string()],
+            },
+            MultiOp::ContainsKv {
+                pairs: vec![
+                    ("ethos".to_string(), "materialist".to_string()),
+                    ("ethos".to_string(), "militarist".to_string()),
+                ],
+            },
+        ];
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+        let (counts, matches) = fused_count_and_kv_scan(&parsed, &ops);
+        assert_eq!(counts["name"], 2);
+        assert_eq!(counts["missing_key"], 0);
+        assert_eq!(matches.get("ethos=materialist"), Some(&true));
+        assert!(!matches.contains_key("ethos=militarist"));
+    }
 
     #[test]
-    fn test_request_parsing() {
-        let json = r#"{"op": "extract_sections", "sections": ["meta", "player"]}"#;
+    fn test_multi_op_request() {
+        let json = r#"{"op": "multi", "ops": [{"op": "get_entry", "section": "country", "key": "0"}, {"op": "count_keys", "keys": ["name"]}]}"#;
         let req: Request = serde_json::from_str(json).unwrap();
         match req {
-            Request::ExtractSections { sections } => {
-                assert_eq!(sections, vec!["meta", "player"]);
+            Request::Multi { ops, max_concurrency } => {
+                assert_eq!(ops.len(), 2);
+                assert_eq!(max_concurrency, None);
+                match &ops[0] {
+                    MultiOp::GetEntry { section, key } => {
+                        assert_eq!(section, "country");
+                        assert_eq!(key, "0");
+                    }
+                    _ => panic!("Wrong op type for first op"),
+                }
+                match &ops[1] {
+                    MultiOp::CountKeys { keys } => {
+                        assert_eq!(keys, &vec!["name"]);
+                    }
+                    _ => panic!("Wrong op type for second op"),
+                }
             }
             _ => panic!("Wrong request type"),
         }
     }
 
     #[test]
-    fn test_iter_section_request() {
-        let json = r#"{"op": "iter_section", "section": "country"}"#;
+    fn test_multi_op_all_types() {
+        // Test that all MultiOp variants can be parsed
+        let json = r#"{"op": "multi", "ops": [
+            {"op": "extract_sections", "sections": ["meta"]},
+            {"op": "get_entry", "section": "country", "key": "0"},
+            {"op": "get_entries", "section": "country", "keys": ["0", "1"]},
+            {"op": "count_keys", "keys": ["name"]},
+            {"op": "contains_tokens", "tokens": ["test"]},
+            {"op": "contains_kv", "pairs": [["key", "value"]]},
+            {"op": "get_country_summaries", "fields": ["name"]},
+            {"op": "get_duplicate_values", "section": "leaders", "key": "0", "field": "traits"},
+            {"op": "query_entries", "section": "country"}
+        ]}"#;
         let req: Request = serde_json::from_str(json).unwrap();
         match req {
-            Request::IterSection {
+            Request::Multi { ops, .. } => {
+                assert_eq!(ops.len(), 9);
+            }
+            _ => panic!("Wrong request type"),
+        }
+    }
+
+    #[test]
+    fn test_query_entries_request_defaults() {
+        let json = r#"{"op": "query_entries", "section": "country"}"#;
+        let req: Request = serde_json::from_str(json).unwrap();
+        match req {
+            Request::QueryEntries {
                 section,
-                batch_size,
+                filter,
+                sort,
+                limit,
+                fields,
             } => {
                 assert_eq!(section, "country");
-                assert_eq!(batch_size, 100); // Default batch size
+                assert!(filter.is_none());
+                assert!(sort.is_empty());
+                assert!(limit.is_none());
+                assert!(fields.is_none());
+            }
+            _ => panic!("Wrong request type"),
+        }
+    }
+
+    #[test]
+    fn test_query_entries_request_full() {
+        let json = r#"{
+            "op": "query_entries",
+            "section": "country",
+            "filter": {"and": [
+                {"field": "owner.faction", "op": "=", "value": "materialist"},
+                {"field": "military_power", "op": ">=", "value": 10}
+            ]},
+            "sort": [{"field": "military_power", "dir": "desc"}],
+            "limit": 5,
+            "fields": ["name", "military_power"]
+        }"#;
+        let req: Request = serde_json::from_str(json).unwrap();
+        match req {
+            Request::QueryEntries {
+                section,
+                filter,
+                sort,
+                limit,
+                fields,
+            } => {
+                assert_eq!(section, "country");
+                assert!(filter.is_some());
+                assert_eq!(sort.len(), 1);
+                assert_eq!(limit, Some(5));
+                assert_eq!(
+                    fields,
+                    Some(vec!["name".to_string(), "military_power".to_string()])
+                );
             }
             _ => panic!("Wrong request type"),
         }
     }
 
     #[test]
-    fn test_iter_section_request_with_batch_size() {
-        let json = r#"{"op": "iter_section", "section": "country", "batch_size": 50}"#;
+    fn test_eval_filter_leaf_comparisons() {
+        let entry = json!({"military_power": 42, "owner": {"faction": "materialist"}});
+
+        let ge = FilterNode::Leaf {
+            field: "military_power".to_string(),
+            op: FilterOp::Ge,
+            value: json!(10),
+        };
+        assert!(eval_filter(&entry, &ge));
+
+        let lt = FilterNode::Leaf {
+            field: "military_power".to_string(),
+            op: FilterOp::Lt,
+            value: json!(10),
+        };
+        assert!(!eval_filter(&entry, &lt));
+
+        let nested_eq = FilterNode::Leaf {
+            field: "owner.faction".to_string(),
+            op: FilterOp::Eq,
+            value: json!("materialist"),
+        };
+        assert!(eval_filter(&entry, &nested_eq));
+
+        let missing_field = FilterNode::Leaf {
+            field: "owner.nonexistent".to_string(),
+            op: FilterOp::Ne,
+            value: json!("anything"),
+        };
+        assert!(!eval_filter(&entry, &missing_field));
+    }
+
+    #[test]
+    fn test_eval_filter_combinators() {
+        let entry = json!({"military_power": 42});
+
+        let and_node = FilterNode::And {
+            and: vec![
+                FilterNode::Leaf {
+                    field: "military_power".to_string(),
+                    op: FilterOp::Gt,
+                    value: json!(10),
+                },
+                FilterNode::Leaf {
+                    field: "military_power".to_string(),
+                    op: FilterOp::Lt,
+                    value: json!(100),
+                },
+            ],
+        };
+        assert!(eval_filter(&entry, &and_node));
+
+        let not_node = FilterNode::Not {
+            not: Box::new(FilterNode::Leaf {
+                field: "military_power".to_string(),
+                op: FilterOp::Gt,
+                value: json!(1000),
+            }),
+        };
+        assert!(eval_filter(&entry, &not_node));
+    }
+
+    #[test]
+    fn test_query_entries_filters_sorts_limits_and_projects() {
+        let mut gamestate = HashMap::new();
+        gamestate.insert(
+            "country".to_string(),
+            json!({
+                "0": {"name": "Empire A", "military_power": 30},
+                "1": {"name": "Empire B", "military_power": 80},
+                "2": {"name": "Empire C", "military_power": 50},
+            }),
+        );
+        let parsed = ParsedSave {
+            gamestate,
+            gamestate_bytes: GamestateBytes::Owned(Vec::new()),
+            meta: None,
+            search_index: HashMap::new(),
+            term_bk_tree: BkTree::new(),
+        };
+
+        let filter = Some(FilterNode::Leaf {
+            field: "military_power".to_string(),
+            op: FilterOp::Ge,
+            value: json!(50),
+        });
+        let sort = vec![SortKey {
+            field: "military_power".to_string(),
+            dir: SortDir::Desc,
+        }];
+
+        let results = query_entries(
+            &parsed,
+            "country",
+            &filter,
+            &sort,
+            Some(1),
+            &Some(vec!["name".to_string()]),
+        );
+
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0]["_key"], json!("1"));
+        assert_eq!(results[0]["name"], json!("Empire B"));
+    }
+
+    #[test]
+    fn test_query_request_defaults() {
+        let json = r#"{"op": "query", "section": "country"}"#;
         let req: Request = serde_json::from_str(json).unwrap();
         match req {
-            Request::IterSection {
+            Request::Query {
                 section,
-                batch_size,
+                filters,
+                sort,
+                offset,
+                limit,
+                fields,
             } => {
                 assert_eq!(section, "country");
-                assert_eq!(batch_size, 50);
+                assert!(filters.is_empty());
+                assert!(sort.is_none());
+                assert_eq!(offset, 0);
+                assert!(limit.is_none());
+                assert!(fields.is_none());
             }
             _ => panic!("Wrong request type"),
         }
     }
 
     #[test]
-    fn test_get_entry_request() {
-        let json = r#"{"op": "get_entry", "section": "country", "key": "0"}"#;
-        let req: Request = serde_json::from_str(json).unwrap();
-        match req {
-            Request::GetEntry { section, key } => {
-                assert_eq!(section, "country");
-                assert_eq!(key, "0");
+    fn test_eval_query_filter_ops() {
+        let entry = json!({
+            "name": "Materialist Empire",
+            "military_power": 50,
+            "traits": ["xenophile", "thrifty"],
+        });
+
+        assert!(eval_query_filter(
+            &entry,
+            &QueryFilter {
+                field: "military_power".to_string(),
+                op: QueryFilterOp::Gte,
+                value: json!(50),
             }
-            _ => panic!("Wrong request type"),
-        }
+        ));
+        assert!(!eval_query_filter(
+            &entry,
+            &QueryFilter {
+                field: "military_power".to_string(),
+                op: QueryFilterOp::Lt,
+                value: json!(50),
+            }
+        ));
+        assert!(eval_query_filter(
+            &entry,
+            &QueryFilter {
+                field: "name".to_string(),
+                op: QueryFilterOp::Contains,
+                value: json!("Empire"),
+            }
+        ));
+        assert!(eval_query_filter(
+            &entry,
+            &QueryFilter {
+                field: "traits".to_string(),
+                op: QueryFilterOp::Contains,
+                value: json!("thrifty"),
+            }
+        ));
+        assert!(eval_query_filter(
+            &entry,
+            &QueryFilter {
+                field: "missing_field".to_string(),
+                op: QueryFilterOp::Exists,
+                value: Value::Null,
+            }
+        ));
+        assert!(!eval_query_filter(
+            &entry,
+            &QueryFilter {
+                field: "missing_field".to_string(),
+                op: QueryFilterOp::Eq,
+                value: json!("anything"),
+            }
+        ));
     }
 
     #[test]
-    fn test_close_request() {
-        let json = r#"{"op": "close"}"#;
-        let req: Request = serde_json::from_str(json).unwrap();
-        assert!(matches!(req, Request::Close));
+    fn test_query_page_filters_sorts_and_paginates() {
+        let mut gamestate = HashMap::new();
+        gamestate.insert(
+            "country".to_string(),
+            json!({
+                "0": {"name": "Empire A", "military_power": 30},
+                "1": {"name": "Empire B", "military_power": 80},
+                "2": {"name": "Empire C", "military_power": 50},
+                "3": {"name": "Empire D", "military_power": 60},
+            }),
+        );
+        let parsed = ParsedSave {
+            gamestate,
+            gamestate_bytes: GamestateBytes::Owned(Vec::new()),
+            meta: None,
+            search_index: HashMap::new(),
+            term_bk_tree: BkTree::new(),
+        };
+
+        let filters = vec![QueryFilter {
+            field: "military_power".to_string(),
+            op: QueryFilterOp::Gte,
+            value: json!(50),
+        }];
+        let sort = Some(SortKey {
+            field: "military_power".to_string(),
+            dir: SortDir::Desc,
+        });
+
+        let (page, total, next_offset) = query_page(
+            &parsed,
+            "country",
+            &filters,
+            &sort,
+            0,
+            Some(2),
+            &Some(vec!["name".to_string()]),
+        );
+
+        assert_eq!(total, 3);
+        assert_eq!(page.len(), 2);
+        assert_eq!(page[0]["_key"], json!("1"));
+        assert_eq!(page[1]["_key"], json!("3"));
+        assert_eq!(next_offset, Some(2));
+
+        let (last_page, _, last_next_offset) =
+            query_page(&parsed, "country", &filters, &sort, 2, Some(2), &None);
+        assert_eq!(last_page.len(), 1);
+        assert_eq!(last_next_offset, None);
     }
 
     #[test]
-    fn test_get_entries_request() {
-        let json = r#"{"op": "get_entries", "section": "country", "keys": ["0", "1", "2"]}"#;
-        let req: Request = serde_json::from_str(json).unwrap();
-        match req {
-            Request::GetEntries {
-                section,
-                keys,
-                fields,
-            } => {
-                assert_eq!(section, "country");
-                assert_eq!(keys, vec!["0", "1", "2"]);
-                assert!(fields.is_none());
-            }
-            _ => panic!("Wrong request type"),
-        }
+    fn test_request_envelope_parses_optional_id() {
+        let json = r#"{"id": "req-1", "op": "get_entry", "section": "country", "key": "0"}"#;
+        let envelope: RequestEnvelope = serde_json::from_str(json).unwrap();
+        assert_eq!(envelope.id, Some(json!("req-1")));
+        assert!(matches!(envelope.request, Request::GetEntry { .. }));
+
+        let json_no_id = r#"{"op": "get_entry", "section": "country", "key": "0"}"#;
+        let envelope: RequestEnvelope = serde_json::from_str(json_no_id).unwrap();
+        assert!(envelope.id.is_none());
     }
 
     #[test]
-    fn test_get_entries_request_with_fields() {
-        let json = r#"{"op": "get_entries", "section": "country", "keys": ["0"], "fields": ["name", "type"]}"#;
+    fn test_search_request_defaults() {
+        let json = r#"{"op": "search", "terms": ["materialist"]}"#;
         let req: Request = serde_json::from_str(json).unwrap();
         match req {
-            Request::GetEntries {
+            Request::Search {
+                terms,
+                top_k,
                 section,
-                keys,
-                fields,
             } => {
-                assert_eq!(section, "country");
-                assert_eq!(keys, vec!["0"]);
-                assert_eq!(fields, Some(vec!["name".to_string(), "type".to_string()]));
+                assert_eq!(terms, vec!["materialist"]);
+                assert_eq!(top_k, 10);
+                assert!(section.is_none());
             }
             _ => panic!("Wrong request type"),
         }
     }
 
     #[test]
-    fn test_count_keys_request() {
-        let json = r#"{"op": "count_keys", "keys": ["name", "type", "flag"]}"#;
-        let req: Request = serde_json::from_str(json).unwrap();
-        match req {
-            Request::CountKeys { keys } => {
-                assert_eq!(keys, vec!["name", "type", "flag"]);
-            }
-            _ => panic!("Wrong request type"),
+    fn test_tokenize_splits_on_non_alphanumeric() {
+        assert_eq!(
+            tokenize("Materialist_Xenophile 42"),
+            vec!["materialist", "xenophile", "42"]
+        );
+        assert!(tokenize("---").is_empty());
+    }
+
+    #[test]
+    fn test_build_search_index_and_rank_by_distinct_terms() {
+        let mut gamestate = HashMap::new();
+        gamestate.insert(
+            "country".to_string(),
+            json!({
+                "0": {"name": "Materialist Empire", "ethos": "materialist"},
+                "1": {"name": "Xenophile Federation", "ethos": "xenophile"},
+            }),
+        );
+        let index = build_search_index(&gamestate);
+        let bk_tree = build_term_bk_tree(&index);
+
+        let hits = search_entries(
+            &index,
+            &bk_tree,
+            &["materialist".to_string(), "empire".to_string()],
+            10,
+            &None,
+        );
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].section, "country");
+        assert_eq!(hits[0].entry_key, "0");
+        assert_eq!(hits[0].matching_terms, 2);
+        assert!(hits[0].matched_fields.contains(&"name".to_string()));
+        assert!(hits[0].matched_fields.contains(&"ethos".to_string()));
+    }
+
+    #[test]
+    fn test_search_entries_respects_section_filter_and_top_k() {
+        let mut gamestate = HashMap::new();
+        gamestate.insert(
+            "country".to_string(),
+            json!({"0": {"name": "xenophile"}}),
+        );
+        gamestate.insert(
+            "leaders".to_string(),
+            json!({"1": {"name": "xenophile"}}),
+        );
+        let index = build_search_index(&gamestate);
+        let bk_tree = build_term_bk_tree(&index);
+
+        let hits = search_entries(
+            &index,
+            &bk_tree,
+            &["xenophile".to_string()],
+            10,
+            &Some("leaders".to_string()),
+        );
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].section, "leaders");
+
+        let limited = search_entries(&index, &bk_tree, &["xenophile".to_string()], 1, &None);
+        assert_eq!(limited.len(), 1);
+    }
+
+    #[test]
+    fn test_typo_budget_scales_with_word_length() {
+        assert_eq!(typo_budget(3), 0);
+        assert_eq!(typo_budget(4), 0);
+        assert_eq!(typo_budget(5), 1);
+        assert_eq!(typo_budget(8), 1);
+        assert_eq!(typo_budget(9), 2);
+    }
+
+    #[test]
+    fn test_bk_tree_fuzzy_matches_within_budget() {
+        let mut tree = BkTree::new();
+        for term in ["materialist", "militarist", "xenophile"] {
+            tree.insert(term.to_string());
         }
+
+        let exact = tree.fuzzy_matches("materialist", 0);
+        assert_eq!(exact, vec![("materialist".to_string(), 0)]);
+
+        let mut fuzzy = tree.fuzzy_matches("materialist", 6);
+        fuzzy.sort();
+        assert_eq!(
+            fuzzy,
+            vec![
+                ("materialist".to_string(), 0),
+                ("militarist".to_string(), 6),
+            ]
+        );
     }
 
     #[test]
-    fn test_error_response_serialization() {
-        let err = ErrorResponse::new("SectionNotFound", "Section 'foo' not found", 2);
-        let json = serde_json::to_string(&err).unwrap();
-        assert!(json.contains(r#""ok":false"#));
-        assert!(json.contains(r#""error":"SectionNotFound""#));
+    fn test_search_entries_tolerates_typos_and_ranks_exact_first() {
+        let mut gamestate = HashMap::new();
+        gamestate.insert(
+            "country".to_string(),
+            json!({
+                "0": {"name": "Materialist Empire"},
+                "1": {"name": "Materalist Outpost"},
+            }),
+        );
+        let index = build_search_index(&gamestate);
+        let bk_tree = build_term_bk_tree(&index);
+
+        let hits = search_entries(&index, &bk_tree, &["materalist".to_string()], 10, &None);
+        assert_eq!(hits.len(), 2);
+        assert_eq!(hits[0].entry_key, "1");
+        assert_eq!(hits[0].typo_count, 0);
+        assert_eq!(hits[1].entry_key, "0");
+        assert_eq!(hits[1].typo_count, 1);
     }
 
     #[test]
-    fn test_contains_tokens_request() {
-        let json = r#"{"op": "contains_tokens", "tokens": ["country", "fleet", "xyz123"]}"#;
-        let req: Request = serde_json::from_str(json).unwrap();
-        match req {
-            Request::ContainsTokens { tokens } => {
-                assert_eq!(tokens, vec!["country", "fleet", "xyz123"]);
-            }
-            _ => panic!("Wrong request type"),
+    fn test_fnv1a_hash_is_deterministic_and_sensitive_to_input() {
+        assert_eq!(fnv1a_hash(b"hello"), fnv1a_hash(b"hello"));
+        assert_ne!(fnv1a_hash(b"hello"), fnv1a_hash(b"hellp"));
+    }
+
+    #[test]
+    fn test_cache_file_path_is_stable_and_distinct_per_save_path() {
+        let dir = std::path::Path::new("/tmp/cache");
+        let path_a = cache_file_path(dir, "/saves/empire.sav");
+        let path_b = cache_file_path(dir, "/saves/empire.sav");
+        let path_c = cache_file_path(dir, "/saves/other.sav");
+        assert_eq!(path_a, path_b);
+        assert_ne!(path_a, path_c);
+        assert_eq!(path_a.parent(), Some(dir));
+    }
+
+    #[test]
+    fn test_write_cache_then_load_from_cache_round_trips_parsed_save() {
+        let save_path = std::env::temp_dir().join(format!(
+            "stellaris-companion-test-save-{}.sav",
+            std::process::id()
+        ));
+        let cache_dir = std::env::temp_dir().join(format!(
+            "stellaris-companion-test-cache-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&cache_dir);
+        std::fs::write(&save_path, b"gamestate=yes").unwrap();
+
+        let mut gamestate = HashMap::new();
+        gamestate.insert("country".to_string(), json!({"0": {"name": "Fortress"}}));
+        let search_index = build_search_index(&gamestate);
+        let gamestate_bytes = b"gamestate=yes".to_vec();
+
+        ParsedSave::write_cache(
+            save_path.to_str().unwrap(),
+            &gamestate_bytes,
+            None,
+            &gamestate,
+            &None,
+            &search_index,
+            &cache_dir,
+        );
+
+        let loaded =
+            ParsedSave::load_from_cache(save_path.to_str().unwrap(), &gamestate_bytes, None, &cache_dir)
+                .expect("freshly written cache entry should load back");
+        assert_eq!(loaded.gamestate, gamestate);
+        assert!(loaded.meta.is_none());
+        assert_eq!(loaded.search_index.len(), search_index.len());
+
+        let _ = std::fs::remove_file(&save_path);
+        let _ = std::fs::remove_dir_all(&cache_dir);
+    }
+
+    #[test]
+    fn test_load_from_cache_misses_when_content_changes() {
+        let save_path = std::env::temp_dir().join(format!(
+            "stellaris-companion-test-save-stale-{}.sav",
+            std::process::id()
+        ));
+        let cache_dir = std::env::temp_dir().join(format!(
+            "stellaris-companion-test-cache-stale-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&cache_dir);
+        std::fs::write(&save_path, b"gamestate=yes").unwrap();
+
+        let gamestate = HashMap::new();
+        let search_index = build_search_index(&gamestate);
+        ParsedSave::write_cache(
+            save_path.to_str().unwrap(),
+            b"gamestate=yes",
+            None,
+            &gamestate,
+            &None,
+            &search_index,
+            &cache_dir,
+        );
+
+        let miss = ParsedSave::load_from_cache(
+            save_path.to_str().unwrap(),
+            b"gamestate=changed",
+            None,
+            &cache_dir,
+        );
+        assert!(miss.is_none(), "a changed fingerprint must not be served from cache");
+
+        let _ = std::fs::remove_file(&save_path);
+        let _ = std::fs::remove_dir_all(&cache_dir);
+    }
+
+    #[test]
+    fn test_worker_pool_barrier_waits_for_outstanding_jobs() {
+        use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+
+        let pool = WorkerPool::new(4);
+        let completed = Arc::new(AtomicUsize::new(0));
+
+        for _ in 0..50 {
+            let completed = Arc::clone(&completed);
+            pool.submit(Box::new(move || {
+                completed.fetch_add(1, AtomicOrdering::SeqCst);
+            }));
         }
+
+        pool.barrier();
+        assert_eq!(completed.load(AtomicOrdering::SeqCst), 50);
     }
 
     #[test]
-    fn test_get_country_summaries_request() {
-        let json = r#"{"op": "get_country_summaries", "fields": ["name", "type", "flag"]}"#;
+    fn test_json_path_request_parses() {
+        let json = r#"{"op": "json_path", "path": "$.country[*].name"}"#;
         let req: Request = serde_json::from_str(json).unwrap();
         match req {
-            Request::GetCountrySummaries { fields } => {
-                assert_eq!(fields, vec!["name", "type", "flag"]);
-            }
+            Request::JsonPath { path } => assert_eq!(path, "$.country[*].name"),
             _ => panic!("Wrong request type"),
         }
     }
 
     #[test]
-    fn test_contains_kv_request() {
-        let json =
-            r#"{"op": "contains_kv", "pairs": [["war_in_heaven", "yes"], ["version", "3"]]}"#;
+    fn test_parse_json_path_covers_every_step_kind() {
+        let steps = parse_json_path("$.country[*].fleets..ship[0][?(@.hp>10)]").unwrap();
+        assert_eq!(
+            steps,
+            vec![
+                PathStep::Child("country".to_string()),
+                PathStep::Wildcard,
+                PathStep::Child("fleets".to_string()),
+                PathStep::RecursiveDescent("ship".to_string()),
+                PathStep::Index(0),
+                PathStep::Filter {
+                    field: "hp".to_string(),
+                    op: JsonPathFilterOp::Gt,
+                    value: json!(10.0),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_json_path_rejects_missing_root() {
+        assert!(parse_json_path("country.name").is_err());
+    }
+
+    #[test]
+    fn test_eval_json_path_child_and_wildcard() {
+        let mut gamestate = HashMap::new();
+        gamestate.insert(
+            "country".to_string(),
+            json!({
+                "0": {"name": "Materialist Empire"},
+                "1": {"name": "Xenophile Federation"},
+            }),
+        );
+
+        let mut names = eval_json_path(&gamestate, "$.country[*].name")
+            .unwrap()
+            .into_iter()
+            .map(|v| v.as_str().unwrap().to_string())
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, vec!["Materialist Empire", "Xenophile Federation"]);
+    }
+
+    #[test]
+    fn test_eval_json_path_recursive_descent_finds_nested_key() {
+        let mut gamestate = HashMap::new();
+        gamestate.insert(
+            "country".to_string(),
+            json!({
+                "0": {"fleets": {"0": {"ship": {"name": "Vanguard"}}}},
+            }),
+        );
+
+        let matches = eval_json_path(&gamestate, "$..ship").unwrap();
+        assert_eq!(matches, vec![json!({"name": "Vanguard"})]);
+    }
+
+    #[test]
+    fn test_eval_json_path_filter_predicate() {
+        let mut gamestate = HashMap::new();
+        gamestate.insert(
+            "country".to_string(),
+            json!({
+                "0": {"name": "Weak Empire", "military_power": 5},
+                "1": {"name": "Strong Empire", "military_power": 50},
+            }),
+        );
+
+        let matches = eval_json_path(&gamestate, "$.country[*][?(@.military_power>10)]").unwrap();
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0]["name"], json!("Strong Empire"));
+    }
+
+    #[test]
+    fn test_eval_json_path_reports_unsupported_first_step() {
+        let gamestate = HashMap::new();
+        assert!(eval_json_path(&gamestate, "$[0]").is_err());
+    }
+
+    #[test]
+    fn test_filter_entries_request_parses() {
+        let json = r#"{"op": "filter_entries", "section": "country", "filter": "fleet_size >= 10"}"#;
         let req: Request = serde_json::from_str(json).unwrap();
         match req {
-            Request::ContainsKv { pairs } => {
-                assert_eq!(pairs.len(), 2);
-                assert_eq!(pairs[0], ("war_in_heaven".to_string(), "yes".to_string()));
-                assert_eq!(pairs[1], ("version".to_string(), "3".to_string()));
+            Request::FilterEntries { section, filter } => {
+                assert_eq!(section, "country");
+                assert_eq!(filter, "fleet_size >= 10");
             }
             _ => panic!("Wrong request type"),
         }
     }
 
     #[test]
-    fn test_get_duplicate_values_request() {
-        let json = r#"{"op": "get_duplicate_values", "section": "leaders", "key": "123", "field": "traits"}"#;
+    fn test_parse_filter_expr_and_binds_tighter_than_or() {
+        let ast = parse_filter_expr(
+            "war_in_heaven = yes AND fleet_size >= 10 OR NOT capital = none",
+        )
+        .unwrap();
+        assert_eq!(
+            ast,
+            FilterExprNode::Or(
+                Box::new(FilterExprNode::And(
+                    Box::new(FilterExprNode::Condition {
+                        field: "war_in_heaven".to_string(),
+                        op: FilterEntriesOp::Eq,
+                        value: json!(true),
+                    }),
+                    Box::new(FilterExprNode::Condition {
+                        field: "fleet_size".to_string(),
+                        op: FilterEntriesOp::Gte,
+                        value: json!(10.0),
+                    }),
+                )),
+                Box::new(FilterExprNode::Not(Box::new(FilterExprNode::Condition {
+                    field: "capital".to_string(),
+                    op: FilterEntriesOp::Eq,
+                    value: json!("none"),
+                }))),
+            )
+        );
+    }
+
+    #[test]
+    fn test_parse_filter_expr_range_and_grouping() {
+        let ast = parse_filter_expr("(fleet_size 5 TO 20) AND NOT war_exhaustion > 0.5").unwrap();
+        assert_eq!(
+            ast,
+            FilterExprNode::And(
+                Box::new(FilterExprNode::Range {
+                    field: "fleet_size".to_string(),
+                    low: json!(5.0),
+                    high: json!(20.0),
+                }),
+                Box::new(FilterExprNode::Not(Box::new(FilterExprNode::Condition {
+                    field: "war_exhaustion".to_string(),
+                    op: FilterEntriesOp::Gt,
+                    value: json!(0.5),
+                }))),
+            )
+        );
+    }
+
+    #[test]
+    fn test_eval_filter_expr_matches_compound_expression() {
+        let ast = parse_filter_expr(
+            "war_in_heaven = yes AND fleet_size >= 10 OR NOT capital = none",
+        )
+        .unwrap();
+
+        let matches = json!({"war_in_heaven": true, "fleet_size": 12, "capital": "none"});
+        assert!(eval_filter_expr(&matches, &ast));
+
+        let no_war = json!({"war_in_heaven": false, "fleet_size": 3, "capital": "our_star"});
+        assert!(eval_filter_expr(&no_war, &ast));
+
+        let neither = json!({"war_in_heaven": false, "fleet_size": 3, "capital": "none"});
+        assert!(!eval_filter_expr(&neither, &ast));
+
+        let missing_field = json!({"capital": "none"});
+        assert!(!eval_filter_expr(&missing_field, &ast));
+    }
+
+    #[test]
+    fn test_eval_filter_expr_range_is_inclusive() {
+        let ast = parse_filter_expr("fleet_size 5 TO 20").unwrap();
+        assert!(eval_filter_expr(&json!({"fleet_size": 5}), &ast));
+        assert!(eval_filter_expr(&json!({"fleet_size": 20}), &ast));
+        assert!(!eval_filter_expr(&json!({"fleet_size": 21}), &ast));
+    }
+
+    #[test]
+    fn test_filter_entries_returns_passing_keys_and_values() {
+        let mut gamestate = HashMap::new();
+        gamestate.insert(
+            "country".to_string(),
+            json!({
+                "0": {"name": "Weak Empire", "fleet_size": 3},
+                "1": {"name": "Strong Empire", "fleet_size": 15},
+            }),
+        );
+        let ast = parse_filter_expr("fleet_size >= 10").unwrap();
+
+        let parsed = ParsedSave {
+            gamestate,
+            gamestate_bytes: GamestateBytes::Owned(Vec::new()),
+            meta: None,
+            search_index: HashMap::new(),
+            term_bk_tree: BkTree::new(),
+        };
+
+        let entries = filter_entries(&parsed, "country", &ast);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0]["_key"], json!("1"));
+        assert_eq!(entries[0]["_value"]["name"], json!("Strong Empire"));
+    }
+
+    #[test]
+    fn test_parse_filter_expr_rejects_malformed_input() {
+        assert!(parse_filter_expr("fleet_size >=").is_err());
+        assert!(parse_filter_expr("(fleet_size >= 10").is_err());
+        assert!(parse_filter_expr("").is_err());
+    }
+
+    #[test]
+    fn test_sort_entries_request_parses() {
+        let json = r#"{"op": "sort_entries", "section": "country", "field": "fleet_size", "order": "desc"}"#;
         let req: Request = serde_json::from_str(json).unwrap();
         match req {
-            Request::GetDuplicateValues {
+            Request::SortEntries {
                 section,
-                key,
                 field,
+                order,
             } => {
-                assert_eq!(section, "leaders");
-                assert_eq!(key, "123");
-                assert_eq!(field, "traits");
+                assert_eq!(section, "country");
+                assert_eq!(field, "fleet_size");
+                assert_eq!(order, SortDir::Desc);
             }
             _ => panic!("Wrong request type"),
         }
     }
 
     #[test]
-    fn test_multi_op_request() {
-        let json = r#"{"op": "multi", "ops": [{"op": "get_entry", "section": "country", "key": "0"}, {"op": "count_keys", "keys": ["name"]}]}"#;
+    fn test_sort_entries_numeric_desc_with_missing_last() {
+        let mut gamestate = HashMap::new();
+        gamestate.insert(
+            "country".to_string(),
+            json!({
+                "0": {"name": "Weak Empire", "fleet_size": 3},
+                "1": {"name": "Strong Empire", "fleet_size": 15},
+                "2": {"name": "No Fleet"},
+            }),
+        );
+        let parsed = ParsedSave {
+            gamestate,
+            gamestate_bytes: GamestateBytes::Owned(Vec::new()),
+            meta: None,
+            search_index: HashMap::new(),
+            term_bk_tree: BkTree::new(),
+        };
+
+        let keys = sort_entries(&parsed, "country", "fleet_size", SortDir::Desc).unwrap();
+        assert_eq!(keys, vec!["1".to_string(), "0".to_string(), "2".to_string()]);
+    }
+
+    #[test]
+    fn test_sort_entries_rejects_structured_field() {
+        let mut gamestate = HashMap::new();
+        gamestate.insert(
+            "country".to_string(),
+            json!({
+                "0": {"name": "Weak Empire", "fleet": {"size": 3}},
+                "1": {"name": "Strong Empire", "fleet": {"size": 15}},
+            }),
+        );
+        let parsed = ParsedSave {
+            gamestate,
+            gamestate_bytes: GamestateBytes::Owned(Vec::new()),
+            meta: None,
+            search_index: HashMap::new(),
+            term_bk_tree: BkTree::new(),
+        };
+
+        let err = sort_entries(&parsed, "country", "fleet", SortDir::Asc).unwrap_err();
+        assert_eq!(err.code, OpErrorCode::FieldNotSortable);
+    }
+
+    #[test]
+    fn test_diff_request_parses() {
+        let json = r#"{"op": "diff", "other_path": "prev.sav", "section": "country"}"#;
         let req: Request = serde_json::from_str(json).unwrap();
         match req {
-            Request::Multi { ops } => {
-                assert_eq!(ops.len(), 2);
-                match &ops[0] {
-                    MultiOp::GetEntry { section, key } => {
-                        assert_eq!(section, "country");
-                        assert_eq!(key, "0");
-                    }
-                    _ => panic!("Wrong op type for first op"),
-                }
-                match &ops[1] {
-                    MultiOp::CountKeys { keys } => {
-                        assert_eq!(keys, &vec!["name"]);
-                    }
-                    _ => panic!("Wrong op type for second op"),
-                }
+            Request::Diff {
+                other_path,
+                section,
+            } => {
+                assert_eq!(other_path, "prev.sav");
+                assert_eq!(section, Some("country".to_string()));
             }
             _ => panic!("Wrong request type"),
         }
     }
 
     #[test]
-    fn test_multi_op_all_types() {
-        // Test that all MultiOp variants can be parsed
-        let json = r#"{"op": "multi", "ops": [
-            {"op": "extract_sections", "sections": ["meta"]},
-            {"op": "get_entry", "section": "country", "key": "0"},
-            {"op": "get_entries", "section": "country", "keys": ["0", "1"]},
-            {"op": "count_keys", "keys": ["name"]},
-            {"op": "contains_tokens", "tokens": ["test"]},
-            {"op": "contains_kv", "pairs": [["key", "value"]]},
-            {"op": "get_country_summaries", "fields": ["name"]},
-            {"op": "get_duplicate_values", "section": "leaders", "key": "0", "field": "traits"}
-        ]}"#;
+    fn test_diff_values_reports_add_remove_and_changed_leaves() {
+        let mut before = HashMap::new();
+        before.insert(
+            "country".to_string(),
+            json!({
+                "0": {"name": "Old Empire", "fleet_size": 10},
+                "1": {"name": "Doomed Empire", "fleet_size": 1},
+            }),
+        );
+        let mut after = HashMap::new();
+        after.insert(
+            "country".to_string(),
+            json!({
+                "0": {"name": "Old Empire", "fleet_size": 20},
+                "2": {"name": "New Empire", "fleet_size": 5},
+            }),
+        );
+
+        let mut changes = diff_gamestates(&before, &after, None);
+        changes.sort_by(|a, b| a.path.cmp(&b.path));
+
+        assert_eq!(changes.len(), 3);
+        assert_eq!(changes[0].path, "country.0.fleet_size");
+        assert!(matches!(changes[0].kind, DiffKind::Changed));
+        assert_eq!(changes[0].before, Some(json!(10)));
+        assert_eq!(changes[0].after, Some(json!(20)));
+        assert_eq!(changes[1].path, "country.1");
+        assert!(matches!(changes[1].kind, DiffKind::Removed));
+        assert_eq!(changes[2].path, "country.2");
+        assert!(matches!(changes[2].kind, DiffKind::Added));
+    }
+
+    #[test]
+    fn test_diff_values_ignores_array_reorder() {
+        let mut out = Vec::new();
+        let before = json!({"members": ["alice", "bob"]});
+        let after = json!({"members": ["bob", "alice"]});
+        diff_values("federation", Some(&before), Some(&after), &mut out);
+        assert!(out.is_empty());
+
+        let mut out = Vec::new();
+        let before = json!({"members": ["alice", "bob"]});
+        let after = json!({"members": ["bob", "carol"]});
+        diff_values("federation", Some(&before), Some(&after), &mut out);
+        assert_eq!(out.len(), 1);
+        assert_eq!(out[0].path, "federation.members");
+        assert!(matches!(out[0].kind, DiffKind::Changed));
+    }
+
+    #[test]
+    fn test_poll_request_parses_with_defaults() {
+        let json = r#"{"op": "poll"}"#;
         let req: Request = serde_json::from_str(json).unwrap();
         match req {
-            Request::Multi { ops } => {
-                assert_eq!(ops.len(), 8);
+            Request::Poll { token, sections } => {
+                assert_eq!(token, 0);
+                assert!(sections.is_empty());
             }
             _ => panic!("Wrong request type"),
         }
     }
+
+    #[test]
+    fn test_file_fingerprint_changes_when_file_is_rewritten() {
+        let save_path = std::env::temp_dir().join(format!(
+            "stellaris-companion-test-poll-fingerprint-{}.sav",
+            std::process::id()
+        ));
+        std::fs::write(&save_path, b"gamestate=yes").unwrap();
+        let before = file_fingerprint(save_path.to_str().unwrap()).unwrap();
+
+        std::fs::write(&save_path, b"gamestate=yes\nlonger_now=yes").unwrap();
+        let after = file_fingerprint(save_path.to_str().unwrap()).unwrap();
+
+        assert_ne!(before.0, after.0);
+        let _ = std::fs::remove_file(&save_path);
+    }
+
+    #[test]
+    fn test_poll_changes_empty_when_token_already_current() {
+        let mut gamestate = HashMap::new();
+        gamestate.insert("country".to_string(), json!({"0": {"fleet_size": 10}}));
+        let state = PollState {
+            generation: 3,
+            file_len: 0,
+            modified_unix_secs: 0,
+            gamestate,
+            previous_gamestate: None,
+        };
+
+        assert!(poll_changes(&state, 3, &[]).is_empty());
+        assert!(poll_changes(&state, 4, &[]).is_empty());
+    }
+
+    #[test]
+    fn test_poll_changes_restricted_to_requested_sections() {
+        let mut previous = HashMap::new();
+        previous.insert("country".to_string(), json!({"0": {"fleet_size": 10}}));
+        previous.insert("leaders".to_string(), json!({"0": {"alive": true}}));
+
+        let mut current = HashMap::new();
+        current.insert("country".to_string(), json!({"0": {"fleet_size": 20}}));
+        current.insert("leaders".to_string(), json!({"0": {"alive": false}}));
+
+        let state = PollState {
+            generation: 2,
+            file_len: 0,
+            modified_unix_secs: 0,
+            gamestate: current,
+            previous_gamestate: Some(previous),
+        };
+
+        let changes = poll_changes(&state, 1, &["country".to_string()]);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].path, "country.0.fleet_size");
+
+        let changes = poll_changes(&state, 1, &[]);
+        assert_eq!(changes.len(), 2);
+    }
 }
@@ -8,6 +8,12 @@
 //! - Escape sequences (\" and \\) → handled properly
 //!
 //! See docs/RUST_PARSER_ARCHITECTURE.md for the full architecture decision record.
+//!
+//! For broader, fixture-driven coverage of these quirks (and others this
+//! module doesn't have room to enumerate inline), see
+//! `commands::conformance`, which runs a directory of paired
+//! `<name>.clausewitz`/`<name>.json` fixtures instead of hardcoded byte
+//! literals.
 
 #[cfg(test)]
 mod tests {
@@ -0,0 +1,148 @@
+//! Configurable input text encoding for Clausewitz save bodies.
+//!
+//! `output::decode_gamestate`'s UTF-8-first/Windows-1252-fallback heuristic
+//! (see that module) covers the vast majority of saves, but community mods
+//! and some localizations store user-entered strings (empire/planet/fleet
+//! names) in other code pages — Windows-1251 for Cyrillic, GBK/Big5/GB18030
+//! for CJK mod text. This module exposes those as an explicit `--encoding`
+//! choice, decoded through `encoding_rs` into a UTF-8 buffer before handing
+//! the bytes to jomini's plaintext parser.
+
+use crate::error::CompanionError;
+use encoding_rs::Encoding as RsEncoding;
+
+/// Supported input encodings for plain-text gamestate/meta bodies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Windows1250,
+    Windows1251,
+    Windows1252,
+    Windows1253,
+    Windows1254,
+    Windows1255,
+    Windows1256,
+    Windows1257,
+    Windows1258,
+    Gb18030,
+    Gbk,
+    Big5,
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+}
+
+impl Encoding {
+    fn as_encoding_rs(self) -> &'static RsEncoding {
+        match self {
+            Encoding::Windows1250 => encoding_rs::WINDOWS_1250,
+            Encoding::Windows1251 => encoding_rs::WINDOWS_1251,
+            Encoding::Windows1252 => encoding_rs::WINDOWS_1252,
+            Encoding::Windows1253 => encoding_rs::WINDOWS_1253,
+            Encoding::Windows1254 => encoding_rs::WINDOWS_1254,
+            Encoding::Windows1255 => encoding_rs::WINDOWS_1255,
+            Encoding::Windows1256 => encoding_rs::WINDOWS_1256,
+            Encoding::Windows1257 => encoding_rs::WINDOWS_1257,
+            Encoding::Windows1258 => encoding_rs::WINDOWS_1258,
+            Encoding::Gb18030 => encoding_rs::GB18030,
+            Encoding::Gbk => encoding_rs::GBK,
+            Encoding::Big5 => encoding_rs::BIG5,
+            Encoding::Utf8 => encoding_rs::UTF_8,
+            Encoding::Utf16Le => encoding_rs::UTF_16LE,
+            Encoding::Utf16Be => encoding_rs::UTF_16BE,
+        }
+    }
+
+    /// Decode `bytes` into a UTF-8 buffer using this encoding. Malformed
+    /// sequences are replaced per `encoding_rs`'s WHATWG decode algorithm
+    /// (the same behavior a browser's `TextDecoder` gives you) rather than
+    /// failing outright, since a single mis-encoded name shouldn't sink an
+    /// entire export.
+    pub fn decode(self, bytes: &[u8]) -> String {
+        self.as_encoding_rs().decode(bytes).0.into_owned()
+    }
+}
+
+impl Default for Encoding {
+    fn default() -> Self {
+        Encoding::Windows1252
+    }
+}
+
+impl TryFrom<&str> for Encoding {
+    type Error = CompanionError;
+
+    fn try_from(label: &str) -> Result<Self, Self::Error> {
+        match label.to_ascii_lowercase().as_str() {
+            "windows-1250" => Ok(Encoding::Windows1250),
+            "windows-1251" => Ok(Encoding::Windows1251),
+            "windows-1252" => Ok(Encoding::Windows1252),
+            "windows-1253" => Ok(Encoding::Windows1253),
+            "windows-1254" => Ok(Encoding::Windows1254),
+            "windows-1255" => Ok(Encoding::Windows1255),
+            "windows-1256" => Ok(Encoding::Windows1256),
+            "windows-1257" => Ok(Encoding::Windows1257),
+            "windows-1258" => Ok(Encoding::Windows1258),
+            "gb18030" => Ok(Encoding::Gb18030),
+            "gbk" => Ok(Encoding::Gbk),
+            "big5" => Ok(Encoding::Big5),
+            "utf-8" | "utf8" => Ok(Encoding::Utf8),
+            "utf-16le" => Ok(Encoding::Utf16Le),
+            "utf-16be" => Ok(Encoding::Utf16Be),
+            other => Err(CompanionError::InvalidArgument {
+                detail: format!(
+                    "Unknown encoding: {:?} (expected one of windows-1250..1258, \
+                     gb18030, gbk, big5, utf-8, utf-16le, utf-16be)",
+                    other
+                ),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_try_from_accepts_known_labels() {
+        assert_eq!(Encoding::try_from("windows-1252").unwrap(), Encoding::Windows1252);
+        assert_eq!(Encoding::try_from("Windows-1251").unwrap(), Encoding::Windows1251);
+        assert_eq!(Encoding::try_from("gb18030").unwrap(), Encoding::Gb18030);
+        assert_eq!(Encoding::try_from("utf-8").unwrap(), Encoding::Utf8);
+        assert_eq!(Encoding::try_from("utf8").unwrap(), Encoding::Utf8);
+    }
+
+    #[test]
+    fn test_try_from_rejects_unknown_label() {
+        let result = Encoding::try_from("latin-9");
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_default_is_windows_1252() {
+        assert_eq!(Encoding::default(), Encoding::Windows1252);
+    }
+
+    #[test]
+    fn test_decode_windows_1251_cyrillic() {
+        // Windows-1251 byte 0xEF = 'п' (Cyrillic small letter pe)
+        let decoded = Encoding::Windows1251.decode(&[0xEF]);
+        assert_eq!(decoded, "п");
+    }
+
+    #[test]
+    fn test_decode_ascii_is_passthrough_for_every_encoding() {
+        let ascii = b"name=\"Test Empire\"";
+        for encoding in [
+            Encoding::Windows1250,
+            Encoding::Windows1251,
+            Encoding::Windows1252,
+            Encoding::Gb18030,
+            Encoding::Gbk,
+            Encoding::Big5,
+            Encoding::Utf8,
+        ] {
+            assert_eq!(encoding.decode(ascii), "name=\"Test Empire\"");
+        }
+    }
+}
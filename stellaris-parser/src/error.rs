@@ -1,13 +1,184 @@
 use serde_json::json;
+use std::io::IsTerminal;
+use std::sync::OnceLock;
+use thiserror::Error;
 
-pub const SCHEMA_VERSION: u32 = 1;
+pub const SCHEMA_VERSION: u32 = 2;
 pub const TOOL_VERSION: &str = env!("CARGO_PKG_VERSION");
 
-/// Error types that map to specific exit codes
+/// Environment variable that selects the error output format, overriding the
+/// TTY-based default. The `--error-format` CLI flag takes precedence over it.
+pub const ERROR_FORMAT_ENV_VAR: &str = "STELLARIS_COMPANION_ERROR_FORMAT";
+
+/// How errors are rendered to stderr.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorFormat {
+    /// The existing machine-readable JSON object.
+    Json,
+    /// A concise human message plus an indented cause chain.
+    Rendered,
+}
+
+impl ErrorFormat {
+    /// Parse a `--error-format`/env var value. Unrecognized values are `None`
+    /// so callers can fall back to the TTY-based default.
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "json" => Some(ErrorFormat::Json),
+            "rendered" => Some(ErrorFormat::Rendered),
+            _ => None,
+        }
+    }
+}
+
+static ERROR_FORMAT: OnceLock<ErrorFormat> = OnceLock::new();
+
+/// Explicitly select the error output format for the rest of the process.
+/// Called once from `main` after resolving `--error-format` / the
+/// `STELLARIS_COMPANION_ERROR_FORMAT` env var.
+pub fn set_error_format(format: ErrorFormat) {
+    let _ = ERROR_FORMAT.set(format);
+}
+
+/// The active error format: whatever was explicitly set, or `Rendered` for
+/// an interactive stderr and `Json` otherwise.
+fn error_format() -> ErrorFormat {
+    *ERROR_FORMAT.get_or_init(|| {
+        if std::io::stderr().is_terminal() {
+            ErrorFormat::Rendered
+        } else {
+            ErrorFormat::Json
+        }
+    })
+}
+
+/// Typed errors for save loading/parsing.
+///
+/// Unlike the old approach of guessing a classification from the formatted
+/// message of a generic `anyhow::Error`, each variant here carries its
+/// classification directly, so `handle_error` never has to scan message
+/// text to pick an exit code.
+#[derive(Debug, Error)]
+pub enum CompanionError {
+    #[error("Failed to open file: {path}")]
+    FileNotFound { path: String },
+
+    #[error("Failed to parse: {source:#}")]
+    ParseError {
+        #[source]
+        source: anyhow::Error,
+        /// Byte offset of the first invalid UTF-8 sequence, if the loader
+        /// had to fall back to a Windows-1252 decode before parsing.
+        decode_offset: Option<usize>,
+    },
+
+    #[error("Invalid argument: {detail}")]
+    InvalidArgument { detail: String },
+
+    #[error("No gamestate file in archive")]
+    MissingGamestate,
+
+    #[error("No meta file in archive")]
+    MissingMeta,
+
+    #[error("Failed to read ZIP archive: {source:#}")]
+    CorruptArchive {
+        #[source]
+        source: anyhow::Error,
+    },
+
+    #[error("Unsupported save version: {found}")]
+    UnsupportedSaveVersion { found: String },
+
+    #[error("Requested schema version {requested} is not supported. Supported: {supported}")]
+    SchemaVersionMismatch { requested: String, supported: u32 },
+
+    #[error("Checksum mismatch: expected {expected}, got {actual}")]
+    ChecksumMismatch { expected: String, actual: String },
+
+    #[error("Unknown binary token id: 0x{token_id:04X}")]
+    UnknownToken { token_id: u16 },
+
+    #[error("Failed to encode output as {format}: {source:#}")]
+    SerializeError {
+        format: String,
+        #[source]
+        source: anyhow::Error,
+    },
+}
+
+impl CompanionError {
+    /// Classify this error into the stable `ErrorKind` used for exit codes.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            CompanionError::FileNotFound { .. } => ErrorKind::FileNotFound,
+            CompanionError::ParseError { .. } => ErrorKind::ParseError,
+            CompanionError::InvalidArgument { .. } => ErrorKind::InvalidArgument,
+            CompanionError::MissingGamestate => ErrorKind::MissingGamestate,
+            CompanionError::MissingMeta => ErrorKind::MissingMeta,
+            CompanionError::CorruptArchive { .. } => ErrorKind::CorruptArchive,
+            CompanionError::UnsupportedSaveVersion { .. } => ErrorKind::UnsupportedSaveVersion,
+            CompanionError::SchemaVersionMismatch { .. } => ErrorKind::SchemaVersionMismatch,
+            CompanionError::ChecksumMismatch { .. } => ErrorKind::ChecksumMismatch,
+            CompanionError::UnknownToken { .. } => ErrorKind::UnknownToken,
+            CompanionError::SerializeError { .. } => ErrorKind::SerializeError,
+        }
+    }
+
+    /// The process exit code this error maps to, per the table in `ErrorKind`.
+    pub fn exit_code(&self) -> i32 {
+        self.kind().exit_code()
+    }
+
+    /// Render this error (plus its cause chain) as the same JSON object
+    /// `handle_error` would print, so embedders that catch a `CompanionError`
+    /// directly can still reproduce the CLI's machine-readable output.
+    pub fn to_json(&self) -> serde_json::Value {
+        let mut causes = vec![self.to_string()];
+        let mut source: Option<&dyn std::error::Error> = std::error::Error::source(self);
+        while let Some(err) = source {
+            causes.push(err.to_string());
+            source = err.source();
+        }
+        let mut error_json = json!({
+            "schema_version": SCHEMA_VERSION,
+            "tool_version": TOOL_VERSION,
+            "error": self.kind().error_type(),
+            "message": self.to_string(),
+        });
+        if let Some(top_cause) = causes.first() {
+            error_json["cause"] = json!(top_cause);
+            error_json["causes"] = json!(causes);
+        }
+        if let CompanionError::ParseError {
+            decode_offset: Some(offset),
+            ..
+        } = self
+        {
+            error_json["decode_offset"] = json!(offset);
+        }
+        error_json
+    }
+}
+
+/// Error types that map to specific exit codes.
+///
+/// Codes 1-3 are the original generic categories. Codes 10-19 are a reserved
+/// range for save-domain failures so automation can distinguish, say, a
+/// corrupt archive from an unsupported save version without parsing message
+/// text.
 pub enum ErrorKind {
     FileNotFound,
     ParseError,
     InvalidArgument,
+    UnsupportedSaveVersion,
+    SchemaVersionMismatch,
+    ChecksumMismatch,
+    MissingGamestate,
+    MissingMeta,
+    CorruptArchive,
+    UnknownToken,
+    SerializeError,
 }
 
 impl ErrorKind {
@@ -16,6 +187,14 @@ impl ErrorKind {
             ErrorKind::FileNotFound => 1,
             ErrorKind::ParseError => 2,
             ErrorKind::InvalidArgument => 3,
+            ErrorKind::UnsupportedSaveVersion => 10,
+            ErrorKind::SchemaVersionMismatch => 11,
+            ErrorKind::ChecksumMismatch => 12,
+            ErrorKind::MissingGamestate => 13,
+            ErrorKind::MissingMeta => 14,
+            ErrorKind::CorruptArchive => 15,
+            ErrorKind::UnknownToken => 16,
+            ErrorKind::SerializeError => 17,
         }
     }
 
@@ -24,42 +203,109 @@ impl ErrorKind {
             ErrorKind::FileNotFound => "FileNotFound",
             ErrorKind::ParseError => "ParseError",
             ErrorKind::InvalidArgument => "InvalidArgument",
+            ErrorKind::UnsupportedSaveVersion => "UnsupportedSaveVersion",
+            ErrorKind::SchemaVersionMismatch => "SchemaVersionMismatch",
+            ErrorKind::ChecksumMismatch => "ChecksumMismatch",
+            ErrorKind::MissingGamestate => "MissingGamestate",
+            ErrorKind::MissingMeta => "MissingMeta",
+            ErrorKind::CorruptArchive => "CorruptArchive",
+            ErrorKind::UnknownToken => "UnknownToken",
+            ErrorKind::SerializeError => "SerializeError",
         }
     }
+
+    /// The full, documented exit code table: `(error_type, exit_code)` for
+    /// every kind, so automation can branch on the precise failure without
+    /// hardcoding the numbers in multiple places.
+    pub fn code_table() -> &'static [(&'static str, i32)] {
+        &[
+            ("FileNotFound", 1),
+            ("ParseError", 2),
+            ("InvalidArgument", 3),
+            ("UnsupportedSaveVersion", 10),
+            ("SchemaVersionMismatch", 11),
+            ("ChecksumMismatch", 12),
+            ("MissingGamestate", 13),
+            ("MissingMeta", 14),
+            ("CorruptArchive", 15),
+            ("UnknownToken", 16),
+            ("SerializeError", 17),
+        ]
+    }
 }
 
 /// Print error as JSON to stderr and exit with appropriate code
 pub fn exit_with_error(kind: ErrorKind, message: &str) -> ! {
-    let error_json = json!({
-        "schema_version": SCHEMA_VERSION,
-        "tool_version": TOOL_VERSION,
-        "error": kind.error_type(),
-        "message": message
-    });
-    eprintln!("{}", error_json);
+    exit_with_error_detailed(kind, message, &[], None)
+}
+
+/// Print an error to stderr in the active `ErrorFormat`, including the
+/// layered cause chain, and exit.
+///
+/// `causes` should be the `Display` of each layer of the underlying error,
+/// outermost first (as produced by `anyhow::Error::chain`). In `Json` mode
+/// the flat `"message"` field is kept alongside for backward compatibility
+/// with consumers that only look at the top-level string. `decode_offset`
+/// is the byte offset `CompanionError::ParseError` carries when the loader
+/// had to fall back to a Windows-1252 decode; `None` for every other error.
+fn exit_with_error_detailed(
+    kind: ErrorKind,
+    message: &str,
+    causes: &[String],
+    decode_offset: Option<usize>,
+) -> ! {
+    match error_format() {
+        ErrorFormat::Json => {
+            let mut error_json = json!({
+                "schema_version": SCHEMA_VERSION,
+                "tool_version": TOOL_VERSION,
+                "error": kind.error_type(),
+                "message": message,
+            });
+            if let Some(top_cause) = causes.first() {
+                error_json["cause"] = json!(top_cause);
+                error_json["causes"] = json!(causes);
+            }
+            if let Some(offset) = decode_offset {
+                error_json["decode_offset"] = json!(offset);
+            }
+            eprintln!("{}", error_json);
+        }
+        ErrorFormat::Rendered => {
+            eprintln!("error: {}", message);
+            // `message` already is the outermost cause, so only render the
+            // deeper layers underneath it.
+            for cause in causes.iter().skip(1) {
+                eprintln!("  caused by: {}", cause);
+            }
+        }
+    }
     std::process::exit(kind.exit_code());
 }
 
-/// Convert an anyhow error to a JSON error and exit
+/// Report an anyhow error on stderr in the active `ErrorFormat` and exit.
+///
+/// If the error (or one of its causes) is a `CompanionError`, its variant
+/// supplies the classification directly. Otherwise this falls back to
+/// `ErrorKind::ParseError` for unclassified cases, so callers that haven't
+/// been migrated to return `CompanionError` yet still get a sane exit code.
 pub fn handle_error(err: anyhow::Error) -> ! {
     let message = format!("{:#}", err);
 
-    // Determine error kind from message content
-    let kind = if message.contains("No such file or directory")
-        || message.contains("Failed to open file")
-        || message.contains("cannot find the file")
-        || message.contains("The system cannot find")
-    {
-        ErrorKind::FileNotFound
-    } else if message.contains("Failed to parse")
-        || message.contains("Failed to read ZIP")
-        || message.contains("No gamestate file")
-        || message.contains("No meta file")
-    {
-        ErrorKind::ParseError
-    } else {
-        ErrorKind::ParseError // Default to parse error for other issues
-    };
-
-    exit_with_error(kind, &message);
+    let companion_err = err
+        .chain()
+        .find_map(|cause| cause.downcast_ref::<CompanionError>());
+
+    let kind = companion_err
+        .map(CompanionError::kind)
+        .unwrap_or(ErrorKind::ParseError);
+
+    let decode_offset = companion_err.and_then(|err| match err {
+        CompanionError::ParseError { decode_offset, .. } => *decode_offset,
+        _ => None,
+    });
+
+    let causes: Vec<String> = err.chain().map(|cause| cause.to_string()).collect();
+
+    exit_with_error_detailed(kind, &message, &causes, decode_offset);
 }
@@ -0,0 +1,389 @@
+//! SAX-style streaming event API over a parsed Clausewitz value tree.
+//!
+//! `commands::iter`'s `IterSave` decodes a section through
+//! `output::decode_auto`, which deserializes the *entire* gamestate into an
+//! in-memory `HashMap<String, Value>` before `IterSave` ever looks at the
+//! one section it actually wants — on a large empire/galaxy section that's
+//! gigabytes of short-lived allocation just to throw most of it away. This
+//! module instead walks jomini's mid-level `ObjectReader`/`ValueReader` API
+//! directly, the way git-config's `Event` iterator walks a config file:
+//! events hold `Cow` borrows into the source buffer and are only promoted to
+//! an owned `String` where jomini itself had to unescape one. `IterSave`'s
+//! `--streaming` flag (see `commands::iter::run_save`) uses this to process
+//! one top-level entry of the requested section at a time, so peak memory
+//! becomes O(single entry) instead of O(whole gamestate).
+//!
+//! Most Stellaris scalars — identifiers (`trait_adaptive`), booleans
+//! (`yes`/`no`), numeric-looking tokens — are plain ASCII with no `\"`/`\\`
+//! escape anywhere in them, so `Field`/`Scalar` borrow straight from the
+//! tape's buffer (`Cow::Borrowed`) in the common case and only allocate an
+//! owned, unescaped `String` for the minority of values that actually need
+//! it (quoted names with an embedded quote, Windows paths).
+
+use crate::error::CompanionError;
+use jomini::text::{ObjectReader, ValueReader};
+use jomini::Encoding as JominiEncoding;
+use serde_json::{Map, Value};
+use std::borrow::Cow;
+
+/// One step of a streamed walk over a single Clausewitz value. `Field`
+/// precedes the value it names; `ObjectStart`/`ArrayStart` bracket their
+/// children, each closed by a matching `End`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event<'a> {
+    ObjectStart,
+    Field(Cow<'a, str>),
+    Scalar(Cow<'a, str>),
+    ArrayStart,
+    End,
+}
+
+/// Recursively walk `value`, invoking `visit` for each `Event` in document
+/// order. This is the zero-materialization counterpart of
+/// `output::value_to_json`: nothing here builds a `serde_json::Value` tree,
+/// so memory use is bounded by the recursion depth, not the value's size.
+pub fn walk_value<'data, 'tape, E: JominiEncoding + Clone>(
+    value: ValueReader<'data, 'tape, E>,
+    visit: &mut impl FnMut(Event<'data>) -> Result<(), CompanionError>,
+) -> Result<(), CompanionError> {
+    if let Ok(obj) = value.read_object() {
+        return walk_object(obj, visit);
+    }
+    if let Ok(arr) = value.read_array() {
+        visit(Event::ArrayStart)?;
+        for entry in arr.values() {
+            walk_value(entry, visit)?;
+        }
+        return visit(Event::End);
+    }
+    visit(Event::Scalar(lazy_scalar(value)?))
+}
+
+fn walk_object<'data, 'tape, E: JominiEncoding + Clone>(
+    obj: ObjectReader<'data, 'tape, E>,
+    visit: &mut impl FnMut(Event<'data>) -> Result<(), CompanionError>,
+) -> Result<(), CompanionError> {
+    visit(Event::ObjectStart)?;
+    for (key, _operator, value) in obj.fields() {
+        visit(Event::Field(lazy_key(key)))?;
+        walk_value(value, visit)?;
+    }
+    visit(Event::End)
+}
+
+/// Borrow a scalar value straight from the tape's buffer when it's ASCII
+/// and contains no `\` escape — the raw bytes already ARE the decoded text
+/// in that case, under either UTF-8 or Windows-1252. Otherwise fall back to
+/// `ValueReader::read_string`, which does the real unescape/decode work.
+fn lazy_scalar<'data, 'tape, E: JominiEncoding + Clone>(
+    value: ValueReader<'data, 'tape, E>,
+) -> Result<Cow<'data, str>, CompanionError> {
+    let scalar = value.read_scalar().map_err(tape_error)?;
+    let bytes = scalar.view_data();
+    if is_ascii_no_backslash(bytes) {
+        Ok(Cow::Borrowed(
+            std::str::from_utf8(bytes).expect("ascii is valid utf-8"),
+        ))
+    } else {
+        Ok(Cow::Owned(value.read_string().map_err(tape_error)?))
+    }
+}
+
+/// Same borrow-when-cheap strategy as `lazy_scalar`, applied to an object
+/// field's key instead of its value. Falls back to `Scalar::to_string`,
+/// matching `output::object_to_map`'s existing (unescaped) key handling.
+fn lazy_key(key: jomini::Scalar<'_>) -> Cow<'_, str> {
+    let bytes = key.view_data();
+    if is_ascii_no_backslash(bytes) {
+        Cow::Borrowed(std::str::from_utf8(bytes).expect("ascii is valid utf-8"))
+    } else {
+        Cow::Owned(key.to_string())
+    }
+}
+
+fn is_ascii_no_backslash(bytes: &[u8]) -> bool {
+    bytes.iter().all(|&b| b < 0x80 && b != b'\\')
+}
+
+/// Find `section` among `root`'s top-level fields and invoke `on_entry` once
+/// per field of its sub-object, without ever materializing the rest of the
+/// document. Returns `false` if `section` isn't a top-level key at all, so
+/// callers can still emit a `begin`/`summary` pair with zero entries the way
+/// `commands::iter::stream_jsonl` does for a missing section; a section
+/// that exists but isn't an object also yields zero entries (returns
+/// `true` with `on_entry` never called), matching that same contract.
+pub fn stream_section<'data, 'tape, E: JominiEncoding + Clone>(
+    root: ObjectReader<'data, 'tape, E>,
+    section: &str,
+    mut on_entry: impl FnMut(Cow<'data, str>, ValueReader<'data, 'tape, E>) -> Result<(), CompanionError>,
+) -> Result<bool, CompanionError> {
+    for (key, _operator, value) in root.fields() {
+        if key.to_string() != section {
+            continue;
+        }
+        if let Ok(obj) = value.read_object() {
+            for (entry_key, _operator, entry_value) in obj.fields() {
+                on_entry(lazy_key(entry_key), entry_value)?;
+            }
+        }
+        return Ok(true);
+    }
+    Ok(false)
+}
+
+/// Convert a single value to its `serde_json::Value` by walking it through
+/// the `Event` API, bounding the conversion to that one value's size instead
+/// of needing a whole-document `Value` tree already in memory. Scalars come
+/// out typed (numbers, booleans, strings); unlike `output::value_to_json`, a
+/// key repeated within one object keeps only its last value instead of being
+/// grouped into an array.
+pub fn entry_to_value<E: JominiEncoding + Clone>(
+    value: ValueReader<'_, '_, E>,
+) -> Result<Value, CompanionError> {
+    let mut events = Vec::new();
+    walk_value(value, &mut |event| {
+        events.push(event.into_owned());
+        Ok(())
+    })?;
+    let mut pos = 0;
+    let value = build_value(&events, &mut pos);
+    Ok(value)
+}
+
+impl<'a> Event<'a> {
+    /// Detach this event from the source buffer's lifetime, for callers
+    /// (like `entry_to_value`) that need to collect a whole entry's worth of
+    /// events before consuming them.
+    fn into_owned(self) -> Event<'static> {
+        match self {
+            Event::ObjectStart => Event::ObjectStart,
+            Event::ArrayStart => Event::ArrayStart,
+            Event::End => Event::End,
+            Event::Field(s) => Event::Field(Cow::Owned(s.into_owned())),
+            Event::Scalar(s) => Event::Scalar(Cow::Owned(s.into_owned())),
+        }
+    }
+}
+
+/// Rebuild a `serde_json::Value` from a flat, well-formed `Event` sequence
+/// (as produced by `walk_value`): `ObjectStart`/`ArrayStart` and their
+/// matching `End` nest exactly the way the original value did.
+fn build_value(events: &[Event<'static>], pos: &mut usize) -> Value {
+    match &events[*pos] {
+        Event::ObjectStart => {
+            *pos += 1;
+            let mut map = Map::new();
+            loop {
+                match &events[*pos] {
+                    Event::Field(key) => {
+                        let key = key.clone().into_owned();
+                        *pos += 1;
+                        map.insert(key, build_value(events, pos));
+                    }
+                    Event::End => {
+                        *pos += 1;
+                        break;
+                    }
+                    other => unreachable!("object body must be Field or End, got {:?}", other),
+                }
+            }
+            Value::Object(map)
+        }
+        Event::ArrayStart => {
+            *pos += 1;
+            let mut items = Vec::new();
+            while !matches!(events[*pos], Event::End) {
+                items.push(build_value(events, pos));
+            }
+            *pos += 1;
+            Value::Array(items)
+        }
+        Event::Scalar(s) => {
+            let s = s.clone().into_owned();
+            *pos += 1;
+            crate::output::scalar_text_to_json(s)
+        }
+        other => unreachable!("a value must start with ObjectStart/ArrayStart/Scalar, got {:?}", other),
+    }
+}
+
+fn tape_error(e: jomini::Error) -> CompanionError {
+    CompanionError::ParseError {
+        source: anyhow::Error::new(e),
+        decode_offset: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jomini::TextTape;
+
+    #[test]
+    fn test_walk_value_emits_object_events_in_order() {
+        let tape = TextTape::from_slice(br#"empire={name="Test Empire" id=42}"#).unwrap();
+        let root = tape.utf8_reader();
+        let (_, _, value) = root.fields().next().expect("empire field");
+
+        let mut events = Vec::new();
+        walk_value(value, &mut |event| {
+            events.push(event.into_owned());
+            Ok(())
+        })
+        .unwrap();
+
+        assert_eq!(
+            events,
+            vec![
+                Event::ObjectStart,
+                Event::Field(Cow::Borrowed("name")),
+                Event::Scalar(Cow::Borrowed("Test Empire")),
+                Event::Field(Cow::Borrowed("id")),
+                Event::Scalar(Cow::Borrowed("42")),
+                Event::End,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_walk_value_emits_array_events() {
+        let tape = TextTape::from_slice(br#"fleet={ ships={1 2 3} }"#).unwrap();
+        let root = tape.utf8_reader();
+        let (_, _, fleet) = root.fields().next().unwrap();
+        let fleet_obj = fleet.read_object().unwrap();
+        let (_, _, ships) = fleet_obj.fields().next().unwrap();
+
+        let mut events = Vec::new();
+        walk_value(ships, &mut |event| {
+            events.push(event.into_owned());
+            Ok(())
+        })
+        .unwrap();
+
+        assert_eq!(
+            events,
+            vec![
+                Event::ArrayStart,
+                Event::Scalar(Cow::Borrowed("1")),
+                Event::Scalar(Cow::Borrowed("2")),
+                Event::Scalar(Cow::Borrowed("3")),
+                Event::End,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_stream_section_finds_requested_section() {
+        let data = br#"
+country={
+    1={name="Empire A"}
+    2={name="Empire B"}
+}
+other_section={x=1}
+"#;
+        let tape = TextTape::from_slice(data).unwrap();
+        let root = tape.utf8_reader();
+
+        let mut seen = Vec::new();
+        let found = stream_section(root, "country", |key, value| {
+            let value = entry_to_value(value)?;
+            seen.push((key.into_owned(), value));
+            Ok(())
+        })
+        .unwrap();
+
+        assert!(found);
+        assert_eq!(seen.len(), 2);
+        assert_eq!(seen[0].0, "1");
+        assert_eq!(seen[0].1["name"], "Empire A");
+    }
+
+    #[test]
+    fn test_stream_section_missing_returns_false() {
+        let data = br#"country={1={name="Empire A"}}"#;
+        let tape = TextTape::from_slice(data).unwrap();
+        let root = tape.utf8_reader();
+
+        let mut calls = 0;
+        let found = stream_section(root, "no_such_section", |_, _| {
+            calls += 1;
+            Ok(())
+        })
+        .unwrap();
+
+        assert!(!found);
+        assert_eq!(calls, 0);
+    }
+
+    #[test]
+    fn test_entry_to_value_types_scalars_and_keeps_last_duplicate() {
+        let data = br#"entry={name="Test" id=42 active=yes traits={trait=a trait=b}}"#;
+        let tape = TextTape::from_slice(data).unwrap();
+        let root = tape.utf8_reader();
+        let (_, _, value) = root.fields().next().unwrap();
+
+        let via_events = entry_to_value(value).unwrap();
+        assert_eq!(via_events["name"], "Test");
+        assert_eq!(via_events["id"], 42);
+        assert_eq!(via_events["active"], true);
+        assert_eq!(via_events["traits"]["trait"], "b");
+    }
+
+    #[test]
+    fn test_escape_free_scalars_and_keys_borrow_from_the_buffer() {
+        let tape = TextTape::from_slice(br#"empire={trait_adaptive=yes}"#).unwrap();
+        let root = tape.utf8_reader();
+        let (_, _, value) = root.fields().next().unwrap();
+
+        let mut events = Vec::new();
+        walk_value(value, &mut |event| {
+            events.push(event);
+            Ok(())
+        })
+        .unwrap();
+
+        match &events[1] {
+            Event::Field(key) => assert!(
+                matches!(key, Cow::Borrowed(_)),
+                "escape-free key should borrow, got {:?}",
+                key
+            ),
+            other => panic!("expected Field, got {:?}", other),
+        }
+        match &events[2] {
+            Event::Scalar(scalar) => assert!(
+                matches!(scalar, Cow::Borrowed(_)),
+                "escape-free scalar should borrow, got {:?}",
+                scalar
+            ),
+            other => panic!("expected Scalar, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_escaped_scalar_allocates_an_owned_unescaped_string() {
+        let tape =
+            TextTape::from_slice(br#"empire={name="The \"Great\" Empire"}"#).unwrap();
+        let root = tape.utf8_reader();
+        let (_, _, value) = root.fields().next().unwrap();
+
+        let mut events = Vec::new();
+        walk_value(value, &mut |event| {
+            events.push(event);
+            Ok(())
+        })
+        .unwrap();
+
+        match &events[2] {
+            Event::Scalar(scalar) => {
+                assert!(
+                    matches!(scalar, Cow::Owned(_)),
+                    "escaped scalar should allocate, got {:?}",
+                    scalar
+                );
+                assert!(scalar.contains("Great"));
+            }
+            other => panic!("expected Scalar, got {:?}", other),
+        }
+    }
+}
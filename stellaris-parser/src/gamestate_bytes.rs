@@ -0,0 +1,108 @@
+//! Shared owned-or-mapped byte storage for gamestate/meta bodies.
+//!
+//! Late-game Stellaris saves routinely decompress to hundreds of megabytes.
+//! Reading a ZIP entry fully into a `Vec<u8>` works, but that allocation
+//! stays resident for the whole command on top of whatever the text/binary
+//! decoders copy out of it. `GamestateBytes` lets `extract`/`iter` opt into
+//! extracting the entry to a temp file and viewing it through a
+//! `memmap2::Mmap` instead, trading the upfront read for page-cache-backed
+//! access; callers that don't care just get the simple owned path.
+
+use crate::error::CompanionError;
+use memmap2::Mmap;
+use std::fs::File;
+use std::io::Read;
+use std::ops::Deref;
+
+/// A gamestate/meta body, either fully read into memory or mapped from a
+/// temp file on disk. Both variants deref to `&[u8]`, so decoders that take
+/// `&[u8]` (e.g. `from_windows1252_slice(&bytes)`) don't need to know or
+/// care which backing a given instance uses.
+pub enum GamestateBytes {
+    Owned(Vec<u8>),
+    Mapped(Mmap),
+}
+
+impl GamestateBytes {
+    /// Read `reader` fully into an owned buffer.
+    pub fn read_owned<R: Read>(mut reader: R) -> std::io::Result<Self> {
+        let mut content = Vec::new();
+        reader.read_to_end(&mut content)?;
+        Ok(GamestateBytes::Owned(content))
+    }
+
+    /// Copy `reader` to a temp file under the OS temp dir and map it,
+    /// avoiding a second full-size allocation for the decode step. The temp
+    /// file is removed once it's mapped; the mapping itself keeps the
+    /// underlying pages readable after unlinking on every platform that
+    /// matters here (unlink-while-open is a no-op hazard on Unix, and
+    /// best-effort on Windows since this is just cleanup).
+    pub fn extract_and_map<R: Read>(mut reader: R, label: &str) -> Result<Self, CompanionError> {
+        let tmp_path = std::env::temp_dir().join(format!(
+            "stellaris-companion-{}-{}.tmp",
+            label,
+            std::process::id()
+        ));
+
+        {
+            let mut tmp_file = File::create(&tmp_path).map_err(|e| CompanionError::ParseError {
+                source: anyhow::Error::new(e).context(format!(
+                    "failed to create temp file for mmap at {}",
+                    tmp_path.display()
+                )),
+                decode_offset: None,
+            })?;
+            std::io::copy(&mut reader, &mut tmp_file).map_err(|e| CompanionError::ParseError {
+                source: anyhow::Error::new(e)
+                    .context("failed to copy gamestate entry to temp file"),
+                decode_offset: None,
+            })?;
+        }
+
+        let tmp_file = File::open(&tmp_path).map_err(|e| CompanionError::ParseError {
+            source: anyhow::Error::new(e).context(format!(
+                "failed to reopen temp file {} for mmap",
+                tmp_path.display()
+            )),
+            decode_offset: None,
+        })?;
+        // SAFETY: the temp file was just written by this process and isn't
+        // shared with anything else that might truncate it concurrently.
+        let mmap = unsafe { Mmap::map(&tmp_file) }.map_err(|e| CompanionError::ParseError {
+            source: anyhow::Error::new(e).context(format!("failed to mmap {}", tmp_path.display())),
+            decode_offset: None,
+        })?;
+        let _ = std::fs::remove_file(&tmp_path);
+
+        Ok(GamestateBytes::Mapped(mmap))
+    }
+}
+
+impl Deref for GamestateBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            GamestateBytes::Owned(v) => v.as_slice(),
+            GamestateBytes::Mapped(m) => &m[..],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_owned_round_trips_bytes() {
+        let bytes = GamestateBytes::read_owned(&b"hello gamestate"[..]).unwrap();
+        assert_eq!(&bytes[..], b"hello gamestate");
+    }
+
+    #[test]
+    fn test_extract_and_map_round_trips_bytes() {
+        let bytes =
+            GamestateBytes::extract_and_map(&b"mapped gamestate"[..], "test-roundtrip").unwrap();
+        assert_eq!(&bytes[..], b"mapped gamestate");
+    }
+}
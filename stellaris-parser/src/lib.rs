@@ -0,0 +1,16 @@
+//! Library core for the Stellaris save parser.
+//!
+//! Parsing/loading entry points return `Result`s instead of calling
+//! `process::exit`, so embedders (tests, GUIs, a future in-process server)
+//! can inspect and recover from failures. `handle_error`/`process::exit` are
+//! reserved for `main.rs`, the thin binary front-end over this library.
+
+pub mod binary;
+pub mod commands;
+pub mod edge_cases;
+pub mod encoding;
+pub mod error;
+pub mod events;
+pub mod gamestate_bytes;
+pub mod output;
+pub mod serialize;
@@ -1,9 +1,5 @@
 use clap::{Parser, Subcommand};
-
-mod commands;
-mod edge_cases;
-mod error;
-mod output;
+use stellaris_parser::{commands, encoding, error, serialize};
 
 #[derive(Parser)]
 #[command(name = "stellaris-parser")]
@@ -12,6 +8,12 @@ mod output;
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+
+    /// Error output format: "json" (machine-readable) or "rendered" (human-readable).
+    /// Defaults to "rendered" on an interactive terminal and "json" otherwise.
+    /// Can also be set via the STELLARIS_COMPANION_ERROR_FORMAT env var.
+    #[arg(long, global = true)]
+    error_format: Option<String>,
 }
 
 #[derive(Subcommand)]
@@ -29,8 +31,43 @@ enum Commands {
         /// Output file (- for stdout)
         #[arg(long, default_value = "-")]
         output: String,
+        /// Path to a binary token table (one `0xID name` pair per line),
+        /// required only when the save turns out to be binary (ironman)
+        #[arg(long)]
+        tokens: Option<String>,
+        /// Input text encoding for plain-text saves (windows-1250..1258,
+        /// gb18030, gbk, big5, utf-8, utf-16le, utf-16be)
+        #[arg(long, default_value = "windows-1252")]
+        encoding: String,
+        /// Extract the gamestate/meta entries to a temp file and mmap
+        /// them instead of reading them fully into memory (see
+        /// `gamestate_bytes::GamestateBytes`) — worth it for the
+        /// hundreds-of-MB gamestates a late-game save can produce.
+        #[arg(long)]
+        mmap: bool,
+        /// Strip Stellaris's `\x15`-prefixed in-game color markup from
+        /// every string in the output, leaving clean display text. Off by
+        /// default so the raw form is preserved.
+        #[arg(long)]
+        strip_color_codes: bool,
+        /// Decode through jomini's TextTape mid-level API instead of
+        /// straight into a HashMap, so a key that repeats within one
+        /// object (species traits, fleet lists) comes out as a JSON array
+        /// instead of only its last occurrence. No effect on binary
+        /// (ironman) saves.
+        #[arg(long)]
+        preserve_duplicates: bool,
+        /// Promote bare `Y.M.D[.H]` date strings (start_date, last_war, etc.)
+        /// to a tagged `{"__type": "date", ...}` object instead of leaving
+        /// them as plain strings. Off by default so existing consumers keep
+        /// seeing bare date strings.
+        #[arg(long)]
+        typed_dates: bool,
+        /// Output serialization: json, jsonl, toml, msgpack, or cbor
+        #[arg(long, default_value = "json")]
+        format: String,
     },
-    /// Iterate entries in a section (JSONL output) from a .sav file
+    /// Iterate entries in a section from a .sav file
     IterSave {
         /// Path to .sav file
         path: String,
@@ -40,9 +77,39 @@ enum Commands {
         /// Schema version for JSON contract
         #[arg(long, default_value = "1")]
         schema_version: String,
-        /// Output format
+        /// Output serialization: jsonl (streamed, default), json, toml,
+        /// msgpack, or cbor (the latter four buffer the whole section into
+        /// one document, since they have no line-oriented mode)
         #[arg(long, default_value = "jsonl")]
         format: String,
+        /// Path to a binary token table (one `0xID name` pair per line),
+        /// required only when the save turns out to be binary (ironman)
+        #[arg(long)]
+        tokens: Option<String>,
+        /// Input text encoding for plain-text saves (windows-1250..1258,
+        /// gb18030, gbk, big5, utf-8, utf-16le, utf-16be)
+        #[arg(long, default_value = "windows-1252")]
+        encoding: String,
+        /// Extract the gamestate entry to a temp file and mmap it
+        /// instead of reading it fully into memory (see
+        /// `gamestate_bytes::GamestateBytes`) — worth it for the
+        /// hundreds-of-MB gamestates a late-game save can produce.
+        #[arg(long)]
+        mmap: bool,
+        /// Decode through jomini's TextTape mid-level API instead of
+        /// straight into a HashMap, so a key that repeats within one
+        /// object comes out as a JSON array instead of only its last
+        /// occurrence. No effect on binary (ironman) saves.
+        #[arg(long)]
+        preserve_duplicates: bool,
+        /// Walk the requested section one entry at a time via
+        /// `events::stream_section` instead of decoding the whole gamestate
+        /// into memory first, bounding peak memory to a single entry
+        /// (see `commands::iter::run_streaming`) — worth it on large
+        /// empire/galaxy sections. No effect when combined with
+        /// `--preserve-duplicates` or on binary (ironman) saves.
+        #[arg(long)]
+        streaming: bool,
     },
     /// Extract sections from an already-extracted gamestate file (debug only)
     ExtractGamestate {
@@ -57,12 +124,69 @@ enum Commands {
         /// Output file (- for stdout)
         #[arg(long, default_value = "-")]
         output: String,
+        /// Path to a binary token table (one `0xID name` pair per line),
+        /// required only when the save turns out to be binary (ironman)
+        #[arg(long)]
+        tokens: Option<String>,
+        /// Input text encoding for plain-text saves (windows-1250..1258,
+        /// gb18030, gbk, big5, utf-8, utf-16le, utf-16be)
+        #[arg(long, default_value = "windows-1252")]
+        encoding: String,
+        /// Copy the gamestate file to a temp file and mmap it instead of
+        /// reading it fully into memory (see
+        /// `gamestate_bytes::GamestateBytes`) — worth it for the
+        /// hundreds-of-MB gamestates a late-game save can produce.
+        #[arg(long)]
+        mmap: bool,
+        /// Strip Stellaris's `\x15`-prefixed in-game color markup from
+        /// every string in the output, leaving clean display text. Off by
+        /// default so the raw form is preserved.
+        #[arg(long)]
+        strip_color_codes: bool,
+        /// Decode through jomini's TextTape mid-level API instead of
+        /// straight into a HashMap, so a key that repeats within one
+        /// object (species traits, fleet lists) comes out as a JSON array
+        /// instead of only its last occurrence. No effect on binary
+        /// (ironman) saves.
+        #[arg(long)]
+        preserve_duplicates: bool,
+        /// Promote bare `Y.M.D[.H]` date strings (start_date, last_war, etc.)
+        /// to a tagged `{"__type": "date", ...}` object instead of leaving
+        /// them as plain strings. Off by default so existing consumers keep
+        /// seeing bare date strings.
+        #[arg(long)]
+        typed_dates: bool,
+        /// Output serialization: json, jsonl, toml, msgpack, or cbor
+        #[arg(long, default_value = "json")]
+        format: String,
     },
     /// Start a session server (parse once, respond to multiple queries via stdin/stdout)
     Serve {
         /// Path to .sav file
         #[arg(long)]
         path: String,
+        /// Path to a binary token table (one `0xID name` pair per line),
+        /// required only when the save turns out to be binary (ironman)
+        #[arg(long)]
+        tokens: Option<String>,
+        /// Directory for the on-disk parsed-save cache. Defaults to a
+        /// `stellaris-companion-cache` subdirectory of the OS temp dir.
+        #[arg(long)]
+        cache_dir: Option<String>,
+        /// Disable the on-disk parsed-save cache (always re-parse the save).
+        #[arg(long)]
+        no_cache: bool,
+    },
+    /// Run the `<name>.clausewitz`/`<name>.json` fixture corpus under a
+    /// directory and report pass/fail/skip counts (internal; the fixture
+    /// corpus lives outside the Rust build, see `commands::conformance`)
+    #[command(hide = true)]
+    Conformance {
+        /// Directory of `<name>.clausewitz`/`<name>.json` fixture pairs
+        fixtures: String,
+        /// Output file for the JSON report (- for stdout)
+        #[arg(long, default_value = "-")]
+        output: String,
     },
 }
 
@@ -78,29 +202,137 @@ fn main() {
         }
     };
 
+    // Resolve the error output format: explicit flag wins, then the env
+    // var, then the TTY-based default handled lazily by `error::error_format`.
+    let format_str = cli
+        .error_format
+        .clone()
+        .or_else(|| std::env::var(error::ERROR_FORMAT_ENV_VAR).ok());
+    if let Some(format) = format_str.as_deref().and_then(error::ErrorFormat::parse) {
+        error::set_error_format(format);
+    }
+
     let result = match cli.command {
         Commands::ExtractSave {
             path,
             sections,
             schema_version,
             output,
-        } => commands::extract::run_save(&path, &sections, &schema_version, &output),
+            tokens,
+            encoding,
+            mmap,
+            strip_color_codes,
+            preserve_duplicates,
+            typed_dates,
+            format,
+        } => parse_encoding(&encoding).and_then(|encoding| {
+            parse_format(&format).and_then(|format| {
+                commands::extract::run_save(
+                    &path,
+                    &sections,
+                    &schema_version,
+                    &output,
+                    tokens.as_deref(),
+                    encoding,
+                    mmap,
+                    strip_color_codes,
+                    preserve_duplicates,
+                    typed_dates,
+                    format,
+                )
+            })
+        }),
         Commands::IterSave {
             path,
             section,
             schema_version,
             format,
-        } => commands::iter::run_save(&path, &section, &schema_version, &format),
+            tokens,
+            encoding,
+            mmap,
+            preserve_duplicates,
+            streaming,
+        } => parse_encoding(&encoding).and_then(|encoding| {
+            parse_format(&format).and_then(|format| {
+                commands::iter::run_save(
+                    &path,
+                    &section,
+                    &schema_version,
+                    format,
+                    tokens.as_deref(),
+                    encoding,
+                    mmap,
+                    preserve_duplicates,
+                    streaming,
+                )
+            })
+        }),
         Commands::ExtractGamestate {
             path,
             sections,
             schema_version,
             output,
-        } => commands::extract::run_gamestate(&path, &sections, &schema_version, &output),
-        Commands::Serve { path } => commands::serve::run(&path),
+            tokens,
+            encoding,
+            mmap,
+            strip_color_codes,
+            preserve_duplicates,
+            typed_dates,
+            format,
+        } => parse_encoding(&encoding).and_then(|encoding| {
+            parse_format(&format).and_then(|format| {
+                commands::extract::run_gamestate(
+                    &path,
+                    &sections,
+                    &schema_version,
+                    &output,
+                    tokens.as_deref(),
+                    encoding,
+                    mmap,
+                    strip_color_codes,
+                    preserve_duplicates,
+                    typed_dates,
+                    format,
+                )
+            })
+        }),
+        Commands::Serve {
+            path,
+            tokens,
+            cache_dir,
+            no_cache,
+        } => commands::serve::run(&path, tokens.as_deref(), cache_dir.as_deref(), !no_cache),
+        Commands::Conformance { fixtures, output } => run_conformance(&fixtures, &output),
     };
 
     if let Err(e) = result {
         error::handle_error(e);
     }
 }
+
+/// Parse the `--encoding` flag value into an `encoding::Encoding`, wrapped
+/// for use with `Result::and_then` at each command's dispatch site.
+fn parse_encoding(label: &str) -> anyhow::Result<encoding::Encoding> {
+    Ok(encoding::Encoding::try_from(label)?)
+}
+
+/// Parse the `--format` flag value into a `serialize::Format`, wrapped for
+/// use with `Result::and_then` at each command's dispatch site.
+fn parse_format(label: &str) -> anyhow::Result<serialize::Format> {
+    Ok(serialize::Format::try_from(label)?)
+}
+
+/// Run the conformance fixture corpus under `fixtures_dir` and print the
+/// resulting pass/fail/skip report as JSON.
+fn run_conformance(fixtures_dir: &str, output: &str) -> anyhow::Result<()> {
+    let report = commands::conformance::run(fixtures_dir)?;
+    let json = serde_json::to_string_pretty(&report.to_json())?;
+
+    if output == "-" {
+        println!("{}", json);
+    } else {
+        std::fs::write(output, json)?;
+    }
+
+    Ok(())
+}
@@ -36,7 +36,369 @@
 //! - jomini library: <https://docs.rs/jomini>
 //! - Windows-1252: <https://en.wikipedia.org/wiki/Windows-1252>
 
-use serde_json::Value;
+use crate::binary::TokenLookup;
+use crate::encoding::Encoding;
+use crate::error::CompanionError;
+use jomini::text::de::{from_utf8_slice, from_windows1252_slice};
+use jomini::text::{ObjectReader, ValueReader};
+use jomini::{Encoding as JominiEncoding, TextTape};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+
+/// Decode raw Clausewitz bytes into a parsed object tree.
+///
+/// Most modern saves are plain ASCII/UTF-8, so we try the fast UTF-8 path
+/// first. If the bytes aren't valid UTF-8 (older saves, some localizations),
+/// we fall back to the lossless Windows-1252 decode instead of failing
+/// outright. The byte offset of the first invalid UTF-8 sequence is returned
+/// alongside the parse result so callers can surface it as a diagnostic
+/// without treating it as fatal.
+pub fn decode_gamestate(
+    bytes: &[u8],
+) -> Result<(HashMap<String, Value>, Option<usize>), CompanionError> {
+    match std::str::from_utf8(bytes) {
+        Ok(_) => {
+            let parsed = from_utf8_slice(bytes).map_err(|e| CompanionError::ParseError {
+                source: anyhow::Error::new(e),
+                decode_offset: None,
+            })?;
+            Ok((parsed, None))
+        }
+        Err(e) => {
+            let offset = e.valid_up_to();
+            let parsed =
+                from_windows1252_slice(bytes).map_err(|e| CompanionError::ParseError {
+                    source: anyhow::Error::new(e),
+                    decode_offset: Some(offset),
+                })?;
+            Ok((parsed, Some(offset)))
+        }
+    }
+}
+
+/// Decode raw Clausewitz bytes the same way `decode_gamestate` picks an
+/// encoding, but walk jomini's mid-level `TextTape`/`ObjectReader` API
+/// instead of deserializing straight into a `HashMap`. Plain `HashMap`
+/// deserialization silently keeps only the last occurrence of a repeated
+/// key within an object (see `edge_cases::tests::test_duplicate_keys`);
+/// this instead groups each object's fields by key and emits a JSON array
+/// for any key that appears more than once, so species traits, fleet
+/// lists, and event chains all survive intact. Opt-in (see the CLI's
+/// `--preserve-duplicates` flag) since it changes the shape callers see
+/// for any key that happens to repeat.
+pub fn decode_gamestate_preserving_duplicates(
+    bytes: &[u8],
+) -> Result<(HashMap<String, Value>, Option<usize>), CompanionError> {
+    match std::str::from_utf8(bytes) {
+        Ok(_) => {
+            let tape = TextTape::from_slice(bytes).map_err(|e| CompanionError::ParseError {
+                source: anyhow::Error::new(e),
+                decode_offset: None,
+            })?;
+            let map = object_to_map(tape.utf8_reader()).map_err(|e| CompanionError::ParseError {
+                source: anyhow::Error::new(e),
+                decode_offset: None,
+            })?;
+            Ok((map.into_iter().collect(), None))
+        }
+        Err(e) => {
+            let offset = e.valid_up_to();
+            let tape = TextTape::from_slice(bytes).map_err(|e| CompanionError::ParseError {
+                source: anyhow::Error::new(e),
+                decode_offset: Some(offset),
+            })?;
+            let map =
+                object_to_map(tape.windows1252_reader()).map_err(|e| CompanionError::ParseError {
+                    source: anyhow::Error::new(e),
+                    decode_offset: Some(offset),
+                })?;
+            Ok((map.into_iter().collect(), Some(offset)))
+        }
+    }
+}
+
+/// Walk one object's fields, grouping repeated keys into a JSON array and
+/// leaving single-occurrence keys as scalars. `order` tracks first-seen
+/// order so the emitted map doesn't get shuffled by `HashMap` iteration.
+fn object_to_map<E: JominiEncoding + Clone>(
+    obj: ObjectReader<'_, '_, E>,
+) -> Result<Map<String, Value>, jomini::Error> {
+    let mut order: Vec<String> = Vec::new();
+    let mut grouped: HashMap<String, Vec<Value>> = HashMap::new();
+    for (key, _operator, value) in obj.fields() {
+        let key = key.to_string();
+        let json_value = value_to_json(value)?;
+        grouped
+            .entry(key.clone())
+            .or_insert_with(|| {
+                order.push(key.clone());
+                Vec::new()
+            })
+            .push(json_value);
+    }
+
+    let mut map = Map::new();
+    for key in order {
+        let mut values = grouped.remove(&key).expect("key tracked in order");
+        map.insert(
+            key,
+            if values.len() == 1 {
+                values.pop().expect("just checked len == 1")
+            } else {
+                Value::Array(values)
+            },
+        );
+    }
+    Ok(map)
+}
+
+fn value_to_json<E: JominiEncoding + Clone>(
+    value: ValueReader<'_, '_, E>,
+) -> Result<Value, jomini::Error> {
+    if let Ok(obj) = value.read_object() {
+        return Ok(Value::Object(object_to_map(obj)?));
+    }
+    if let Ok(arr) = value.read_array() {
+        let mut items = Vec::new();
+        for entry in arr.values() {
+            items.push(value_to_json(entry)?);
+        }
+        return Ok(Value::Array(items));
+    }
+    Ok(scalar_to_json(&value.read_scalar()?))
+}
+
+/// Coerce a jomini scalar to the same typed JSON shape the whole-gamestate
+/// serde deserialization would produce: `yes`/`no` as booleans, integers and
+/// floats as numbers, everything else as a string.
+pub(crate) fn scalar_to_json(scalar: &jomini::Scalar) -> Value {
+    scalar_text_to_json(scalar.to_string())
+}
+
+/// The same `yes`/`no`/integer/float/string coercion as `scalar_to_json`,
+/// starting from already-decoded scalar text instead of a `jomini::Scalar`
+/// — used by callers (like `events::build_value`) that only have the
+/// scalar's text, not the tape reference it came from.
+pub(crate) fn scalar_text_to_json(raw: String) -> Value {
+    match raw.as_str() {
+        "yes" => Value::Bool(true),
+        "no" => Value::Bool(false),
+        _ => {
+            if let Ok(i) = raw.parse::<i64>() {
+                Value::from(i)
+            } else if let Ok(f) = raw.parse::<f64>() {
+                Value::from(f)
+            } else {
+                Value::String(raw)
+            }
+        }
+    }
+}
+
+/// Decode raw Clausewitz text bytes using an explicit `Encoding` rather than
+/// the UTF-8-first/Windows-1252-fallback heuristic.
+///
+/// `Encoding::Windows1252` (the default) defers to `decode_gamestate`
+/// instead of forcing a Windows-1252 decode, since most saves are plain
+/// ASCII/UTF-8 and a forced Windows-1252 decode would mangle legitimate
+/// multi-byte UTF-8 text (accented names, CJK mod content). Any other
+/// encoding means the caller has opted into it explicitly (e.g. a mod
+/// known to store names in Windows-1251 or GBK), so it's applied outright.
+pub fn decode_gamestate_with_encoding(
+    bytes: &[u8],
+    encoding: Encoding,
+) -> Result<(HashMap<String, Value>, Option<usize>), CompanionError> {
+    if encoding == Encoding::default() {
+        return decode_gamestate(bytes);
+    }
+
+    let utf8 = encoding.decode(bytes);
+    let parsed = from_utf8_slice(utf8.as_bytes()).map_err(|e| CompanionError::ParseError {
+        source: anyhow::Error::new(e),
+        decode_offset: None,
+    })?;
+    Ok((parsed, None))
+}
+
+/// Decode raw Clausewitz bytes, auto-detecting binary (ironman) bodies and
+/// dispatching to the binary decoder when a token table is available,
+/// falling back to `decode_gamestate_with_encoding`'s text path otherwise.
+///
+/// Unknown tokens are reported as `"0x<hex>"` keys rather than aborting the
+/// whole decode (see `binary::LenientTokenLookup`) since the CLI export
+/// commands this feeds are one-shot, not an interactive session worth
+/// failing outright over a single unrecognized field.
+pub fn decode_auto(
+    bytes: &[u8],
+    tokens: Option<&TokenLookup>,
+    encoding: Encoding,
+) -> Result<(HashMap<String, Value>, Option<usize>), CompanionError> {
+    if crate::binary::looks_like_binary(bytes) {
+        let tokens = tokens.ok_or_else(|| CompanionError::InvalidArgument {
+            detail: "binary (ironman) save detected; pass --tokens <file> to decode it"
+                .to_string(),
+        })?;
+        let resolver = crate::binary::LenientTokenLookup::new(tokens);
+        let parsed = crate::binary::decode_binary(bytes, &resolver)?;
+        Ok((parsed, None))
+    } else {
+        decode_gamestate_with_encoding(bytes, encoding)
+    }
+}
+
+/// One run of text under a single active color, as produced by
+/// `split_color_spans`. `color` is `None` for text outside any `\x15`
+/// markup, or after a `\x15!` reset.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextSpan {
+    pub text: String,
+    pub color: Option<String>,
+}
+
+/// Strip Stellaris's `\x15`-prefixed in-game color markup, leaving plain
+/// display text. A `\x15` byte is followed by exactly one key character (a
+/// letter naming the color, or `!` to reset to default); both the marker
+/// and its key are removed.
+pub fn strip_color_codes(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c == '\u{15}' {
+            chars.next();
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// Split `s` into runs of text under the color active at that point. A
+/// `\x15<letter>` marker opens a color, `\x15!` resets to `None`; the
+/// markers themselves never appear in a span's `text`.
+pub fn split_color_spans(s: &str) -> Vec<TextSpan> {
+    let mut spans = Vec::new();
+    let mut current_color = None;
+    let mut current_text = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c == '\u{15}' {
+            if let Some(key) = chars.next() {
+                if !current_text.is_empty() {
+                    spans.push(TextSpan {
+                        text: std::mem::take(&mut current_text),
+                        color: current_color.clone(),
+                    });
+                }
+                current_color = if key == '!' { None } else { Some(key.to_string()) };
+            }
+        } else {
+            current_text.push(c);
+        }
+    }
+    if !current_text.is_empty() {
+        spans.push(TextSpan {
+            text: current_text,
+            color: current_color,
+        });
+    }
+    spans
+}
+
+/// Recursively strip `\x15` color markup from every string leaf in `value`,
+/// for callers that want clean display text in JSON output. Raw markup is
+/// preserved unless a caller explicitly asks for this (see the CLI's
+/// `--strip-color-codes` flag).
+pub fn strip_color_codes_in_value(value: &mut Value) {
+    match value {
+        Value::String(s) => *s = strip_color_codes(s),
+        Value::Array(arr) => arr.iter_mut().for_each(strip_color_codes_in_value),
+        Value::Object(obj) => obj.values_mut().for_each(strip_color_codes_in_value),
+        _ => {}
+    }
+}
+
+/// A Clausewitz `Y.M.D[.H]` date/time scalar (`2200.1.1`, `2200.1.1.12`),
+/// promoted from a bare string the way a TOML deserializer promotes a bare
+/// datetime token to a first-class `Datetime` instead of leaving it as a
+/// string. `hour` defaults to 0 when the save omits it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClausewitzDate {
+    pub year: i32,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+}
+
+impl ClausewitzDate {
+    /// Parse `s` as a Clausewitz date scalar, requiring 3 or 4 dot-separated
+    /// components and in-range month/day/hour. Each component is parsed
+    /// with plain decimal `str::parse`, which (unlike some ad-hoc number
+    /// literal parsers) never treats a leading zero as an octal prefix, so
+    /// the zero-padded months/days real saves use (`2200.01.01`) come out
+    /// as the decimal values they mean.
+    pub fn parse(s: &str) -> Option<Self> {
+        let mut parts = s.split('.');
+        let year = parts.next()?.parse().ok()?;
+        let month: u8 = parts.next()?.parse().ok()?;
+        let day: u8 = parts.next()?.parse().ok()?;
+        let hour: u8 = match parts.next() {
+            Some(h) => h.parse().ok()?,
+            None => 0,
+        };
+        if parts.next().is_some() {
+            return None; // trailing component: not a date
+        }
+        if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 {
+            return None;
+        }
+        Some(ClausewitzDate {
+            year,
+            month,
+            day,
+            hour,
+        })
+    }
+
+    /// Normalized `YYYY-MM-DDTHH` string for consumers that just want
+    /// something sortable/comparable without unpacking `__type`.
+    pub fn to_iso_string(&self) -> String {
+        format!(
+            "{:04}-{:02}-{:02}T{:02}",
+            self.year, self.month, self.day, self.hour
+        )
+    }
+
+    /// The tagged JSON shape emitted by `promote_dates_in_value`.
+    pub fn to_json(&self) -> Value {
+        serde_json::json!({
+            "__type": "date",
+            "year": self.year,
+            "month": self.month,
+            "day": self.day,
+            "hour": self.hour,
+            "iso": self.to_iso_string(),
+        })
+    }
+}
+
+/// Recursively promote any string leaf in `value` that parses as a
+/// Clausewitz date (`ClausewitzDate::parse`) into its tagged JSON shape,
+/// leaving every other string untouched. For callers that want to sort or
+/// compare in-game dates without re-parsing strings themselves (see the
+/// CLI's `--typed-dates` flag); off by default so existing consumers keep
+/// seeing bare date strings.
+pub fn promote_dates_in_value(value: &mut Value) {
+    match value {
+        Value::String(s) => {
+            if let Some(date) = ClausewitzDate::parse(s) {
+                *value = date.to_json();
+            }
+        }
+        Value::Array(arr) => arr.iter_mut().for_each(promote_dates_in_value),
+        Value::Object(obj) => obj.values_mut().for_each(promote_dates_in_value),
+        _ => {}
+    }
+}
 
 /// Verify that a JSON value contains valid UTF-8 strings recursively.
 /// This is used for testing that encoding conversion produces valid output.
@@ -212,6 +574,163 @@ empire={
         );
     }
 
+    /// Test that an explicit non-default encoding is applied outright,
+    /// rather than going through the UTF-8-first heuristic.
+    #[test]
+    fn test_decode_gamestate_with_encoding_windows_1251() {
+        // Windows-1251 byte 0xEF = 'п' (Cyrillic small letter pe)
+        let data = b"test={name=\"\xef\"}";
+        let (parsed, offset) =
+            decode_gamestate_with_encoding(data, crate::encoding::Encoding::Windows1251)
+                .expect("should decode with Windows-1251");
+        assert_eq!(offset, None);
+        let test = parsed.get("test").expect("test should exist");
+        assert_eq!(test.get("name").and_then(|v| v.as_str()), Some("п"));
+    }
+
+    /// Test that repeated keys survive as a JSON array instead of the
+    /// last-value-wins behavior `from_utf8_slice`/`HashMap` produces (see
+    /// `edge_cases::tests::test_duplicate_keys`).
+    #[test]
+    fn test_decode_gamestate_preserving_duplicates() {
+        let data = br#"
+traits={
+    trait="trait_organic"
+    trait="trait_adaptive"
+    trait="trait_nomadic"
+}
+"#;
+        let (parsed, offset) =
+            decode_gamestate_preserving_duplicates(data).expect("should decode");
+        assert_eq!(offset, None);
+        let traits = parsed.get("traits").expect("traits should exist");
+        let trait_values = traits
+            .get("trait")
+            .and_then(|v| v.as_array())
+            .expect("trait should be an array of all occurrences");
+        assert_eq!(trait_values.len(), 3);
+        assert_eq!(trait_values[0], "trait_organic");
+        assert_eq!(trait_values[2], "trait_nomadic");
+    }
+
+    #[test]
+    fn test_decode_gamestate_preserving_duplicates_single_occurrence_stays_scalar() {
+        let data = br#"empire={name="Test Empire"}"#;
+        let (parsed, _) = decode_gamestate_preserving_duplicates(data).expect("should decode");
+        let empire = parsed.get("empire").expect("empire should exist");
+        assert_eq!(empire.get("name").and_then(|v| v.as_str()), Some("Test Empire"));
+    }
+
+    /// Duplicate-preservation only changes repeated-key shape; scalars must
+    /// still type the same way the default serde decode would (see
+    /// `scalar_to_json`), not come back restringified.
+    #[test]
+    fn test_decode_gamestate_preserving_duplicates_types_scalars() {
+        let data = br#"country={id=42 active=yes ratio=1.5 name="Foo"}"#;
+        let (parsed, _) = decode_gamestate_preserving_duplicates(data).expect("should decode");
+        let country = parsed.get("country").expect("country should exist");
+        assert_eq!(country.get("id").and_then(|v| v.as_i64()), Some(42));
+        assert_eq!(country.get("active").and_then(|v| v.as_bool()), Some(true));
+        assert_eq!(country.get("ratio").and_then(|v| v.as_f64()), Some(1.5));
+        assert_eq!(country.get("name").and_then(|v| v.as_str()), Some("Foo"));
+    }
+
+    /// Test that a zero-padded date with no hour parses correctly
+    #[test]
+    fn test_clausewitz_date_parse_no_hour() {
+        let date = ClausewitzDate::parse("2200.01.01").expect("should parse");
+        assert_eq!(
+            date,
+            ClausewitzDate {
+                year: 2200,
+                month: 1,
+                day: 1,
+                hour: 0
+            }
+        );
+        assert_eq!(date.to_iso_string(), "2200-01-01T00");
+    }
+
+    #[test]
+    fn test_clausewitz_date_parse_with_hour() {
+        let date = ClausewitzDate::parse("2200.1.1.12").expect("should parse");
+        assert_eq!(date.hour, 12);
+        assert_eq!(date.to_iso_string(), "2200-01-01T12");
+    }
+
+    #[test]
+    fn test_clausewitz_date_rejects_non_dates() {
+        assert!(ClausewitzDate::parse("not a date").is_none());
+        assert!(ClausewitzDate::parse("2200.13.01").is_none(), "month out of range");
+        assert!(ClausewitzDate::parse("2200.01.32").is_none(), "day out of range");
+        assert!(ClausewitzDate::parse("2200.1.1.24").is_none(), "hour out of range");
+        assert!(ClausewitzDate::parse("2200.1.1.1.1").is_none(), "too many components");
+    }
+
+    #[test]
+    fn test_promote_dates_in_value_recurses() {
+        let mut value = json!({
+            "start_date": "2200.01.01",
+            "name": "not a date",
+            "events": ["2200.01.15.6", "plain text"]
+        });
+        promote_dates_in_value(&mut value);
+        assert_eq!(value["start_date"]["__type"], "date");
+        assert_eq!(value["start_date"]["year"], 2200);
+        assert_eq!(value["name"], "not a date");
+        assert_eq!(value["events"][0]["__type"], "date");
+        assert_eq!(value["events"][0]["hour"], 6);
+        assert_eq!(value["events"][1], "plain text");
+    }
+
+    /// Test strip_color_codes removes both the marker and its key char
+    #[test]
+    fn test_strip_color_codes() {
+        let text = "\u{15}BColored\u{15}! Text";
+        assert_eq!(strip_color_codes(text), "Colored Text");
+    }
+
+    #[test]
+    fn test_strip_color_codes_no_markup() {
+        assert_eq!(strip_color_codes("plain text"), "plain text");
+    }
+
+    /// Test split_color_spans groups text under the active color
+    #[test]
+    fn test_split_color_spans() {
+        let text = "before\u{15}Bred\u{15}!after";
+        let spans = split_color_spans(text);
+        assert_eq!(
+            spans,
+            vec![
+                TextSpan {
+                    text: "before".to_string(),
+                    color: None
+                },
+                TextSpan {
+                    text: "red".to_string(),
+                    color: Some("B".to_string())
+                },
+                TextSpan {
+                    text: "after".to_string(),
+                    color: None
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_strip_color_codes_in_value_recurses() {
+        let mut value = json!({
+            "name": "\u{15}BColored\u{15}! Text",
+            "tags": ["\u{15}Ra", "plain"]
+        });
+        strip_color_codes_in_value(&mut value);
+        assert_eq!(value["name"], "Colored Text");
+        assert_eq!(value["tags"][0], "a");
+        assert_eq!(value["tags"][1], "plain");
+    }
+
     /// Test validate_json_strings helper
     #[test]
     fn test_validate_json_strings() {
@@ -0,0 +1,283 @@
+//! Pluggable output serializers for exported section `Value` trees.
+//!
+//! `--format` used to mean two different things depending which command you
+//! ran: `IterSave` accepted it but only ever allowed `jsonl`, while
+//! `ExtractSave`/`ExtractGamestate` had no flag at all and always wrote
+//! pretty JSON. Downstream tooling (spreadsheets, other languages) often
+//! wants TOML or a compact binary envelope instead of JSON, the same way a
+//! data-shell "from/to"-style format command would, so this module gives
+//! every export command the same `--format` choice behind one `Serializer`
+//! trait with one impl per format. The section-walking logic in
+//! `commands::extract`/`commands::iter` stays shared; only the final encode
+//! step differs.
+
+use crate::error::CompanionError;
+use serde_json::Value;
+use std::io::Write;
+
+/// Output format for exported sections, selected by `--format` across
+/// `ExtractSave`, `IterSave`, and `ExtractGamestate`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    Jsonl,
+    Toml,
+    MessagePack,
+    Cbor,
+}
+
+impl Default for Format {
+    fn default() -> Self {
+        Format::Json
+    }
+}
+
+impl TryFrom<&str> for Format {
+    type Error = CompanionError;
+
+    fn try_from(label: &str) -> Result<Self, Self::Error> {
+        match label.to_ascii_lowercase().as_str() {
+            "json" => Ok(Format::Json),
+            "jsonl" => Ok(Format::Jsonl),
+            "toml" => Ok(Format::Toml),
+            "msgpack" | "messagepack" => Ok(Format::MessagePack),
+            "cbor" => Ok(Format::Cbor),
+            other => Err(CompanionError::InvalidArgument {
+                detail: format!(
+                    "Unknown format: {:?} (expected one of json, jsonl, toml, msgpack, cbor)",
+                    other
+                ),
+            }),
+        }
+    }
+}
+
+impl Format {
+    /// Whether this format streams one record per line. Only `Jsonl` does;
+    /// `IterSave` uses this to decide between printing one JSON line per
+    /// entry versus buffering the whole section into a single
+    /// `Serializer::write` call.
+    pub fn is_line_oriented(self) -> bool {
+        matches!(self, Format::Jsonl)
+    }
+}
+
+/// Render a single `Value` document in one output format.
+pub trait Serializer {
+    fn write(&self, value: &Value, out: &mut dyn Write) -> Result<(), CompanionError>;
+}
+
+struct JsonSerializer;
+struct JsonlSerializer;
+struct TomlSerializer;
+struct MessagePackSerializer;
+struct CborSerializer;
+
+impl Serializer for JsonSerializer {
+    fn write(&self, value: &Value, out: &mut dyn Write) -> Result<(), CompanionError> {
+        let text = serde_json::to_string_pretty(value).map_err(|e| encode_error("json", e))?;
+        out.write_all(text.as_bytes())
+            .map_err(|e| io_error("json", e))
+    }
+}
+
+impl Serializer for JsonlSerializer {
+    fn write(&self, value: &Value, out: &mut dyn Write) -> Result<(), CompanionError> {
+        let text = serde_json::to_string(value).map_err(|e| encode_error("jsonl", e))?;
+        out.write_all(text.as_bytes())
+            .and_then(|_| out.write_all(b"\n"))
+            .map_err(|e| io_error("jsonl", e))
+    }
+}
+
+impl Serializer for TomlSerializer {
+    fn write(&self, value: &Value, out: &mut dyn Write) -> Result<(), CompanionError> {
+        // TOML has no top-level scalar or null: the document must be a
+        // table, and a table can't hold a null value. `json_to_toml` drops
+        // `null` map/array entries rather than failing the whole export over
+        // one unset optional field.
+        let table = json_to_toml_table(value)?;
+        let text = toml::to_string_pretty(&table).map_err(|e| encode_error("toml", e))?;
+        out.write_all(text.as_bytes())
+            .map_err(|e| io_error("toml", e))
+    }
+}
+
+impl Serializer for MessagePackSerializer {
+    fn write(&self, value: &Value, out: &mut dyn Write) -> Result<(), CompanionError> {
+        let bytes = rmp_serde::to_vec(value).map_err(|e| encode_error("msgpack", e))?;
+        out.write_all(&bytes).map_err(|e| io_error("msgpack", e))
+    }
+}
+
+impl Serializer for CborSerializer {
+    fn write(&self, value: &Value, out: &mut dyn Write) -> Result<(), CompanionError> {
+        let mut bytes = Vec::new();
+        ciborium::ser::into_writer(value, &mut bytes).map_err(|e| encode_error("cbor", e))?;
+        out.write_all(&bytes).map_err(|e| io_error("cbor", e))
+    }
+}
+
+/// Pick the `Serializer` impl for a `Format`.
+pub fn for_format(format: Format) -> Box<dyn Serializer> {
+    match format {
+        Format::Json => Box::new(JsonSerializer),
+        Format::Jsonl => Box::new(JsonlSerializer),
+        Format::Toml => Box::new(TomlSerializer),
+        Format::MessagePack => Box::new(MessagePackSerializer),
+        Format::Cbor => Box::new(CborSerializer),
+    }
+}
+
+/// Convert a JSON `Value` (which must be an object at the top level) into a
+/// `toml::Table`, dropping `null` entries along the way since TOML has no
+/// representation for them.
+fn json_to_toml_table(value: &Value) -> Result<toml::Table, CompanionError> {
+    match json_to_toml(value) {
+        Some(toml::Value::Table(table)) => Ok(table),
+        _ => Err(CompanionError::InvalidArgument {
+            detail: "TOML output requires an object at the top level".to_string(),
+        }),
+    }
+}
+
+/// Recursively convert a JSON `Value` to a `toml::Value`, returning `None`
+/// for `Value::Null` (the caller drops these rather than failing, since a
+/// save section with one unset optional field shouldn't sink a whole TOML
+/// export).
+fn json_to_toml(value: &Value) -> Option<toml::Value> {
+    match value {
+        Value::Null => None,
+        Value::Bool(b) => Some(toml::Value::Boolean(*b)),
+        Value::Number(n) => {
+            if let Some(i) = n.as_i64() {
+                Some(toml::Value::Integer(i))
+            } else {
+                Some(toml::Value::Float(n.as_f64().unwrap_or_default()))
+            }
+        }
+        Value::String(s) => Some(toml::Value::String(s.clone())),
+        Value::Array(arr) => Some(toml::Value::Array(
+            arr.iter().filter_map(json_to_toml).collect(),
+        )),
+        Value::Object(obj) => {
+            let mut table = toml::Table::new();
+            for (key, value) in obj {
+                if let Some(toml_value) = json_to_toml(value) {
+                    table.insert(key.clone(), toml_value);
+                }
+            }
+            Some(toml::Value::Table(table))
+        }
+    }
+}
+
+fn encode_error(format: &str, source: impl std::error::Error + Send + Sync + 'static) -> CompanionError {
+    CompanionError::SerializeError {
+        format: format.to_string(),
+        source: anyhow::Error::new(source),
+    }
+}
+
+fn io_error(format: &str, source: std::io::Error) -> CompanionError {
+    CompanionError::SerializeError {
+        format: format.to_string(),
+        source: anyhow::Error::new(source),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn round_trip(format: Format, value: &Value) -> Vec<u8> {
+        let mut out = Vec::new();
+        for_format(format)
+            .write(value, &mut out)
+            .expect("should encode");
+        out
+    }
+
+    #[test]
+    fn test_try_from_accepts_known_labels() {
+        assert_eq!(Format::try_from("json").unwrap(), Format::Json);
+        assert_eq!(Format::try_from("JSONL").unwrap(), Format::Jsonl);
+        assert_eq!(Format::try_from("toml").unwrap(), Format::Toml);
+        assert_eq!(Format::try_from("msgpack").unwrap(), Format::MessagePack);
+        assert_eq!(Format::try_from("cbor").unwrap(), Format::Cbor);
+    }
+
+    #[test]
+    fn test_try_from_rejects_unknown_label() {
+        assert!(Format::try_from("yaml").is_err());
+    }
+
+    #[test]
+    fn test_only_jsonl_is_line_oriented() {
+        assert!(Format::Jsonl.is_line_oriented());
+        assert!(!Format::Json.is_line_oriented());
+        assert!(!Format::Toml.is_line_oriented());
+        assert!(!Format::MessagePack.is_line_oriented());
+        assert!(!Format::Cbor.is_line_oriented());
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let value = json!({"name": "Test Empire", "id": 42, "active": true});
+        let bytes = round_trip(Format::Json, &value);
+        let decoded: Value = serde_json::from_slice(&bytes).expect("should decode");
+        assert_eq!(decoded, value);
+    }
+
+    #[test]
+    fn test_jsonl_round_trip_and_trailing_newline() {
+        let value = json!({"name": "Test Empire"});
+        let bytes = round_trip(Format::Jsonl, &value);
+        assert_eq!(bytes.last(), Some(&b'\n'));
+        let decoded: Value = serde_json::from_slice(&bytes[..bytes.len() - 1]).unwrap();
+        assert_eq!(decoded, value);
+    }
+
+    #[test]
+    fn test_toml_round_trip() {
+        let value = json!({"name": "Test Empire", "id": 42, "active": true, "ratio": 0.5});
+        let bytes = round_trip(Format::Toml, &value);
+        let decoded: toml::Table = toml::from_str(std::str::from_utf8(&bytes).unwrap()).unwrap();
+        assert_eq!(decoded["name"].as_str(), Some("Test Empire"));
+        assert_eq!(decoded["id"].as_integer(), Some(42));
+        assert_eq!(decoded["active"].as_bool(), Some(true));
+    }
+
+    #[test]
+    fn test_toml_drops_null_entries() {
+        let value = json!({"name": "Test Empire", "missing": null});
+        let bytes = round_trip(Format::Toml, &value);
+        let decoded: toml::Table = toml::from_str(std::str::from_utf8(&bytes).unwrap()).unwrap();
+        assert!(!decoded.contains_key("missing"));
+    }
+
+    #[test]
+    fn test_toml_rejects_non_object_top_level() {
+        let value = json!([1, 2, 3]);
+        let mut out = Vec::new();
+        let result = for_format(Format::Toml).write(&value, &mut out);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_msgpack_round_trip() {
+        let value = json!({"name": "Test Empire", "id": 42});
+        let bytes = round_trip(Format::MessagePack, &value);
+        let decoded: Value = rmp_serde::from_slice(&bytes).expect("should decode");
+        assert_eq!(decoded, value);
+    }
+
+    #[test]
+    fn test_cbor_round_trip() {
+        let value = json!({"name": "Test Empire", "id": 42, "tags": ["a", "b"]});
+        let bytes = round_trip(Format::Cbor, &value);
+        let decoded: Value = ciborium::de::from_reader(&bytes[..]).expect("should decode");
+        assert_eq!(decoded, value);
+    }
+}